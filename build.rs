@@ -6,31 +6,48 @@ use std::{error::Error, path::Path};
 ///
 /// These function pointer LUTs can then be indexed with certain bits
 /// of the opcode encoding. This code generation ensures less manual work.
+///
+/// The same handlers are also emitted as one big `match` per instruction set,
+/// used instead of the LUTs with the `match-decoder` feature.
 fn main() -> Result<(), Box<dyn Error>> {
     let out_dir = std::env::var_os("OUT_DIR").unwrap();
 
     // Define the output files and const array signatures of the function pointer LUTs.
+    // The LUTs are associated consts so they work for any `Arm7TDMI<M: Mcu>`.
     let arm_path = Path::new(&out_dir).join("arm_instructions.rs");
     let thumb_path = Path::new(&out_dir).join("thumb_instructions.rs");
 
-    let arm_pre = "pub const ARM_INSTRUCTIONS: [fn(&mut Arm7TDMI, u32); 4096] = [\n";
-    let thumb_pre = "pub const THUMB_INSTRUCTIONS: [fn(&mut Arm7TDMI, u16); 256] = [\n";
+    let arm_pre = "impl<M: Mcu> Arm7TDMI<M> {\npub const ARM_INSTRUCTIONS: [fn(&mut Self, u32); 4096] = [\n";
+    let thumb_pre = "impl<M: Mcu> Arm7TDMI<M> {\npub const THUMB_INSTRUCTIONS: [fn(&mut Self, u16); 1024] = [\n";
+
+    let arm_match_path = Path::new(&out_dir).join("arm_match.rs");
+    let thumb_match_path = Path::new(&out_dir).join("thumb_match.rs");
+
+    let arm_match_pre = "impl<M: Mcu> Arm7TDMI<M> {\n#[inline(always)]\npub fn dispatch_arm(&mut self, index: u16, opcode: u32) {\nmatch index {\n";
+    let thumb_match_pre = "impl<M: Mcu> Arm7TDMI<M> {\n#[inline(always)]\npub fn dispatch_thumb(&mut self, index: u16, opcode: u16) {\nmatch index {\n";
+    let match_post = "_ => unreachable!(),\n}\n}\n}";
 
     let mut arm_instrs = String::new();
     let mut thumb_instrs = String::new();
+    let mut arm_arms = String::new();
+    let mut thumb_arms = String::new();
 
     // Bits 20-27 and 4-7 are used to index the opcode (2^12 = 4096).
     for i in 0..4096 {
         arm_instrs += &format!("{},\n", decode_arm(i));
+        arm_arms += &format!("{} => {}(self, opcode),\n", i, decode_arm(i));
     }
 
-    // Upper 8 bits are used to index the opcode (2^8 = 256).
-    for i in 0..=255 {
+    // Upper 10 bits are used to index the opcode (2^10 = 1024).
+    for i in 0..1024 {
         thumb_instrs += &format!("{},\n", decode_thumb(i));
+        thumb_arms += &format!("{} => {}(self, opcode),\n", i, decode_thumb(i));
     }
 
-    std::fs::write(arm_path, arm_pre.to_string() + &arm_instrs + "\n];")?;
-    std::fs::write(thumb_path, thumb_pre.to_string() + &thumb_instrs + "\n];")?;
+    std::fs::write(arm_path, arm_pre.to_string() + &arm_instrs + "\n];\n}")?;
+    std::fs::write(thumb_path, thumb_pre.to_string() + &thumb_instrs + "\n];\n}")?;
+    std::fs::write(arm_match_path, arm_match_pre.to_string() + &arm_arms + match_post)?;
+    std::fs::write(thumb_match_path, thumb_match_pre.to_string() + &thumb_arms + match_post)?;
     println!("cargo:rerun-if-changed=build.rs");
 
     Ok(())
@@ -94,6 +111,9 @@ fn decode_arm(index: u16) -> String {
         let imm = index & (1 << 9) != 0;
         let s_bit = index & (1 << 4) != 0;
         format!("Arm7TDMI::data_processing::<{}, {}>", imm, s_bit)
+    } else if index & 0b1110_0000_0001 == 0b0110_0000_0001 {
+        // Register offset with bit 4 set is the undefined instruction space, not a transfer.
+        format!("Arm7TDMI::undefined")
     } else if index & 0b1100_0000_0000 == 0b0100_0000_0000 {
         let i_bit = index & (1 << 9) != 0;
         let p_bit = index & (1 << 8) != 0;
@@ -113,63 +133,72 @@ fn decode_arm(index: u16) -> String {
     }
 }
 
-/// Decode a THUMB opcode based on 8 bits (8-15) with bitmasks.
-fn decode_thumb(index: u8) -> String {
-    if index & 0b1111_1000 == 0b0001_1000 {
-        let imm = index & (1 << 2) != 0;
-        format!("Arm7TDMI::add_sub::<{}>", imm)
-    } else if index & 0b1110_0000 == 0b0000_0000 {
+/// Decode a THUMB opcode based on 10 bits (6-15) with bitmasks.
+fn decode_thumb(index: u16) -> String {
+    if index & 0b11_1110_0000 == 0b00_0110_0000 {
+        let imm = index & (1 << 4) != 0;
+        let sub = index & (1 << 3) != 0;
+        format!("Arm7TDMI::add_sub::<{}, {}>", imm, sub)
+    } else if index & 0b11_1000_0000 == 0b00_0000_0000 {
         format!("Arm7TDMI::mov_shifted_reg")
-    } else if index & 0b1110_0000 == 0b0010_0000 {
+    } else if index & 0b11_1000_0000 == 0b00_1000_0000 {
         format!("Arm7TDMI::mov_cmp_alu_imm")
-    } else if index & 0b1111_1100 == 0b0100_0000 {
-        format!("Arm7TDMI::alu_ops")
-    } else if index & 0b1111_1100 == 0b0100_0100 {
-        format!("Arm7TDMI::hi_reg_op_bx")
-    } else if index & 0b1111_1000 == 0b0100_1000 {
+    } else if index & 0b11_1111_0000 == 0b01_0000_0000 {
+        let op = index & 0xF;
+        format!("Arm7TDMI::alu_ops::<{}>", op)
+    } else if index & 0b11_1111_0000 == 0b01_0001_0000 {
+        let op = (index >> 2) & 0x3;
+        let h1 = index & (1 << 1) != 0;
+        let h2 = index & 1 != 0;
+
+        format!("Arm7TDMI::hi_reg_op_bx::<{}, {}, {}>", op, h1, h2)
+    } else if index & 0b11_1110_0000 == 0b01_0010_0000 {
         format!("Arm7TDMI::pc_rel_load")
-    } else if index & 0b1111_0010 == 0b0101_0000 {
-        let l_bit = index & (1 << 3) != 0;
-        let b_bit = index & (1 << 2) != 0;
+    } else if index & 0b11_1100_1000 == 0b01_0100_0000 {
+        let l_bit = index & (1 << 5) != 0;
+        let b_bit = index & (1 << 4) != 0;
 
         format!("Arm7TDMI::load_store_reg::<{}, {}>", l_bit, b_bit)
-    } else if index & 0b1111_0010 == 0b0101_0010 {
-        let h_bit = index & (1 << 3) != 0;
-        let s_bit = index & (1 << 2) != 0;
+    } else if index & 0b11_1100_1000 == 0b01_0100_1000 {
+        let h_bit = index & (1 << 5) != 0;
+        let s_bit = index & (1 << 4) != 0;
 
         format!("Arm7TDMI::load_store_hw_signext::<{}, {}>", h_bit, s_bit)
-    } else if index & 0b1110_0000 == 0b0110_0000 {
-        let l_bit = index & (1 << 3) != 0;
-        let b_bit = index & (1 << 4) != 0;
+    } else if index & 0b11_1000_0000 == 0b01_1000_0000 {
+        let l_bit = index & (1 << 5) != 0;
+        let b_bit = index & (1 << 6) != 0;
 
         format!("Arm7TDMI::load_store_imm::<{}, {}>", l_bit, b_bit)
-    } else if index & 0b1111_0000 == 0b1000_0000 {
-        let l_bit = index & (1 << 3) != 0;
+    } else if index & 0b11_1100_0000 == 0b10_0000_0000 {
+        let l_bit = index & (1 << 5) != 0;
         format!("Arm7TDMI::load_store_hw::<{}>", l_bit)
-    } else if index & 0b1111_0000 == 0b1001_0000 {
-        let l_bit = index & (1 << 3) != 0;
+    } else if index & 0b11_1100_0000 == 0b10_0100_0000 {
+        let l_bit = index & (1 << 5) != 0;
         format!("Arm7TDMI::sp_rel_load_store::<{}>", l_bit)
-    } else if index & 0b1111_0000 == 0b1010_0000 {
-        let sp = index & (1 << 3) != 0;
+    } else if index & 0b11_1100_0000 == 0b10_1000_0000 {
+        let sp = index & (1 << 5) != 0;
         format!("Arm7TDMI::load_addr::<{}>", sp)
-    } else if index & 0b1111_1111 == 0b1011_0000 {
-        format!("Arm7TDMI::add_sp")
-    } else if index & 0b1111_0110 == 0b1011_0100 {
-        let l_bit = index & (1 << 3) != 0;
-        let r_bit = index & 1 != 0;
+    } else if index & 0b11_1111_1100 == 0b10_1100_0000 {
+        let sign = index & (1 << 1) != 0;
+        format!("Arm7TDMI::add_sp::<{}>", sign)
+    } else if index & 0b11_1101_1000 == 0b10_1101_0000 {
+        let l_bit = index & (1 << 5) != 0;
+        let r_bit = index & (1 << 2) != 0;
 
         format!("Arm7TDMI::push_pop::<{}, {}>", l_bit, r_bit)
-    } else if index & 0b1111_0000 == 0b1100_0000 {
-        let l_bit = index & (1 << 3) != 0;
+    } else if index & 0b11_1100_0000 == 0b11_0000_0000 {
+        let l_bit = index & (1 << 5) != 0;
         format!("Arm7TDMI::ldm_stm::<{}>", l_bit)
-    } else if index & 0b1111_1111 == 0b1101_1111 {
+    } else if index & 0b11_1111_1100 == 0b11_0111_1100 {
         format!("Arm7TDMI::t_swi")
-    } else if index & 0b1111_0000 == 0b1101_0000 {
-        format!("Arm7TDMI::cond_branch")
-    } else if index & 0b1111_0000 == 0b1110_0000 {
+    } else if index & 0b11_1100_0000 == 0b11_0100_0000 {
+        let cond = (index >> 2) & 0xF;
+        format!("Arm7TDMI::cond_branch::<{}>", cond)
+    } else if index & 0b11_1110_0000 == 0b11_1000_0000 {
+        // 0xE800-0xEFFF is undefined on ARMv4T (BLX suffix on ARMv5).
         format!("Arm7TDMI::branch")
-    } else if index & 0b1111_0000 == 0b1111_0000 {
-        let h_bit = index & (1 << 3) != 0;
+    } else if index & 0b11_1100_0000 == 0b11_1100_0000 {
+        let h_bit = index & (1 << 5) != 0;
         format!("Arm7TDMI::long_branch::<{}>", h_bit)
     } else {
         format!("Arm7TDMI::t_undefined")
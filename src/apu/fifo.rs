@@ -0,0 +1,36 @@
+use std::collections::VecDeque;
+
+/// Bytes a DirectSound FIFO holds.
+const FIFO_SIZE: usize = 32;
+
+/// DirectSound channel A or B, 8-bit signed samples written to the FIFO (usually by DMA 1/2)
+/// and played one per overflow of the selected timer.
+#[derive(Default)]
+pub struct Fifo {
+    buffer: VecDeque<i8>,
+    /// Played until the next timer overflow.
+    pub sample: i8,
+}
+
+impl Fifo {
+    /// Writes to a full FIFO are lost.
+    pub fn push(&mut self, value: u8) {
+        if self.buffer.len() < FIFO_SIZE {
+            self.buffer.push_back(value as i8);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// Take the next sample (an empty FIFO keeps the last one),
+    /// returns if the FIFO is half empty and wants to be refilled.
+    pub fn pop(&mut self) -> bool {
+        if let Some(sample) = self.buffer.pop_front() {
+            self.sample = sample;
+        }
+
+        self.buffer.len() <= FIFO_SIZE / 2
+    }
+}
@@ -0,0 +1,528 @@
+pub mod fifo;
+pub mod psg;
+
+use std::collections::VecDeque;
+
+use derivative::Derivative;
+use proc_bitfield::bitfield;
+
+use crate::mmu::Mcu;
+
+use fifo::Fifo;
+use psg::{Noise, Square, Wave};
+
+/// Output rate of `Apu::samples`, one sample every 512 cycles.
+pub const SAMPLE_RATE: u32 = 32768;
+const SAMPLE_PERIOD: u64 = (1 << 24) / SAMPLE_RATE as u64;
+
+/// Cycles per step of the frame sequencer (512 Hz), which clocks length, sweep and envelope.
+const FRAME_SEQ_PERIOD: u64 = 0x8000;
+
+/// A quarter second of stereo samples, older ones are dropped if nobody drains `samples`.
+const MAX_SAMPLES: usize = SAMPLE_RATE as usize / 2;
+
+/// The six sound channels, in the bit order of `Apu::muted_channels`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Channel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+    FifoA,
+    FifoB,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 6] = [
+        Channel::Square1,
+        Channel::Square2,
+        Channel::Wave,
+        Channel::Noise,
+        Channel::FifoA,
+        Channel::FifoB,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Square1 => "PSG 1",
+            Channel::Square2 => "PSG 2",
+            Channel::Wave => "PSG 3",
+            Channel::Noise => "PSG 4",
+            Channel::FifoA => "FIFO A",
+            Channel::FifoB => "FIFO B",
+        }
+    }
+}
+
+/// What the audio sink does to the mixer output before playing it: both sides averaged,
+/// and the master volume in percent. Samples taken from the APU (e.g. `--dump-wav`) don't have it.
+#[derive(Derivative, Clone, Copy)]
+#[derivative(Default)]
+pub struct OutputSettings {
+    pub mono: bool,
+    #[derivative(Default(value = "100"))]
+    pub volume: u8,
+}
+
+impl OutputSettings {
+    /// The played version of interleaved stereo `samples`.
+    pub fn apply(&self, samples: &[i16]) -> Vec<i16> {
+        let volume = self.volume.min(100) as i32;
+
+        samples
+            .chunks_exact(2)
+            .flat_map(|pair| {
+                let (left, right) = match self.mono {
+                    true => ((pair[0] as i32 + pair[1] as i32) / 2, (pair[0] as i32 + pair[1] as i32) / 2),
+                    false => (pair[0] as i32, pair[1] as i32),
+                };
+                [(left * volume / 100) as i16, (right * volume / 100) as i16]
+            })
+            .collect()
+    }
+}
+
+/// Audio Processing Unit, owns the sound IO registers (except SOUNDBIAS).
+#[derive(Default)]
+pub struct Apu {
+    pub square1: Square,
+    pub square2: Square,
+    pub wave: Wave,
+    pub noise: Noise,
+    /// DirectSound A and B.
+    pub fifos: [Fifo; 2],
+
+    pub soundcnt_l: SOUNDCNTL,
+    pub soundcnt_h: SOUNDCNTH,
+    /// SOUNDCNT_X bit 7, everything but wave RAM is reset and read-only while off.
+    pub enabled: bool,
+
+    /// Debug output filter, a set bit mutes PSG 1-4 (bits 0-3) or DirectSound A and B (bits 4, 5).
+    /// Only `mix` looks at it, the channels keep running and SOUNDCNT_X is unaffected.
+    pub muted_channels: u8,
+
+    /// Step of the frame sequencer (0-7).
+    frame_step: u8,
+    /// Interleaved stereo samples (left, right) at `SAMPLE_RATE`, drained by the frontend.
+    pub samples: VecDeque<i16>,
+}
+
+impl Apu {
+    pub fn is_muted(&self, channel: Channel) -> bool {
+        self.muted_channels >> channel as u8 & 1 != 0
+    }
+
+    /// Leave `channel` out of the mix, it keeps running.
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted_channels = self.muted_channels & !(1 << channel as u8) | (muted as u8) << channel as u8;
+    }
+
+    /// `cycles` is the global timestamp, like for the timers.
+    pub fn tick(&mut self, cycles: u64) {
+        if cycles.is_multiple_of(FRAME_SEQ_PERIOD) {
+            self.clock_frame_sequencer();
+        }
+
+        if cycles.is_multiple_of(SAMPLE_PERIOD) {
+            self.square1.step(SAMPLE_PERIOD as u32);
+            self.square2.step(SAMPLE_PERIOD as u32);
+            self.wave.step(SAMPLE_PERIOD as u32);
+            self.noise.step(SAMPLE_PERIOD as u32);
+
+            if self.samples.len() >= MAX_SAMPLES {
+                self.samples.drain(..2);
+            }
+
+            let (left, right) = self.mix();
+            self.samples.extend([left, right]);
+        }
+    }
+
+    /// Timer 0 or 1 overflowed, the DirectSound channels using it play their next sample.
+    /// Returns which FIFOs want a refill by sound DMA.
+    pub fn timer_overflow(&mut self, timer: usize) -> [bool; 2] {
+        let timers = [self.soundcnt_h.a_timer(), self.soundcnt_h.b_timer()];
+        let mut refill = [false; 2];
+
+        for (fifo, selected) in timers.into_iter().enumerate() {
+            if self.enabled && selected as usize == timer {
+                refill[fifo] = self.fifos[fifo].pop();
+            }
+        }
+
+        refill
+    }
+
+    /// Length at 256 Hz, sweep at 128 Hz and envelope at 64 Hz.
+    fn clock_frame_sequencer(&mut self) {
+        if self.frame_step.is_multiple_of(2) {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+
+        if self.frame_step % 4 == 2 {
+            self.square1.clock_sweep();
+        }
+
+        if self.frame_step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+
+        self.frame_step = (self.frame_step + 1) % 8;
+    }
+
+    /// PSG channels with the master volumes of SOUNDCNT_L and SOUNDCNT_H, plus DirectSound
+    /// at 50% or 100%, saturated to the 10-bit range of the DAC and scaled to 16 bits.
+    fn mix(&self) -> (i16, i16) {
+        if !self.enabled {
+            return (0, 0);
+        }
+
+        let muted = |ch: usize| self.muted_channels >> ch & 1 != 0;
+        let outputs = [
+            self.square1.output(),
+            self.square2.output(),
+            self.wave.output(),
+            self.noise.output(),
+        ];
+
+        // 25%, 50%, 100%, 3 is prohibited.
+        let shift = 2 - self.soundcnt_h.psg_volume().min(2);
+
+        let cnt_h = self.soundcnt_h;
+        let direct_sound = |fifo: usize, full: bool| match muted(4 + fifo) {
+            true => 0,
+            false => self.fifos[fifo].sample as i32 * if full { 4 } else { 2 },
+        };
+        let a = direct_sound(0, cnt_h.a_full());
+        let b = direct_sound(1, cnt_h.b_full());
+
+        let side = |enable: u8, volume: u8, a_enable: bool, b_enable: bool| {
+            let psg: i32 = (0..4)
+                .filter(|&ch| enable >> ch & 1 != 0 && !muted(ch))
+                .map(|ch| outputs[ch] as i32)
+                .sum();
+
+            let sample = ((psg * (volume as i32 + 1)) >> shift) + a * a_enable as i32 + b * b_enable as i32;
+            sample.clamp(-0x200, 0x1FF) as i16 * 64
+        };
+
+        let cnt = self.soundcnt_l;
+        (
+            side(cnt.left_enable(), cnt.left_volume(), cnt_h.a_left(), cnt_h.b_left()),
+            side(cnt.right_enable(), cnt.right_volume(), cnt_h.a_right(), cnt_h.b_right()),
+        )
+    }
+
+    /// SOUNDCNT_X, channel on flags in bits 0-3.
+    fn soundcnt_x(&self) -> u16 {
+        (self.enabled as u16) << 7
+            | (self.noise.on as u16) << 3
+            | (self.wave.on as u16) << 2
+            | (self.square2.on as u16) << 1
+            | self.square1.on as u16
+    }
+}
+
+impl Mcu for Apu {
+    fn peek16(&self, address: u32) -> u16 {
+        match address {
+            0x0060 => self.square1.sweep.sweep(),
+            0x0062 => self.square1.duty_env.duty_env() & 0xFFC0,
+            0x0064 => self.square1.freq_cnt.freq_cnt() & 0x4000,
+            0x0068 => self.square2.duty_env.duty_env() & 0xFFC0,
+            0x006C => self.square2.freq_cnt.freq_cnt() & 0x4000,
+            0x0070 => self.wave.select.wave_sel(),
+            0x0072 => self.wave.volume.wave_vol() & 0xE000,
+            0x0074 => self.wave.freq_cnt.freq_cnt() & 0x4000,
+            0x0078 => self.noise.env.duty_env() & 0xFF00,
+            0x007C => self.noise.cnt.noise_cnt(),
+            0x0080 => self.soundcnt_l.soundcnt_l(),
+            0x0082 => self.soundcnt_h.soundcnt_h() & 0x770F,
+            0x0084 => self.soundcnt_x(),
+            0x0090..=0x009F => {
+                let offset = self.wave.ram_offset(address as usize - 0x0090);
+                u16::from_le_bytes([self.wave.ram[offset], self.wave.ram[offset + 1]])
+            }
+            _ => 0,
+        }
+    }
+
+    fn read8(&mut self, address: u32) -> u8 {
+        self.peek8(address)
+    }
+
+    fn write16(&mut self, address: u32, value: u16) {
+        // Only SOUNDCNT_X and wave RAM can be written while the APU is off.
+        if !self.enabled && address < 0x0084 {
+            return;
+        }
+
+        match address {
+            0x0060 => self.square1.write_sweep(value),
+            0x0062 => self.square1.write_duty_env(value),
+            0x0064 => self.square1.write_freq_cnt(value),
+            0x0068 => self.square2.write_duty_env(value),
+            0x006C => self.square2.write_freq_cnt(value),
+            0x0070 => self.wave.write_select(value),
+            0x0072 => self.wave.write_volume(value),
+            0x0074 => self.wave.write_freq_cnt(value),
+            0x0078 => self.noise.write_env(value),
+            0x007C => self.noise.write_cnt(value),
+            0x0080 => self.soundcnt_l.set_soundcnt_l(value & 0xFF77),
+            0x0082 => {
+                // Bits 11 and 15 reset FIFO A and B, they always read 0.
+                if value & (1 << 11) != 0 {
+                    self.fifos[0].reset();
+                }
+                if value & (1 << 15) != 0 {
+                    self.fifos[1].reset();
+                }
+
+                self.soundcnt_h.set_soundcnt_h(value & 0x770F);
+            }
+            0x0084 => {
+                self.enabled = value & (1 << 7) != 0;
+                if !self.enabled {
+                    // Only the registers reset, not the samples or the debug mutes.
+                    let (ram, samples) = (self.wave.ram, std::mem::take(&mut self.samples));
+                    *self = Apu {
+                        samples,
+                        muted_channels: self.muted_channels,
+                        ..Default::default()
+                    };
+                    self.wave.ram = ram;
+                }
+            }
+            0x0090..=0x009F => {
+                let offset = self.wave.ram_offset(address as usize - 0x0090);
+                self.wave.ram[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+            }
+            0x00A0..=0x00A7 => value.to_le_bytes().into_iter().for_each(|byte| self.write8(address, byte)),
+            _ => {}
+        }
+    }
+
+    fn write8(&mut self, address: u32, value: u8) {
+        // Every byte written to FIFO A (0xA0-0xA3) or B (0xA4-0xA7) is queued, in order.
+        if let 0x00A0..=0x00A7 = address {
+            if self.enabled {
+                self.fifos[(address as usize - 0x00A0) / 4].push(value);
+            }
+            return;
+        }
+
+        let [lo, hi] = self.raw_read16(address & !1).to_le_bytes();
+        match address & 1 == 0 {
+            true => self.write16(address, (hi as u16) << 8 | value as u16),
+            false => self.write16(address & !1, (value as u16) << 8 | lo as u16),
+        }
+    }
+
+    /// Also "reads" the write-only lengths and frequencies, the other byte of a byte write.
+    fn raw_read16(&mut self, address: u32) -> u16 {
+        match address {
+            0x0062 => self.square1.duty_env.duty_env(),
+            0x0064 => self.square1.freq_cnt.freq_cnt(),
+            0x0068 => self.square2.duty_env.duty_env(),
+            0x006C => self.square2.freq_cnt.freq_cnt(),
+            0x0072 => self.wave.volume.wave_vol(),
+            0x0074 => self.wave.freq_cnt.freq_cnt(),
+            0x0078 => self.noise.env.duty_env(),
+            _ => self.peek16(address),
+        }
+    }
+}
+
+bitfield! {
+    /// **SOUNDCNT_L - PSG Master Volume and Enable** (r/w).
+    #[derive(Clone, Copy, Default)]
+    pub struct SOUNDCNTL(pub u16) {
+        pub soundcnt_l: u16 @ ..,
+        pub right_volume: u8 @ 0..=2,
+        pub left_volume: u8 @ 4..=6,
+        pub right_enable: u8 @ 8..=11,
+        pub left_enable: u8 @ 12..=15,
+    }
+}
+
+bitfield! {
+    /// **SOUNDCNT_H - DMA Sound Control and Mixing** (r/w).
+    #[derive(Clone, Copy, Default)]
+    pub struct SOUNDCNTH(pub u16) {
+        pub soundcnt_h: u16 @ ..,
+        pub psg_volume: u8 @ 0..=1,
+        pub a_full: bool @ 2,
+        pub b_full: bool @ 3,
+        pub a_right: bool @ 8,
+        pub a_left: bool @ 9,
+        pub a_timer: bool @ 10,
+        pub b_right: bool @ 12,
+        pub b_left: bool @ 13,
+        pub b_timer: bool @ 14,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// All channels playing on both sides: square 1 with a length, square 2, wave, noise and FIFO A.
+    fn playing() -> Apu {
+        let mut apu = Apu::default();
+        for (address, value) in [
+            (0x0084, 0x0080),
+            (0x0080, 0xFF77),
+            (0x0082, 0x0306),
+            (0x0062, 0xF0BF),
+            (0x0064, 0xC000 | 1750),
+            (0x0068, 0xF080),
+            (0x006C, 0x8000 | 1500),
+            (0x0070, 0x0080),
+            (0x0072, 0x2000),
+            (0x0074, 0x8000 | 1900),
+            (0x0078, 0xF000),
+            (0x007C, 0x8021),
+        ] {
+            apu.write16(address, value);
+        }
+        for (address, byte) in [(0x0090, 0xF0), (0x00A0, 0x40), (0x00A1, 0xC0)] {
+            apu.write8(address, byte);
+        }
+
+        apu
+    }
+
+    #[test]
+    fn square_wave_frequency() {
+        // Square 1 at 131072 / (2048 - 1750) = 439.8 Hz, 50% duty without length or envelope.
+        let mut apu = Apu::default();
+        for (address, value) in [
+            (0x0084, 0x0080),
+            (0x0080, 0x1177),
+            (0x0082, 0x0002),
+            (0x0062, 0xF080),
+            (0x0064, 0x8000 | 1750),
+        ] {
+            apu.write16(address, value);
+        }
+
+        // One second, drained as it goes so nothing is dropped.
+        let mut left = Vec::new();
+        for cycles in 0..1 << 24 {
+            apu.tick(cycles);
+            left.extend(apu.samples.drain(..).step_by(2));
+        }
+        assert_eq!(left.len(), SAMPLE_RATE as usize);
+
+        let periods = left.windows(2).filter(|pair| pair[0] < pair[1]).count();
+        assert!(periods.abs_diff(440) <= 1, "{periods} Hz");
+    }
+
+    #[test]
+    fn muted_channel_is_left_out_of_the_mix() {
+        let (mut apu, mut muted) = (playing(), playing());
+        muted.muted_channels = 0b01_0001;
+
+        // The same as if SOUNDCNT_L/H didn't route square 1 and FIFO A to either side.
+        let mut unrouted = playing();
+        unrouted.write16(0x0080, 0xEE77);
+        unrouted.write16(0x0082, 0x0006);
+
+        assert_eq!(apu.peek16(0x0084), 0x008F, "not all PSG channels on");
+        for cycles in 0..0x40000 {
+            if cycles % 0x800 == 0 {
+                for apu in [&mut apu, &mut muted, &mut unrouted] {
+                    apu.timer_overflow(0);
+                }
+            }
+            for apu in [&mut apu, &mut muted, &mut unrouted] {
+                apu.tick(cycles);
+            }
+
+            assert_eq!(muted.peek16(0x0084), apu.peek16(0x0084), "SOUNDCNT_X at {cycles}");
+        }
+
+        // Square 1's length ran out in both, muting didn't stop anything.
+        assert_eq!(apu.peek16(0x0084) & 1, 0);
+        assert_eq!(muted.samples, unrouted.samples);
+        assert_ne!(muted.samples, apu.samples);
+    }
+
+    #[test]
+    fn muting_removes_exactly_the_channel() {
+        // Low enough volumes that the sum of all channels never saturates, the mix stays linear.
+        let mut apu = playing();
+        apu.write16(0x0080, 0xFF33);
+        apu.write16(0x0082, 0x3302);
+        // Without the length of `playing`, square 1 keeps playing.
+        apu.write16(0x0062, 0xF080);
+        apu.write16(0x0064, 0x8000 | 1750);
+        for byte in [0x40, 0xC0, 0x20, 0xE0] {
+            apu.write8(0x00A4, byte);
+        }
+
+        let mut heard = 0;
+        for cycles in 0..0x20000 {
+            if cycles % 0x800 == 0 {
+                apu.timer_overflow(0);
+            }
+            apu.tick(cycles);
+
+            let all = apu.mix();
+            for channel in Channel::ALL {
+                apu.set_muted(channel, true);
+                let without = apu.mix();
+
+                apu.muted_channels = 0x3F;
+                apu.set_muted(channel, false);
+                let only = apu.mix();
+                apu.muted_channels = 0;
+                heard |= ((only != (0, 0)) as u8) << channel as u8;
+
+                assert_eq!(
+                    (all.0 - without.0, all.1 - without.1),
+                    only,
+                    "{} at {cycles}",
+                    channel.name()
+                );
+            }
+        }
+        assert_eq!(heard, 0x3F, "not every channel played");
+    }
+
+    #[test]
+    fn mono_and_volume() {
+        // Square 1 left only, square 2 right only, at different volumes.
+        let mut stereo = Apu::default();
+        for (address, value) in [
+            (0x0084, 0x0080),
+            (0x0080, 0x2177),
+            (0x0082, 0x0002),
+            (0x0062, 0xF080),
+            (0x0064, 0x8000 | 1750),
+            (0x0068, 0x7080),
+            (0x006C, 0x8000 | 1500),
+        ] {
+            stereo.write16(address, value);
+        }
+        for cycles in 0..0x4000 {
+            stereo.tick(cycles);
+        }
+        let samples: Vec<i16> = stereo.samples.iter().copied().collect();
+        assert!(samples.chunks(2).any(|pair| pair[0] != pair[1]), "both sides were the same");
+
+        let mono = OutputSettings { mono: true, ..Default::default() }.apply(&samples);
+        let half = OutputSettings { volume: 50, ..Default::default() }.apply(&samples);
+        assert_eq!(OutputSettings::default().apply(&samples), samples);
+        for (i, pair) in samples.chunks(2).enumerate() {
+            let average = ((pair[0] as i32 + pair[1] as i32) / 2) as i16;
+            assert_eq!(mono[i * 2..i * 2 + 2], [average, average], "mono at {i}");
+            assert_eq!(half[i * 2..i * 2 + 2], [pair[0] / 2, pair[1] / 2], "half volume at {i}");
+        }
+    }
+}
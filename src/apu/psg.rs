@@ -0,0 +1,436 @@
+use proc_bitfield::bitfield;
+
+/// Duty cycles 12.5%, 25%, 50% and 75%, one bit per step, starting with bit 0.
+const DUTY: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// Run a channel's frequency timer for `cycles`, returns how often it expired.
+fn run_timer(timer: &mut u32, period: u32, mut cycles: u32) -> u32 {
+    let mut steps = 0;
+    while cycles >= *timer {
+        cycles -= *timer;
+        *timer = period;
+        steps += 1;
+    }
+
+    *timer -= cycles;
+    steps
+}
+
+/// Volume envelope of the square and noise channels.
+#[derive(Default)]
+struct Envelope {
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn start(&mut self, reg: DUTYENV) {
+        self.volume = reg.env_volume();
+        self.timer = reg.env_step();
+    }
+
+    /// Clocked at 64 Hz, a step time of 0 stops it.
+    fn clock(&mut self, reg: DUTYENV) {
+        if reg.env_step() == 0 {
+            return;
+        }
+
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = reg.env_step();
+            match reg.env_increase() {
+                true if self.volume < 15 => self.volume += 1,
+                false if self.volume > 0 => self.volume -= 1,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Channel 1 and 2, square waves. Only channel 1 has a frequency sweep.
+#[derive(Default)]
+pub struct Square {
+    pub sweep: SWEEP,
+    pub duty_env: DUTYENV,
+    pub freq_cnt: FREQCNT,
+    pub on: bool,
+
+    length: u16,
+    envelope: Envelope,
+    /// Cycles until the next of the 8 duty steps.
+    timer: u32,
+    step: u8,
+
+    shadow_freq: u16,
+    sweep_timer: u8,
+    sweep_on: bool,
+}
+
+impl Square {
+    pub fn write_sweep(&mut self, value: u16) {
+        self.sweep = SWEEP(value & 0x7F);
+    }
+
+    pub fn write_duty_env(&mut self, value: u16) {
+        self.duty_env = DUTYENV(value);
+        self.length = 64 - self.duty_env.length() as u16;
+
+        if !self.dac() {
+            self.on = false;
+        }
+    }
+
+    pub fn write_freq_cnt(&mut self, value: u16) {
+        self.freq_cnt = FREQCNT(value & 0x47FF);
+        if value & 0x8000 != 0 {
+            self.start();
+        }
+    }
+
+    /// The DAC is off with volume 0 and a decreasing envelope, the channel can't be started.
+    fn dac(&self) -> bool {
+        self.duty_env.duty_env() & 0xF800 != 0
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.freq_cnt.freq() as u32) * 16
+    }
+
+    fn start(&mut self) {
+        self.on = self.dac();
+        if self.length == 0 {
+            self.length = 64;
+        }
+
+        self.envelope.start(self.duty_env);
+        self.timer = self.period();
+
+        self.shadow_freq = self.freq_cnt.freq();
+        self.sweep_timer = match self.sweep.time() {
+            0 => 8,
+            time => time,
+        };
+        self.sweep_on = self.sweep.time() != 0 || self.sweep.shift() != 0;
+        if self.sweep.shift() != 0 {
+            self.sweep_freq();
+        }
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        let period = self.period();
+        let steps = run_timer(&mut self.timer, period, cycles);
+        self.step = ((self.step as u32 + steps) % 8) as u8;
+    }
+
+    /// Between -15 and 15.
+    pub fn output(&self) -> i16 {
+        if !self.on {
+            return 0;
+        }
+
+        let volume = self.envelope.volume as i16;
+        match DUTY[self.duty_env.duty() as usize] >> self.step & 1 != 0 {
+            true => volume,
+            false => -volume,
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.freq_cnt.length_enable() && self.length > 0 {
+            self.length -= 1;
+            self.on &= self.length > 0;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock(self.duty_env);
+    }
+
+    /// Next frequency of the sweep, turns the channel off if it overflows.
+    fn sweep_freq(&mut self) -> Option<u16> {
+        let delta = self.shadow_freq >> self.sweep.shift();
+        let freq = match self.sweep.decrease() {
+            true => self.shadow_freq - delta,
+            false => self.shadow_freq + delta,
+        };
+
+        if freq > 2047 {
+            self.on = false;
+            return None;
+        }
+
+        Some(freq)
+    }
+
+    /// Clocked at 128 Hz.
+    pub fn clock_sweep(&mut self) {
+        self.sweep_timer = self.sweep_timer.saturating_sub(1);
+        if self.sweep_timer > 0 {
+            return;
+        }
+
+        self.sweep_timer = match self.sweep.time() {
+            0 => 8,
+            time => time,
+        };
+
+        if self.sweep_on && self.sweep.time() != 0 {
+            if let Some(freq) = self.sweep_freq() {
+                if self.sweep.shift() != 0 {
+                    self.shadow_freq = freq;
+                    self.freq_cnt.set_freq(freq);
+                    // The overflow check runs once more with the new frequency.
+                    self.sweep_freq();
+                }
+            }
+        }
+    }
+}
+
+/// Channel 3, plays 4-bit samples from wave RAM.
+#[derive(Default)]
+pub struct Wave {
+    pub select: WAVESEL,
+    pub volume: WAVEVOL,
+    pub freq_cnt: FREQCNT,
+    /// Two banks of 32 samples, high nibble first.
+    pub ram: [u8; 32],
+    pub on: bool,
+
+    length: u16,
+    timer: u32,
+    /// Sample within the played bank(s).
+    pos: u32,
+}
+
+impl Wave {
+    pub fn write_select(&mut self, value: u16) {
+        self.select = WAVESEL(value & 0xE0);
+        if !self.select.enable() {
+            self.on = false;
+        }
+    }
+
+    pub fn write_volume(&mut self, value: u16) {
+        self.volume = WAVEVOL(value & 0xE0FF);
+        self.length = 256 - self.volume.length() as u16;
+    }
+
+    pub fn write_freq_cnt(&mut self, value: u16) {
+        self.freq_cnt = FREQCNT(value & 0x47FF);
+        if value & 0x8000 != 0 {
+            self.start();
+        }
+    }
+
+    /// Wave RAM offset of the bank the CPU sees, always the one not selected for playing.
+    pub fn ram_offset(&self, offset: usize) -> usize {
+        (!self.select.bank() as usize) * 16 + offset
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.freq_cnt.freq() as u32) * 8
+    }
+
+    fn start(&mut self) {
+        self.on = self.select.enable();
+        if self.length == 0 {
+            self.length = 256;
+        }
+
+        self.timer = self.period();
+        self.pos = 0;
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        let period = self.period();
+        let steps = run_timer(&mut self.timer, period, cycles);
+        let samples = if self.select.two_banks() { 64 } else { 32 };
+        self.pos = (self.pos + steps) % samples;
+    }
+
+    /// Between -15 and 15.
+    pub fn output(&self) -> i16 {
+        if !self.on {
+            return 0;
+        }
+
+        // Playing both banks starts with the selected one.
+        let index = (self.select.bank() as usize * 32 + self.pos as usize) % 64;
+        let byte = self.ram[index / 2];
+        let nibble = match index % 2 {
+            0 => byte >> 4,
+            _ => byte & 0xF,
+        };
+        let sample = nibble as i16 * 2 - 15;
+
+        match (self.volume.force_75(), self.volume.volume()) {
+            (true, _) => sample * 3 / 4,
+            (false, 0) => 0,
+            (false, volume) => sample >> (volume - 1),
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.freq_cnt.length_enable() && self.length > 0 {
+            self.length -= 1;
+            self.on &= self.length > 0;
+        }
+    }
+}
+
+/// Channel 4, noise from a 15- or 7-bit LFSR.
+#[derive(Default)]
+pub struct Noise {
+    /// Only length and envelope, there is no duty.
+    pub env: DUTYENV,
+    pub cnt: NOISECNT,
+    pub on: bool,
+
+    length: u16,
+    envelope: Envelope,
+    timer: u32,
+    lfsr: u16,
+}
+
+impl Noise {
+    pub fn write_env(&mut self, value: u16) {
+        self.env = DUTYENV(value & 0xFF3F);
+        self.length = 64 - self.env.length() as u16;
+
+        if self.env.duty_env() & 0xF800 == 0 {
+            self.on = false;
+        }
+    }
+
+    pub fn write_cnt(&mut self, value: u16) {
+        self.cnt = NOISECNT(value & 0x40FF);
+        if value & 0x8000 != 0 {
+            self.start();
+        }
+    }
+
+    fn period(&self) -> u32 {
+        let divisor = match self.cnt.divisor() as u32 {
+            0 => 8,
+            div => div * 16,
+        };
+
+        (divisor << self.cnt.shift()) * 4
+    }
+
+    fn start(&mut self) {
+        self.on = self.env.duty_env() & 0xF800 != 0;
+        if self.length == 0 {
+            self.length = 64;
+        }
+
+        self.envelope.start(self.env);
+        self.timer = self.period();
+        self.lfsr = 0x7FFF;
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        let period = self.period();
+        for _ in 0..run_timer(&mut self.timer, period, cycles) {
+            let bit = (self.lfsr ^ self.lfsr >> 1) & 1;
+            self.lfsr = self.lfsr >> 1 | bit << 14;
+
+            if self.cnt.width_7() {
+                self.lfsr = self.lfsr & !(1 << 6) | bit << 6;
+            }
+        }
+    }
+
+    /// Between -15 and 15.
+    pub fn output(&self) -> i16 {
+        if !self.on {
+            return 0;
+        }
+
+        let volume = self.envelope.volume as i16;
+        match self.lfsr & 1 == 0 {
+            true => volume,
+            false => -volume,
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.cnt.length_enable() && self.length > 0 {
+            self.length -= 1;
+            self.on &= self.length > 0;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock(self.env);
+    }
+}
+
+bitfield! {
+    /// **SOUND1CNT_L - Channel 1 Sweep** (r/w).
+    #[derive(Clone, Copy, Default)]
+    pub struct SWEEP(pub u16) {
+        pub sweep: u16 @ ..,
+        pub shift: u8 @ 0..=2,
+        pub decrease: bool @ 3,
+        pub time: u8 @ 4..=6,
+    }
+}
+
+bitfield! {
+    /// **SOUND1CNT_H, SOUND2CNT_L, SOUND4CNT_L - Length, Duty, Envelope** (r/w, length w).
+    #[derive(Clone, Copy, Default)]
+    pub struct DUTYENV(pub u16) {
+        pub duty_env: u16 @ ..,
+        pub length: u8 @ 0..=5,
+        pub duty: u8 @ 6..=7,
+        pub env_step: u8 @ 8..=10,
+        pub env_increase: bool @ 11,
+        pub env_volume: u8 @ 12..=15,
+    }
+}
+
+bitfield! {
+    /// **SOUND1CNT_X, SOUND2CNT_H, SOUND3CNT_X - Frequency, Control** (r/w, frequency w).
+    #[derive(Clone, Copy, Default)]
+    pub struct FREQCNT(pub u16) {
+        pub freq_cnt: u16 @ ..,
+        pub freq: u16 @ 0..=10,
+        pub length_enable: bool @ 14,
+    }
+}
+
+bitfield! {
+    /// **SOUND3CNT_L - Channel 3 Stop/Wave RAM select** (r/w).
+    #[derive(Clone, Copy, Default)]
+    pub struct WAVESEL(pub u16) {
+        pub wave_sel: u16 @ ..,
+        pub two_banks: bool @ 5,
+        pub bank: bool @ 6,
+        pub enable: bool @ 7,
+    }
+}
+
+bitfield! {
+    /// **SOUND3CNT_H - Channel 3 Length, Volume** (r/w, length w).
+    #[derive(Clone, Copy, Default)]
+    pub struct WAVEVOL(pub u16) {
+        pub wave_vol: u16 @ ..,
+        pub length: u8 @ 0..=7,
+        pub volume: u8 @ 13..=14,
+        pub force_75: bool @ 15,
+    }
+}
+
+bitfield! {
+    /// **SOUND4CNT_H - Channel 4 Frequency, Control** (r/w).
+    #[derive(Clone, Copy, Default)]
+    pub struct NOISECNT(pub u16) {
+        pub noise_cnt: u16 @ ..,
+        pub divisor: u8 @ 0..=2,
+        pub width_7: bool @ 3,
+        pub shift: u8 @ 4..=7,
+        pub length_enable: bool @ 14,
+    }
+}
@@ -0,0 +1,452 @@
+use super::interpreter::instr_profile::Format;
+
+const CONDITIONS: [&str; 16] = [
+    "EQ", "NE", "CS", "CC", "MI", "PL", "VS", "VC", "HI", "LS", "GE", "LT", "GT", "LE", "", "NV",
+];
+
+const SHIFTS: [&str; 4] = ["LSL", "LSR", "ASR", "ROR"];
+
+/// Register name, R13-R15 by their role.
+fn reg(r: u32) -> String {
+    match r & 0xF {
+        13 => "SP".to_string(),
+        14 => "LR".to_string(),
+        15 => "PC".to_string(),
+        r => format!("R{r}"),
+    }
+}
+
+/// Register list like `{R0-R3, R5, LR}`, runs of three or more registers are collapsed.
+fn reg_list(list: u32) -> String {
+    let mut parts = Vec::new();
+    let mut r = 0;
+
+    while r < 16 {
+        if list & (1 << r) == 0 {
+            r += 1;
+            continue;
+        }
+
+        let start = r;
+        while r < 16 && list & (1 << r) != 0 {
+            r += 1;
+        }
+
+        match r - start {
+            1 => parts.push(reg(start)),
+            2 => parts.extend([reg(start), reg(start + 1)]),
+            _ => parts.push(format!("{}-{}", reg(start), reg(r - 1))),
+        }
+    }
+
+    format!("{{{}}}", parts.join(", "))
+}
+
+fn imm(value: u32) -> String {
+    match value < 10 {
+        true => format!("#{value}"),
+        false => format!("#0x{value:X}"),
+    }
+}
+
+/// Signed immediate offset, `#-0x10` if `up` is not set.
+fn offset(value: u32, up: bool) -> String {
+    match up {
+        true => imm(value),
+        false => format!("#-{}", &imm(value)[1..]),
+    }
+}
+
+/// Immediate shift of a register operand, with the `#0` special cases (`LSR #32`, `RRX`).
+fn imm_shift(rm: u32, shift_type: u32, amount: u32) -> String {
+    match (shift_type, amount) {
+        (0, 0) => reg(rm),
+        (1 | 2, 0) => format!("{}, {} #32", reg(rm), SHIFTS[shift_type as usize]),
+        (3, 0) => format!("{}, RRX", reg(rm)),
+        _ => format!("{}, {} #{amount}", reg(rm), SHIFTS[shift_type as usize]),
+    }
+}
+
+/// Disassemble an ARM opcode at address `pc`, branch targets are absolute.
+pub fn disassemble_arm(opcode: u32, pc: u32) -> String {
+    let cond = CONDITIONS[opcode as usize >> 28];
+    let rn = (opcode >> 16) & 0xF;
+    let rd = (opcode >> 12) & 0xF;
+    let rs = (opcode >> 8) & 0xF;
+    let rm = opcode & 0xF;
+
+    let bit = |n: u32| opcode & (1 << n) != 0;
+    let s = if bit(20) { "S" } else { "" };
+
+    match Format::arm(opcode) {
+        Format::DataProcessing => {
+            const OPS: [&str; 16] = [
+                "AND", "EOR", "SUB", "RSB", "ADD", "ADC", "SBC", "RSC", "TST", "TEQ", "CMP", "CMN",
+                "ORR", "MOV", "BIC", "MVN",
+            ];
+            let op = (opcode >> 21) & 0xF;
+
+            let op2 = if bit(25) {
+                imm((opcode & 0xFF).rotate_right(((opcode >> 8) & 0xF) * 2))
+            } else if bit(4) {
+                format!(
+                    "{}, {} {}",
+                    reg(rm),
+                    SHIFTS[(opcode as usize >> 5) & 3],
+                    reg(rs)
+                )
+            } else {
+                imm_shift(rm, (opcode >> 5) & 3, (opcode >> 7) & 0x1F)
+            };
+
+            match op {
+                // Compares always set flags, no S suffix.
+                0x8..=0xB => format!("{}{cond} {}, {op2}", OPS[op as usize], reg(rn)),
+                0xD | 0xF => format!("{}{cond}{s} {}, {op2}", OPS[op as usize], reg(rd)),
+                _ => format!(
+                    "{}{cond}{s} {}, {}, {op2}",
+                    OPS[op as usize],
+                    reg(rd),
+                    reg(rn)
+                ),
+            }
+        }
+        Format::PsrTransfer => {
+            let psr = if bit(22) { "SPSR" } else { "CPSR" };
+
+            if !bit(21) {
+                return format!("MRS{cond} {}, {psr}", reg(rd));
+            }
+
+            let fields: String = [(19, 'f'), (18, 's'), (17, 'x'), (16, 'c')]
+                .iter()
+                .filter(|(n, _)| bit(*n))
+                .map(|(_, c)| c)
+                .collect();
+            let source = match bit(25) {
+                true => imm((opcode & 0xFF).rotate_right(((opcode >> 8) & 0xF) * 2)),
+                false => reg(rm),
+            };
+
+            format!("MSR{cond} {psr}_{fields}, {source}")
+        }
+        Format::Multiply => match bit(21) {
+            true => format!(
+                "MLA{cond}{s} {}, {}, {}, {}",
+                reg(rn),
+                reg(rm),
+                reg(rs),
+                reg(rd)
+            ),
+            false => format!("MUL{cond}{s} {}, {}, {}", reg(rn), reg(rm), reg(rs)),
+        },
+        Format::MultiplyLong => {
+            let sign = if bit(22) { "S" } else { "U" };
+            let op = if bit(21) { "MLAL" } else { "MULL" };
+            format!(
+                "{sign}{op}{cond}{s} {}, {}, {}, {}",
+                reg(rd),
+                reg(rn),
+                reg(rm),
+                reg(rs)
+            )
+        }
+        Format::Swap => {
+            let b = if bit(22) { "B" } else { "" };
+            format!("SWP{cond}{b} {}, {}, [{}]", reg(rd), reg(rm), reg(rn))
+        }
+        Format::BranchExchange => format!("BX{cond} {}", reg(rm)),
+        Format::HalfwordTransfer => {
+            let op = if bit(20) { "LDR" } else { "STR" };
+            let size = ["", "H", "SB", "SH"][(opcode as usize >> 5) & 3];
+
+            let offset = match bit(22) {
+                true => ((opcode >> 4) & 0xF0) | (opcode & 0xF),
+                false => 0,
+            };
+            let offset = match (bit(22), bit(23)) {
+                (true, up) => offset_or_none(offset, up),
+                (false, true) => Some(reg(rm)),
+                (false, false) => Some(format!("-{}", reg(rm))),
+            };
+
+            format!(
+                "{op}{cond}{size} {}, {}",
+                reg(rd),
+                address(rn, offset, bit(24), bit(21))
+            )
+        }
+        Format::SingleDataTransfer => {
+            let op = if bit(20) { "LDR" } else { "STR" };
+            let b = if bit(22) { "B" } else { "" };
+            // Post-indexed with W set is the user mode (translated) access.
+            let t = if !bit(24) && bit(21) { "T" } else { "" };
+
+            let offset = match bit(25) {
+                true => {
+                    let sign = if bit(23) { "" } else { "-" };
+                    Some(sign.to_string() + &imm_shift(rm, (opcode >> 5) & 3, (opcode >> 7) & 0x1F))
+                }
+                false => offset_or_none(opcode & 0xFFF, bit(23)),
+            };
+
+            format!(
+                "{op}{cond}{b}{t} {}, {}",
+                reg(rd),
+                address(rn, offset, bit(24), bit(21))
+            )
+        }
+        Format::BlockDataTransfer => {
+            let op = if bit(20) { "LDM" } else { "STM" };
+            let mode = ["DA", "IA", "DB", "IB"][(opcode as usize >> 23) & 3];
+            let w = if bit(21) { "!" } else { "" };
+            let user = if bit(22) { "^" } else { "" };
+
+            format!(
+                "{op}{cond}{mode} {}{w}, {}{user}",
+                reg(rn),
+                reg_list(opcode & 0xFFFF)
+            )
+        }
+        Format::Branch => {
+            let op = if bit(24) { "BL" } else { "B" };
+            let offset = ((opcode << 8) as i32 >> 6) as u32;
+            format!(
+                "{op}{cond} 0x{:08X}",
+                pc.wrapping_add(8).wrapping_add(offset)
+            )
+        }
+        Format::SoftwareInterrupt => format!("SWI{cond} {}", imm(opcode & 0x00FF_FFFF)),
+        // No coprocessors on the GBA, these are undefined like in `decode_arm`.
+        _ => format!("UND{cond} 0x{opcode:08X}"),
+    }
+}
+
+/// `None` for a zero offset so it's left out, e.g. `[R0]` instead of `[R0, #0]`.
+fn offset_or_none(value: u32, up: bool) -> Option<String> {
+    (value != 0).then(|| offset(value, up))
+}
+
+/// Pre-indexed `[Rn, offset]{!}` or post-indexed `[Rn], offset`.
+fn address(rn: u32, offset: Option<String>, pre: bool, write_back: bool) -> String {
+    match (offset, pre) {
+        (None, _) => format!("[{}]", reg(rn)),
+        (Some(offset), true) => format!(
+            "[{}, {offset}]{}",
+            reg(rn),
+            if write_back { "!" } else { "" }
+        ),
+        (Some(offset), false) => format!("[{}], {offset}", reg(rn)),
+    }
+}
+
+/// Disassemble a THUMB opcode at address `pc`, branch targets are absolute.
+///
+/// The two halves of `BL` are shown on their own, the first one with the `LR` it sets up.
+pub fn disassemble_thumb(opcode: u16, pc: u32) -> String {
+    let opcode = opcode as u32;
+    let rd = opcode & 0x7;
+    let rs = (opcode >> 3) & 0x7;
+    let ro = (opcode >> 6) & 0x7;
+    let r8 = (opcode >> 8) & 0x7;
+
+    let bit = |n: u32| opcode & (1 << n) != 0;
+    let imm5 = (opcode >> 6) & 0x1F;
+    let imm8 = opcode & 0xFF;
+
+    match Format::thumb(opcode as u16) {
+        Format::ThumbMoveShifted => {
+            let op = (opcode >> 11) & 3;
+            // LSR and ASR by 0 encode a shift by 32.
+            let amount = if op != 0 && imm5 == 0 { 32 } else { imm5 };
+            format!(
+                "{} {}, {}, #{amount}",
+                SHIFTS[op as usize],
+                reg(rd),
+                reg(rs)
+            )
+        }
+        Format::ThumbAddSub => {
+            let op = if bit(9) { "SUB" } else { "ADD" };
+            let operand = if bit(10) { imm(ro) } else { reg(ro) };
+            format!("{op} {}, {}, {operand}", reg(rd), reg(rs))
+        }
+        Format::ThumbImmediate => {
+            let op = ["MOV", "CMP", "ADD", "SUB"][(opcode as usize >> 11) & 3];
+            format!("{op} {}, {}", reg(r8), imm(imm8))
+        }
+        Format::ThumbAlu => {
+            const OPS: [&str; 16] = [
+                "AND", "EOR", "LSL", "LSR", "ASR", "ADC", "SBC", "ROR", "TST", "NEG", "CMP", "CMN",
+                "ORR", "MUL", "BIC", "MVN",
+            ];
+            format!(
+                "{} {}, {}",
+                OPS[(opcode as usize >> 6) & 0xF],
+                reg(rd),
+                reg(rs)
+            )
+        }
+        Format::ThumbHiRegBx => {
+            let rd = rd | if bit(7) { 8 } else { 0 };
+            let rs = rs | if bit(6) { 8 } else { 0 };
+
+            match (opcode >> 8) & 3 {
+                0 => format!("ADD {}, {}", reg(rd), reg(rs)),
+                1 => format!("CMP {}, {}", reg(rd), reg(rs)),
+                2 => format!("MOV {}, {}", reg(rd), reg(rs)),
+                _ => format!("BX {}", reg(rs)),
+            }
+        }
+        Format::ThumbPcRelativeLoad => format!("LDR {}, [PC, {}]", reg(r8), imm(imm8 << 2)),
+        Format::ThumbLoadStoreReg => {
+            let op = ["STR", "STRB", "LDR", "LDRB"][(opcode as usize >> 10) & 3];
+            format!("{op} {}, [{}, {}]", reg(rd), reg(rs), reg(ro))
+        }
+        Format::ThumbLoadStoreSignExt => {
+            let op = ["STRH", "LDRSB", "LDRH", "LDRSH"][(opcode as usize >> 10) & 3];
+            format!("{op} {}, [{}, {}]", reg(rd), reg(rs), reg(ro))
+        }
+        Format::ThumbLoadStoreImm => {
+            let op = ["STR", "LDR", "STRB", "LDRB"][(opcode as usize >> 11) & 3];
+            let offset = if bit(12) { imm5 } else { imm5 << 2 };
+            format!(
+                "{op} {}, {}",
+                reg(rd),
+                address(rs, offset_or_none(offset, true), true, false)
+            )
+        }
+        Format::ThumbLoadStoreHalfword => {
+            let op = if bit(11) { "LDRH" } else { "STRH" };
+            let offset = offset_or_none(imm5 << 1, true);
+            format!("{op} {}, {}", reg(rd), address(rs, offset, true, false))
+        }
+        Format::ThumbSpRelative => {
+            let op = if bit(11) { "LDR" } else { "STR" };
+            format!(
+                "{op} {}, {}",
+                reg(r8),
+                address(13, offset_or_none(imm8 << 2, true), true, false)
+            )
+        }
+        Format::ThumbLoadAddress => {
+            let base = if bit(11) { "SP" } else { "PC" };
+            format!("ADD {}, {base}, {}", reg(r8), imm(imm8 << 2))
+        }
+        Format::ThumbAddSp => format!("ADD SP, {}", offset((opcode & 0x7F) << 2, !bit(7))),
+        Format::ThumbPushPop => {
+            let (op, extra) = if bit(11) {
+                ("POP", 1 << 15)
+            } else {
+                ("PUSH", 1 << 14)
+            };
+            let list = (opcode & 0xFF) | if bit(8) { extra } else { 0 };
+            format!("{op} {}", reg_list(list))
+        }
+        Format::ThumbMultiple => {
+            let op = if bit(11) { "LDMIA" } else { "STMIA" };
+            format!("{op} {}!, {}", reg(r8), reg_list(opcode & 0xFF))
+        }
+        Format::ThumbCondBranch => {
+            let offset = ((imm8 as i8 as i32) << 1) as u32;
+            let cond = CONDITIONS[(opcode as usize >> 8) & 0xF];
+            format!("B{cond} 0x{:08X}", pc.wrapping_add(4).wrapping_add(offset))
+        }
+        Format::ThumbSoftwareInterrupt => format!("SWI {}", imm(imm8)),
+        Format::ThumbBranch => {
+            let offset = (((opcode << 21) as i32) >> 20) as u32;
+            format!("B 0x{:08X}", pc.wrapping_add(4).wrapping_add(offset))
+        }
+        Format::ThumbLongBranchLink => match bit(11) {
+            true => format!("BL LR + {}", imm((opcode & 0x7FF) << 1)),
+            false => {
+                let offset = (((opcode << 21) as i32) >> 9) as u32;
+                format!(
+                    "BL (LR = 0x{:08X})",
+                    pc.wrapping_add(4).wrapping_add(offset)
+                )
+            }
+        },
+        _ => format!("UND 0x{opcode:04X}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    #[test]
+    fn arm_formats() {
+        let cases = [
+            (0xE1A0_0181, "MOV R0, R1, LSL #3"),
+            (0xE1A0_0231, "MOV R0, R1, LSR R2"),
+            (0xE091_0002, "ADDS R0, R1, R2"),
+            (0xE350_0010, "CMP R0, #0x10"),
+            (0x13A0_0001, "MOVNE R0, #1"),
+            (0xE10F_0000, "MRS R0, CPSR"),
+            (0xE129_F000, "MSR CPSR_fc, R0"),
+            (0xE000_0291, "MUL R0, R1, R2"),
+            (0xE021_0392, "MLA R1, R2, R3, R0"),
+            (0xE081_0392, "UMULL R0, R1, R2, R3"),
+            (0xE102_0091, "SWP R0, R1, [R2]"),
+            (0xE12F_FF1E, "BX LR"),
+            (0xE1D1_00B2, "LDRH R0, [R1, #2]"),
+            (0xE59F_0004, "LDR R0, [PC, #4]"),
+            (0xE491_0004, "LDR R0, [R1], #4"),
+            (0xE791_0102, "LDR R0, [R1, R2, LSL #2]"),
+            (0xE8BD_400F, "LDMIA SP!, {R0-R3, LR}"),
+            (0xEA00_0000, "B 0x08000008"),
+            (0xEBFF_FFFE, "BL 0x08000000"),
+            (0xEF00_0005, "SWI #5"),
+        ];
+
+        for (opcode, text) in cases {
+            assert_eq!(disassemble_arm(opcode, 0x0800_0000), text, "{opcode:08X}");
+        }
+    }
+
+    #[test]
+    fn thumb_formats() {
+        let cases = [
+            (0x00C8, "LSL R0, R1, #3"),
+            (0x1888, "ADD R0, R1, R2"),
+            (0x1E48, "SUB R0, R1, #1"),
+            (0x2010, "MOV R0, #0x10"),
+            (0x4348, "MUL R0, R1"),
+            (0x4770, "BX LR"),
+            (0x4801, "LDR R0, [PC, #4]"),
+            (0x5088, "STR R0, [R1, R2]"),
+            (0x5E88, "LDRSH R0, [R1, R2]"),
+            (0x6848, "LDR R0, [R1, #4]"),
+            (0x8848, "LDRH R0, [R1, #2]"),
+            (0x9801, "LDR R0, [SP, #4]"),
+            (0xA801, "ADD R0, SP, #4"),
+            (0xB082, "ADD SP, #-8"),
+            (0xB50F, "PUSH {R0-R3, LR}"),
+            (0xBD0F, "POP {R0-R3, PC}"),
+            (0xC10F, "STMIA R1!, {R0-R3}"),
+            (0xD0FE, "BEQ 0x08000000"),
+            (0xDF05, "SWI #5"),
+            (0xE7FE, "B 0x08000000"),
+            (0xF000, "BL (LR = 0x08000004)"),
+            (0xF801, "BL LR + #2"),
+        ];
+
+        for (opcode, text) in cases {
+            assert_eq!(disassemble_thumb(opcode, 0x0800_0000), text, "{opcode:04X}");
+        }
+    }
+
+    #[test]
+    fn every_opcode_disassembles() {
+        let mut rng = Rng::new(1);
+
+        for _ in 0..100_000 {
+            let (opcode, pc) = (rng.u32(), rng.u32());
+
+            assert!(!disassemble_arm(opcode, pc).is_empty());
+            assert!(!disassemble_thumb(opcode as u16, pc).is_empty());
+        }
+    }
+}
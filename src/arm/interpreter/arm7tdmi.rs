@@ -1,15 +1,17 @@
 use std::ops::{Index, IndexMut};
 
 use crate::{
-    arm::arr_with, box_arr, fl, mmu::{bus::Bus, game_pak::GamePak, Mcu}
+    arm::arr_with, box_arr, fl, mmu::{bus::Bus, game_db, game_pak::{BackupType, GamePak}, Mcu}
 };
 use proc_bitfield::{bitfield, ConvRaw};
 
+use super::instr_profile::{Format, InstrProfile};
+
 /// Saved Program Status Register as an alias for differentiation. Same structure as CPSR.
 type Spsr = Cpsr;
 /// Each mode has its own banked registers (mostly r13 and r14).
 #[derive(Default, Clone, Copy)]
-struct BankedRegisters { spsr: Spsr, bank: [u32; 7] }
+pub(super) struct BankedRegisters { pub spsr: Spsr, pub bank: [u32; 7] }
 
 /// Initialize `BankedRegister` with SPSR and SP while filling the rest.
 macro_rules! bank {
@@ -22,23 +24,41 @@ macro_rules! bank {
 include!(concat!(env!("OUT_DIR"), "/arm_instructions.rs"));
 include!(concat!(env!("OUT_DIR"), "/thumb_instructions.rs"));
 
+// Same handlers as a `match`, lets LLVM build a jump table and inline the small ones.
+#[cfg(feature = "match-decoder")]
+include!(concat!(env!("OUT_DIR"), "/arm_match.rs"));
+#[cfg(feature = "match-decoder")]
+include!(concat!(env!("OUT_DIR"), "/thumb_match.rs"));
+
 #[derive(Default)]
-pub struct Arm7TDMI {
+pub struct Arm7TDMI<M: Mcu = Bus> {
     /// 16 registers, most GPR, r14 = LR, r15 = PC.
     pub regs: [u32; 16],
     /// Current Program Status Register.
     pub cpsr: Cpsr,
 
     /// The memory bus, owned by the CPU for now.
-    pub bus: Bus,
+    /// Generic so the CPU can also run on plain memory like `FlatMem`.
+    pub bus: M,
 
     /// Saved Program Status Register for all modes but User.
-    spsr: Spsr,
+    pub(super) spsr: Spsr,
     /// The other banked registers of the other modes.
-    banked_regs: Registers,
+    pub(super) banked_regs: Registers,
 
     /// If the prev. instruction directly **set** r15.
     pub(super) branch: bool,
+    /// Cycles the last `cycle` took with 1 cycle per access (S, N and I alike),
+    /// memory waitstates are counted by the bus.
+    pub cycles_taken: u32,
+
+    /// Handle the BIOS calls in `hle` instead of running the BIOS code.
+    pub hle: bool,
+    /// Flags an HLE IntrWait is still waiting for.
+    pub(super) intr_wait: Option<u16>,
+
+    /// Optional count of executed instructions per format.
+    pub instr_profile: Option<InstrProfile>,
 }
 
 #[derive(PartialEq)]
@@ -61,7 +81,7 @@ pub enum Mode {
 }
 
 #[derive(Default, Clone, Copy)]
-struct Registers {
+pub(super) struct Registers {
     pub sys_regs: BankedRegisters,
     pub und_regs: BankedRegisters,
     pub abt_regs: BankedRegisters,
@@ -143,21 +163,55 @@ impl From<State> for bool {
 impl Arm7TDMI {
     /// Initialize SP and PC to the correct values.
     pub fn new(rom: &[u8]) -> Self {
-        let regs = [0; 16];
+        // The game database overrides what would be detected otherwise.
+        let info = game_db::lookup(rom);
 
         // Resize ROM to 32 MB always for OOB reads.
         let mut rom_arr: Box<[u8; 0x0200_0000]> = box_arr![0; 0x0200_0000];
         rom_arr[0..(rom.len())].copy_from_slice(rom); 
 
-        // Initialize GamePak memory.
+        if info.is_some_and(|i| i.rom_mirror) && !rom.is_empty() {
+            for chunk in rom_arr.chunks_mut(rom.len()) {
+                chunk.copy_from_slice(&rom[..chunk.len()]);
+            }
+        }
+
+        // Initialize GamePak memory, the backup type from the database or the ROM's save library.
+        let backup = info
+            .map(|i| i.backup)
+            .or_else(|| BackupType::detect(rom))
+            .unwrap_or_default();
+
         let bus = Bus {
             game_pak: GamePak {
                 rom: rom_arr,
-                sram: vec![0; 0x10000],
+                rom_size: rom.len(),
+                sram: vec![0; backup.size()],
+                backup,
+                info,
+                ..Default::default()
             },
             ..Default::default()
         };
 
+        Self::with_bus(bus)
+    }
+
+    /// Check for interrupts between instructions and jump to exception vector.
+    /// Returns the cycles of the pipeline refill (1S + 1N) if the IRQ was taken.
+    pub fn dispatch_irq(&mut self) -> u32 {
+        if self.bus.ime.enabled() && !self.cpsr.irq() && self.bus.irq_pending() {
+            self.exception(Mode::Irq, 0x18, self.regs[15].wrapping_add(4));
+            return 2;
+        }
+
+        0
+    }
+}
+
+impl<M: Mcu> Arm7TDMI<M> {
+    /// Reset state CPU on top of any memory implementation.
+    pub fn with_bus(bus: M) -> Self {
         // Skip BIOS.
         // regs[13] = 0x0300_7F00;
         // regs[15] = 0x0800_0000;
@@ -173,68 +227,122 @@ impl Arm7TDMI {
         };
 
         Self {
-            regs,
+            regs: [0; 16],
             cpsr: Cpsr(0x1F),
             bus,
             spsr: Cpsr(0),
             banked_regs,
             branch: false,
+            cycles_taken: 0,
+            hle: false,
+            intr_wait: None,
+            instr_profile: None,
+        }
+    }
+
+    /// Skip the BIOS and start executing at `pc` in `mode`.
+    ///
+    /// Stack pointers are set up like the BIOS would leave them, bit 0 of `pc` selects THUMB.
+    pub fn boot_at(&mut self, pc: u32, mode: Mode) {
+        self.regs[13] = 0x0300_7F00;
+        self.banked_regs[Mode::Irq].bank[5] = 0x0300_7FA0;
+        self.banked_regs[Mode::Supervisor].bank[5] = 0x0300_7FE0;
+
+        self.swap_regs(self.cpsr.mode().unwrap_or(Mode::System), mode);
+        self.cpsr.set_mode(mode);
+
+        if pc & 1 != 0 {
+            self.cpsr.set_state(State::Thumb);
+            self.regs[15] = pc & !1;
+        } else {
+            self.cpsr.set_state(State::Arm);
+            self.regs[15] = pc & !3;
+        }
+    }
+
+    /// Fast interrupt request, only checked between instructions like IRQs.
+    ///
+    /// Nothing on the GBA raises it (except for some cartridge hardware), but the mode is fully usable.
+    pub fn dispatch_fiq(&mut self) {
+        if !self.cpsr.fiq() {
+            self.exception(Mode::Fiq, 0x1C, self.regs[15].wrapping_add(4));
+        }
+    }
+
+    /// Shared exception entry: bank registers, save CPSR in the new mode's SPSR and
+    /// the return address in its r14, then jump to `vector` in ARM state with IRQs disabled.
+    pub(super) fn exception(&mut self, mode: Mode, vector: u32, return_addr: u32) {
+        let cpsr = self.cpsr;
+
+        // Switch to ARM state.
+        self.cpsr.set_state(State::Arm);
+        self.cpsr.set_irq(true);
+        if mode == Mode::Fiq {
+            self.cpsr.set_fiq(true);
         }
+
+        // Switch to exception mode.
+        self.swap_regs(self.cpsr.mode().unwrap_or(Mode::System), mode);
+        self.cpsr.set_mode(mode);
+
+        self.regs[14] = return_addr;
+        self.spsr = cpsr;
+
+        self.regs[15] = vector;
     }
 
-    /// Cycle through an instruction with 1 CPI.
+    /// Cycle through an instruction, `cycles_taken` holds how long it took.
+    ///
+    /// Every instruction is at least 1S for its fetch, handlers add their data accesses and
+    /// internal cycles. Anything writing r15 adds 1S + 1N to refill the pipeline.
     pub fn cycle(&mut self) {
+        self.cycles_taken = 1;
+
         match self.cpsr.state() {
             State::Arm => {
                 let opcode = self.bus.read32(self.regs[15]);
+                self.bus.set_open_bus(opcode);
 
                 let cond = (opcode >> 28) & 0xF;
                 let op_index = ((opcode & 0x0FF0_0000) >> 16) | ((opcode & 0x00F0) >> 4);
 
                 if self.cond(cond as u8) {
-                    ARM_INSTRUCTIONS[op_index as usize](self, opcode);
+                    if let Some(profile) = &mut self.instr_profile {
+                        profile.record(Format::arm(opcode));
+                    }
+
+                    #[cfg(not(feature = "match-decoder"))]
+                    Self::ARM_INSTRUCTIONS[op_index as usize](self, opcode);
+                    #[cfg(feature = "match-decoder")]
+                    self.dispatch_arm(op_index as u16, opcode);
                 }
             }
             State::Thumb => {
                 let opcode = self.bus.read16(self.regs[15]);
-                THUMB_INSTRUCTIONS[(opcode >> 8) as usize](self, opcode);
+                self.bus.set_open_bus((opcode as u32) << 16 | opcode as u32);
+
+                if let Some(profile) = &mut self.instr_profile {
+                    profile.record(Format::thumb(opcode));
+                }
+
+                #[cfg(not(feature = "match-decoder"))]
+                Self::THUMB_INSTRUCTIONS[(opcode >> 6) as usize](self, opcode);
+                #[cfg(feature = "match-decoder")]
+                self.dispatch_thumb(opcode >> 6, opcode);
             }
         }
 
-        self.regs[15] += match self.cpsr.state() {
+        self.regs[15] = self.regs[15].wrapping_add(match self.cpsr.state() {
             State::Arm if !self.branch => 4,
             State::Thumb if !self.branch => 2,
             _ => 0,
-        };
-
-        self.branch = false;
-    }
-
-    /// Check for interrupts between instructions and jump to exception vector.
-    pub fn dispatch_irq(&mut self) {
-        if self.bus.ime.enabled() && !self.cpsr.irq() {
-            let int_e = self.bus.ie.ie();
-            let int_f = self.bus.iff.iff();
-
-            if (int_f & int_e) != 0 {
-                let cpsr = self.cpsr;
-
-                // Switch to ARM state.
-                self.cpsr.set_state(State::Arm);
-                self.cpsr.set_irq(true);
-
-                // Switch to IRQ mode.
-                self.swap_regs(self.cpsr.mode().unwrap(), Mode::Irq);
-                self.cpsr.set_mode(Mode::Irq);
+        });
 
-                // Save address of next instruction in r14_svc.
-                self.regs[14] = self.regs[15] + 4;
-                // Save CPSR in SPSR_svc.
-                self.spsr = cpsr;
-
-                self.regs[15] = 0x18;
-            }
+        if self.branch {
+            self.cycles_taken += 2;
         }
+
+        self.branch = false;
     }
 
     // ------------ ARM INSTRUCTIONS IMPLEMENTATION & SHIFTER. ------------
@@ -253,7 +361,7 @@ impl Arm7TDMI {
             (res, c)
         } else {
             let mut rm = if (op as usize & 0xF) == 15 {
-                self.regs[op as usize & 0xF] + 8
+                self.regs[op as usize & 0xF].wrapping_add(8)
             } else {
                 self.regs[op as usize & 0xF]
             };
@@ -306,6 +414,11 @@ impl Arm7TDMI {
         let rn = self.regs[(opcode as usize & 0x000F_0000) >> 16];
         let (op2, carry_out) = self.barrel_shifter::<I>(opcode as u16);
 
+        // Shifting by a register takes an internal cycle to read it.
+        if !I && opcode & (1 << 4) != 0 {
+            self.cycles_taken += 1;
+        }
+
         // Bits 21-24 specify the actual opcode.
         let operation = (opcode & 0x01E0_0000) >> 21;
         // Check if TST, TEQ, CMP, CMN.
@@ -328,7 +441,7 @@ impl Arm7TDMI {
             0b0010 => fl!(rn, op2, -, self, cpsr, S),
             0b0011 => fl!(op2, rn, -, self, cpsr, S),
             0b0100 => fl!(rn, op2, +, self, cpsr, S),
-            0b0101 => fl!(rn, op2 + self.cpsr.c() as u32, +, self, cpsr, S),
+            0b0101 => fl!(rn, op2, self.cpsr.c() as u32, +, self, cpsr, S),
             0b0110 => fl!(rn, op2, !self.cpsr.c() as u32, -, self, cpsr, S),
             0b0111 => fl!(op2, rn, !self.cpsr.c() as u32, -, self, cpsr, S),
             0b1000 => {is_intmd = true; rn & op2},
@@ -347,11 +460,9 @@ impl Arm7TDMI {
                 if self
                     .cpsr
                     .mode()
-                    .is_ok_and(|m| m != Mode::User || m != Mode::System)
+                    .is_ok_and(|m| m != Mode::User && m != Mode::System)
                 {
-                    let spsr = self.spsr;
-                    self.swap_regs(self.cpsr.mode().unwrap(), self.spsr.mode().unwrap());
-                    self.cpsr.set_cpsr(spsr.cpsr());
+                    self.restore_cpsr();
                 }
             } else {
                 // Set Zero flag iff result is all zeros.
@@ -386,7 +497,8 @@ impl Arm7TDMI {
         let rs = self.regs[(opcode as usize & 0x0F00) >> 8];
         let rn = self.regs[(opcode as usize & 0xF000) >> 12];
 
-        self.regs[rd] = rm * rs + (rn * acc as u32);
+        self.regs[rd] = rm.wrapping_mul(rs).wrapping_add(rn * acc as u32);
+        self.cycles_taken += mul_cycles(rs, true) + acc as u32;
 
         if S {
             self.cpsr.set_n(self.regs[rd] & (1 << 31) != 0);
@@ -405,10 +517,11 @@ impl Arm7TDMI {
         let rm = self.regs[opcode as usize & 0xF];
 
         let rd_hi_lo = ((self.regs[rd_hi] as u64) << 32) | self.regs[rd_lo] as u64;
+        self.cycles_taken += mul_cycles(rs, signed) + 1 + acc as u32;
 
         let res = match signed {
-            false => rm as u64 * rs as u64 + (rd_hi_lo * acc as u64),
-            true => ((rm as i32 as i64 * rs as i32 as i64) + (rd_hi_lo as i64 * acc as i64)) as u64,
+            false => (rm as u64 * rs as u64).wrapping_add(rd_hi_lo * acc as u64),
+            true => (rm as i32 as i64 * rs as i32 as i64).wrapping_add(rd_hi_lo as i64 * acc as i64) as u64,
         };
 
         self.regs[rd_hi] = (res >> 32) as u32;
@@ -425,6 +538,8 @@ impl Arm7TDMI {
         let rd = (opcode as usize & 0xF000) >> 12;
         let rn = self.regs[(opcode as usize & 0x000F_0000) >> 16];
         let rm = self.regs[opcode as usize & 0xF];
+        // 1S + 2N + 1I.
+        self.cycles_taken += 3;
 
         match B {
             false => {
@@ -470,11 +585,11 @@ impl Arm7TDMI {
         let signed_off = (offset << 6) as i32 >> 6;
 
         if link {
-            self.regs[14] = (self.regs[15] + 4) & !3;
+            self.regs[14] = (self.regs[15].wrapping_add(4)) & !3;
         }
 
         self.branch = true;
-        self.regs[15] = ((self.regs[15] + 8).wrapping_add_signed(signed_off)) & !3;
+        self.regs[15] = ((self.regs[15].wrapping_add(8)).wrapping_add_signed(signed_off)) & !3;
     }
 
     /// PSR Transfer. Transfer contents of CPSR/SPSR between registers.
@@ -527,7 +642,7 @@ impl Arm7TDMI {
 
             // If PSR = CPSR and modes differ and control bits get set, change mode.
             if let Ok(new_mode) = Mode::try_from(rm & 0x1F) {
-                if !PSR && current_mode != new_mode && opcode & (1 << 16) != 0 {
+                if !PSR && current_mode != Mode::User && current_mode != new_mode && opcode & (1 << 16) != 0 {
                     self.swap_regs(current_mode, new_mode);
                     self.cpsr.set_mode(new_mode);
                 }
@@ -536,24 +651,14 @@ impl Arm7TDMI {
     }
 
     /// Software Interrupt (T for Thumb).
-    pub fn swi<const T: bool>(&mut self, _opcode: u32) {
-        let cpsr = self.cpsr;
-
-        // Switch to ARM state.
-        self.cpsr.set_state(State::Arm);
-        self.cpsr.set_irq(true);
-
-        // Switch to SVC mode.
-        self.swap_regs(self.cpsr.mode().unwrap(), Mode::Supervisor);
-        self.cpsr.set_mode(Mode::Supervisor);
-
-        // Save address of next instruction in r14_svc.
-        self.regs[14] = self.regs[15] + if T { 2 } else { 4 };
-        // Save CPSR in SPSR_svc.
-        self.spsr = cpsr;
+    pub fn swi<const T: bool>(&mut self, opcode: u32) {
+        let comment = if T { opcode & 0xFF } else { (opcode >> 16) & 0xFF };
+        if self.hle && self.hle_swi(comment) {
+            return;
+        }
 
+        self.exception(Mode::Supervisor, 0x08, self.regs[15].wrapping_add(if T { 2 } else { 4 }));
         self.branch = true;
-        self.regs[15] = 0x08;
     }
 
     /// LDR and STR.
@@ -578,15 +683,15 @@ impl Arm7TDMI {
 
         let pc = if rn == 15 { 8 } else { 0 };
         let base_with_offset = if U {
-            self.regs[rn] + pc + offset
+            self.regs[rn].wrapping_add(pc).wrapping_add(offset)
         } else {
-            self.regs[rn] + pc - offset
+            self.regs[rn].wrapping_add(pc).wrapping_sub(offset)
         };
 
         let address = if P {
             base_with_offset
         } else {
-            self.regs[rn] + pc
+            self.regs[rn].wrapping_add(pc)
         };
 
         let (aligned_addr, ror) = if !B && address % 4 != 0 {
@@ -595,6 +700,9 @@ impl Arm7TDMI {
             (address, 0)
         };
 
+        // LDR is 1S + 1N + 1I, STR 2N.
+        self.cycles_taken += if L { 2 } else { 1 };
+
         // Load from memory if L, else store register into memory.
         if L {
             self.branch = rd == 15;
@@ -605,7 +713,7 @@ impl Arm7TDMI {
             };
         } else {
             let data = if rd == 15 {
-                self.regs[rd] + 12
+                self.regs[rd].wrapping_add(12)
             } else {
                 self.regs[rd]
             };
@@ -646,10 +754,11 @@ impl Arm7TDMI {
         let pc_off = (rn == 15) as u32 * 8;
 
         let base_with_offset = if U {
-            self.regs[rn] + offset
+            self.regs[rn].wrapping_add(offset)
         } else {
-            self.regs[rn] - offset
-        } + pc_off;
+            self.regs[rn].wrapping_sub(offset)
+        }
+        .wrapping_add(pc_off);
 
         let address = if P { base_with_offset } else { self.regs[rn] };
         let (aligned_addr, ror) = if address % 2 != 0 {
@@ -658,6 +767,9 @@ impl Arm7TDMI {
             (address, 0)
         };
 
+        // Like LDR/STR, 1S + 1N + 1I or 2N.
+        self.cycles_taken += if L { 2 } else { 1 };
+
         // Load from memory if L, else store register into memory.
         if L {
             if !S {
@@ -672,7 +784,7 @@ impl Arm7TDMI {
         } else {
             self.bus.write16(
                 aligned_addr,
-                self.regs[rd] as u16 + if rd == 15 { 12 } else { 0 }
+                (self.regs[rd] as u16).wrapping_add(if rd == 15 { 12 } else { 0 })
             );
         }
         
@@ -702,6 +814,10 @@ impl Arm7TDMI {
         // Edge case: PSR bit and r15 not in list.
         let user_bank = S && !reg_list.contains(&15);
 
+        // LDM is nS + 1N + 1I, STM (n - 1)S + 2N. An empty list transfers r15 only.
+        let n = reg_list.len().max(1) as u32;
+        self.cycles_taken += if L { n + 1 } else { n };
+
         let mut address = self.regs[rn];
         // Force align address but not directly modify it -- writeback is not aligned.
         let aligned_addr = |address: u32| {
@@ -715,9 +831,9 @@ impl Arm7TDMI {
         // Edge case: empty register list.
         if reg_list.is_empty() {
             address = match (U, P) {
-                (false, false) => self.regs[rn] - 0x3C,
-                (false, true) => self.regs[rn] - 0x40,
-                (true, true) => self.regs[rn] + 0x4,
+                (false, false) => self.regs[rn].wrapping_sub(0x3C),
+                (false, true) => self.regs[rn].wrapping_sub(0x40),
+                (true, true) => self.regs[rn].wrapping_add(0x4),
                 (true, false) => self.regs[rn],
             };
 
@@ -726,13 +842,13 @@ impl Arm7TDMI {
                 self.regs[15] = self.bus.read32(aligned_addr(address));
             } else {
                 self.bus
-                    .write32(aligned_addr(address), (self.regs[15] + 12) & !3);
+                    .write32(aligned_addr(address), self.regs[15].wrapping_add(12) & !3);
             }
 
             self.regs[rn] = if U {
-                self.regs[rn] + 0x40
+                self.regs[rn].wrapping_add(0x40)
             } else {
-                self.regs[rn] - 0x40
+                self.regs[rn].wrapping_sub(0x40)
             };
             return;
         }
@@ -744,18 +860,16 @@ impl Arm7TDMI {
         for r in &reg_list {
             if P {
                 // Pre-{inc, dec}rement addressing.
-                address = if U { address + 4 } else { address - 4 };
+                address = if U { address.wrapping_add(4) } else { address.wrapping_sub(4) };
             }
 
             if L {
-                // Edge case: PSR bit and r15 in list.
-                if S && *r == 15 {
-                    self.cpsr.set_cpsr(self.spsr.cpsr());
-                }
-
                 match user_bank {
                     false => self.regs[*r] = self.bus.read32(aligned_addr(address)),
-                    true => self.banked_regs.sys_regs.bank[*r] = self.bus.read32(aligned_addr(address)),
+                    true => {
+                        let value = self.bus.read32(aligned_addr(address));
+                        self.set_user_reg(*r, value);
+                    }
                 }
             } else {
                 // Edge case: rb in reg list and not first.
@@ -765,18 +879,18 @@ impl Arm7TDMI {
                     self.bus.write32(
                         aligned_addr(address),
                         if U {
-                            self.regs[rn] + (reg_list.len() as u32 * 4)
+                            self.regs[rn].wrapping_add(reg_list.len() as u32 * 4)
                         } else {
-                            self.regs[rn] - (reg_list.len() as u32 * 4)
+                            self.regs[rn].wrapping_sub(reg_list.len() as u32 * 4)
                         },
                     )
                 } else {
                     self.bus.write32(
                         aligned_addr(address),
                         if !user_bank {
-                            self.regs[*r] + if *r == 15 { 12 } else { 0 }
+                            self.regs[*r].wrapping_add(if *r == 15 { 12 } else { 0 })
                         } else {
-                            self.banked_regs.sys_regs.bank[*r]
+                            self.user_reg(*r)
                         },
                     );
                 }
@@ -784,7 +898,7 @@ impl Arm7TDMI {
 
             if !P {
                 // Post-{inc, dec}rement addressing.
-                address = if U { address + 4 } else { address - 4 };
+                address = if U { address.wrapping_add(4) } else { address.wrapping_sub(4) };
             }
         }
 
@@ -793,11 +907,17 @@ impl Arm7TDMI {
         if (W && (L && !reg_list.contains(&rn))) || (!L && W) {
             self.regs[rn] = address;
         }
+
+        // Edge case: PSR bit and r15 in list, return from exception once the writeback went to its bank.
+        if L && S && reg_list.contains(&15) {
+            self.restore_cpsr();
+        }
     }
 
-    /// Test for LUT.
+    /// Undefined instructions (and coprocessor ones, there are none) trap to the undefined vector.
     pub fn undefined(&mut self, _opcode: u32) {
-        panic!("shouldn't be called!")
+        self.exception(Mode::Undefined, 0x04, self.regs[15].wrapping_add(4));
+        self.branch = true;
     }
 
     // BARREL SHIFTER UTILITY METHODS.
@@ -860,19 +980,13 @@ impl Arm7TDMI {
             return (rm, self.cpsr.c());
         }
 
+        // ASR #0 encodes ASR #32, every bit becomes the sign bit.
         let bit31 = rm & (1 << 31);
-        let carry = rm & (1 << (amount - 1)) != 0;
-
-        let mut rm = rm >> amount;
-        for i in 0..amount {
-            rm |= bit31 >> i;
-        }
-
         if amount == 0 || amount >= 32 {
-            ((bit31 >> 31) * 0xFFFF_FFFF, bit31 != 0)
-        } else {
-            (rm, carry)
+            return ((bit31 >> 31) * 0xFFFF_FFFF, bit31 != 0);
         }
+
+        (((rm as i32) >> amount) as u32, rm & (1 << (amount - 1)) != 0)
     }
 
     /// Rotate right, returns result and carry out.
@@ -886,7 +1000,42 @@ impl Arm7TDMI {
             }
         }
 
-        (rm.rotate_right(amount), rm & (1 << (amount - 1)) != 0)
+        // Rotating by 32 or more is the same as by `amount % 32`, with 32 giving back bit 31 as carry.
+        (rm.rotate_right(amount), rm & (1 << ((amount - 1) % 32)) != 0)
+    }
+
+    /// User mode view of register `r` (LDM/STM with S bit).
+    fn user_reg(&self, r: usize) -> u32 {
+        match (r, self.cpsr.mode()) {
+            (8..=12, Ok(Mode::Fiq)) => self.banked_regs.sys_regs.bank[r - 8],
+            (13..=14, Ok(mode)) if mode != Mode::User && mode != Mode::System => {
+                self.banked_regs.sys_regs.bank[r - 8]
+            }
+            _ => self.regs[r],
+        }
+    }
+
+    fn set_user_reg(&mut self, r: usize, value: u32) {
+        match (r, self.cpsr.mode()) {
+            (8..=12, Ok(Mode::Fiq)) => self.banked_regs.sys_regs.bank[r - 8] = value,
+            (13..=14, Ok(mode)) if mode != Mode::User && mode != Mode::System => {
+                self.banked_regs.sys_regs.bank[r - 8] = value
+            }
+            _ => self.regs[r] = value,
+        }
+    }
+
+    /// Return from an exception: CPSR = SPSR, banking in the registers of the mode returned to.
+    ///
+    /// Nested IRQs rely on this, the outer handler's r13/r14 must be back when the inner one returns.
+    fn restore_cpsr(&mut self) {
+        let spsr = self.spsr;
+
+        if let (Ok(current_mode), Ok(new_mode)) = (self.cpsr.mode(), spsr.mode()) {
+            self.swap_regs(current_mode, new_mode);
+        }
+
+        self.cpsr = spsr;
     }
 
     /// Swap banked registers on mode change. Call before changing mode in CPSR.
@@ -906,18 +1055,18 @@ impl Arm7TDMI {
             _ => self.banked_regs[new_mode].spsr,
         };
 
-        // If we are switching from FIQ: load regs 8-14 back into FIQ bank.
-        // Load old system registers back in before switching to new mode register.
+        // Bank slots 0-4 hold r8-r12, only FIQ has its own, all other modes share the system ones.
+        // If we are switching from FIQ: save r8-r12 into FIQ bank and load the system registers back.
         if current_mode == Mode::Fiq {
-            self.banked_regs[current_mode].bank.copy_from_slice(&self.regs[8..=14]);
-            self.regs[8..=14].copy_from_slice(&self.banked_regs.sys_regs.bank[8..=14]);
+            self.banked_regs.fiq_regs.bank[0..5].copy_from_slice(&self.regs[8..=12]);
+            self.regs[8..=12].copy_from_slice(&self.banked_regs.sys_regs.bank[0..5]);
         }
 
-        // If new mode is FIQ: copy current registers into system bank.
-        // Then, load FIQ regs into registers. 
+        // If new mode is FIQ: copy current r8-r12 into system bank.
+        // Then, load FIQ regs into registers.
         if new_mode == Mode::Fiq {
-            self.banked_regs.sys_regs.bank[8..=14].copy_from_slice(&self.regs[8..=14]);
-            self.regs[8..=14].copy_from_slice(&self.banked_regs[new_mode].bank);
+            self.banked_regs.sys_regs.bank[0..5].copy_from_slice(&self.regs[8..=12]);
+            self.regs[8..=12].copy_from_slice(&self.banked_regs.fiq_regs.bank[0..5]);
         }
 
         // In all other cases: just swap r13 and r14 between old and new mode.
@@ -928,3 +1077,426 @@ impl Arm7TDMI {
         self.regs[14] = self.banked_regs[new_mode].bank[6];
     }
 }
+
+/// Internal cycles of a multiply by `rs`, the multiplier stops early on leading zeros (and ones if `signed`).
+pub(super) fn mul_cycles(rs: u32, signed: bool) -> u32 {
+    let rs = if signed && rs & (1 << 31) != 0 { !rs } else { rs };
+
+    match rs {
+        0..=0xFF => 1,
+        0x100..=0xFFFF => 2,
+        0x1_0000..=0xFF_FFFF => 3,
+        _ => 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mmu::flat_mem::FlatMem, rng::Rng};
+
+    /// Take an IRQ on the next instruction boundary like `dispatch_irq`, if the CPSR allows it.
+    fn irq(cpu: &mut Arm7TDMI<FlatMem>) {
+        assert!(!cpu.cpsr.irq(), "IRQs are masked at {:08X}", cpu.regs[15]);
+        cpu.exception(Mode::Irq, 0x18, cpu.regs[15].wrapping_add(4));
+    }
+
+    fn mem_write(cpu: &mut Arm7TDMI<FlatMem>, address: usize, word: u32) {
+        cpu.bus.0[address..address + 4].copy_from_slice(&word.to_le_bytes());
+    }
+
+    /// A CPU in System mode about to run `program` at 0x100, THUMB halfwords if `thumb`.
+    fn cpu_with(program: &[u32], thumb: bool) -> Arm7TDMI<FlatMem> {
+        let mut cpu = Arm7TDMI::with_bus(FlatMem::new(0x1000));
+        let size = if thumb { 2 } else { 4 };
+        for (i, opcode) in program.iter().enumerate() {
+            cpu.bus.0[0x100 + i * size..0x100 + (i + 1) * size].copy_from_slice(&opcode.to_le_bytes()[..size]);
+        }
+
+        cpu.boot_at(0x100 | thumb as u32, Mode::System);
+        cpu
+    }
+
+    fn run_to(cpu: &mut Arm7TDMI<FlatMem>, pc: u32) {
+        for _ in 0..100 {
+            if cpu.regs[15] == pc {
+                return;
+            }
+            cpu.cycle();
+        }
+        panic!("never reached {pc:08X}, stuck at {:08X}", cpu.regs[15]);
+    }
+
+    #[test]
+    fn nested_irq() {
+        let mut cpu = Arm7TDMI::with_bus(FlatMem::new(0x1000));
+        let program: [(u32, &[u32]); 4] = [
+            // b 0x200
+            (0x018, &[0xEA00_0078]),
+            // Main loop: b .
+            (0x100, &[0xEAFF_FFFE]),
+            // Outer handler: sub lr, lr, #4; mrs r0, spsr; stmfd sp!, {r0, lr};
+            // msr cpsr_c, #0x12 (IRQs on); nop; msr cpsr_c, #0x92 (IRQs off);
+            // ldmfd sp!, {r0, lr}; msr spsr_fsxc, r0; movs pc, lr
+            (0x200, &[
+                0xE24E_E004, 0xE14F_0000, 0xE92D_4001, 0xE321_F012, 0xE1A0_0000,
+                0xE321_F092, 0xE8BD_4001, 0xE16F_F000, 0xE1B0_F00E,
+            ]),
+            // Inner handler: subs pc, lr, #4
+            (0x300, &[0xE25E_F004]),
+        ];
+        for (address, words) in program {
+            for (i, &word) in words.iter().enumerate() {
+                mem_write(&mut cpu, address as usize + i * 4, word);
+            }
+        }
+
+        cpu.boot_at(0x100, Mode::System);
+        cpu.regs[13] = 0x800;
+        cpu.banked_regs[Mode::Irq].bank[5] = 0xF00;
+        let main_cpsr = cpu.cpsr;
+
+        // Outer IRQ, the handler saves SPSR and LR and turns IRQs back on.
+        irq(&mut cpu);
+        run_to(&mut cpu, 0x210);
+        assert_eq!(cpu.cpsr.mode(), Ok(Mode::Irq));
+        assert_eq!(cpu.regs[13], 0xF00 - 8);
+
+        // Inner IRQ clobbers r14_irq and SPSR_irq, the outer handler has them on its stack.
+        mem_write(&mut cpu, 0x18, 0xEA00_00B8); // b 0x300
+        irq(&mut cpu);
+        assert_eq!(cpu.regs[15], 0x18);
+        assert_eq!(cpu.regs[14], 0x214);
+        assert_eq!(cpu.spsr.mode(), Ok(Mode::Irq));
+        assert_eq!(cpu.regs[13], 0xF00 - 8, "r13_irq is shared by both levels");
+
+        // Back in the outer handler, still in IRQ mode.
+        run_to(&mut cpu, 0x210);
+        assert_eq!(cpu.cpsr.mode(), Ok(Mode::Irq));
+        assert!(!cpu.cpsr.irq());
+
+        // Back in the main loop with everything the way the outer IRQ found it.
+        run_to(&mut cpu, 0x214);
+        run_to(&mut cpu, 0x100);
+        assert_eq!(cpu.cpsr.0, main_cpsr.0);
+        assert_eq!(cpu.regs[13], 0x800);
+        assert_eq!(cpu.banked_regs[Mode::Irq].bank[5], 0xF00, "r13_irq");
+        assert_eq!(cpu.banked_regs[Mode::Irq].bank[6], 0x100, "r14_irq");
+        assert_eq!(cpu.banked_regs[Mode::Irq].spsr.0, main_cpsr.0, "SPSR_irq");
+    }
+    #[test]
+    fn register_shift_amounts() {
+        const VALUE: u32 = 0x8000_0001;
+        // (amount, result, C) per shift type, `None` keeps the carry in.
+        type Case = (u32, u32, Option<bool>);
+        #[rustfmt::skip]
+        let cases: [(&str, [Case; 4]); 4] = [
+            ("LSL", [(0, VALUE, None), (32, 0, Some(true)), (33, 0, Some(false)), (255, 0, Some(false))]),
+            ("LSR", [(0, VALUE, None), (32, 0, Some(true)), (33, 0, Some(false)), (255, 0, Some(false))]),
+            ("ASR", [(0, VALUE, None), (32, !0, Some(true)), (33, !0, Some(true)), (255, !0, Some(true))]),
+            ("ROR", [(0, VALUE, None), (32, VALUE, Some(true)), (33, 0xC000_0000, Some(true)), (255, 3, Some(false))]),
+        ];
+        // THUMB LSL, LSR, ASR and ROR Rd, Rs.
+        let thumb_ops = [0x2, 0x3, 0x4, 0x7];
+
+        for (shift, ((name, amounts), thumb_op)) in cases.into_iter().zip(thumb_ops).enumerate() {
+            for (amount, result, carry) in amounts {
+                for carry_in in [false, true] {
+                    // Only the low byte of the register counts.
+                    let rs = 0xFFFF_FF00 | amount;
+
+                    // MOVS r0, r1, <shift> r2
+                    let mut arm = cpu_with(&[0xE1B0_0211 | (shift as u32) << 5], false);
+                    (arm.regs[1], arm.regs[2]) = (VALUE, rs);
+                    // <shift> r0, r2
+                    let mut thumb = cpu_with(&[0x4010 | thumb_op << 6], true);
+                    (thumb.regs[0], thumb.regs[2]) = (VALUE, rs);
+
+                    for (set, mut cpu) in [("ARM", arm), ("THUMB", thumb)] {
+                        cpu.cpsr.set_c(carry_in);
+                        cpu.cycle();
+                        assert_eq!(
+                            (cpu.regs[0], cpu.cpsr.c()),
+                            (result, carry.unwrap_or(carry_in)),
+                            "{set} {name} by {amount}, C was {carry_in}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+    #[test]
+    fn carry_and_overflow_with_carry_in() {
+        // (a, b, C in, result, C, V), ADC is a + b + C, SBC a - b - !C and RSC b - a - !C.
+        type Case = (u32, u32, bool, u32, bool, bool);
+        #[rustfmt::skip]
+        let adc: &[Case] = &[
+            (0xFFFF_FFFF, 1, false, 0, true, false),
+            (0xFFFF_FFFF, 1, true, 1, true, false),
+            (1, 0xFFFF_FFFF, true, 1, true, false),
+            (0xFFFF_FFFF, 0xFFFF_FFFF, true, 0xFFFF_FFFF, true, false),
+            (0x7FFF_FFFF, 0, true, 0x8000_0000, false, true),
+            (0x8000_0000, 0x7FFF_FFFF, true, 0, true, false),
+        ];
+        #[rustfmt::skip]
+        let sbc: &[Case] = &[
+            (0, 0, true, 0, true, false),
+            (0, 0, false, 0xFFFF_FFFF, false, false),
+            (0, 0xFFFF_FFFF, false, 0, false, false),
+            (0x8000_0000, 0, false, 0x7FFF_FFFF, true, true),
+            (0x7FFF_FFFF, 0xFFFF_FFFF, true, 0x8000_0000, false, true),
+        ];
+        #[rustfmt::skip]
+        let rsc: &[Case] = &[
+            (0, 0, true, 0, true, false),
+            (0, 0, false, 0xFFFF_FFFF, false, false),
+            (1, 0x8000_0000, true, 0x7FFF_FFFF, true, true),
+        ];
+
+        // ADCS, SBCS and RSCS r0, r1, r2, THUMB ADC and SBC r0, r2.
+        let ops = [
+            ("ADC", adc, 0xE0B1_0002, Some(0x4150)),
+            ("SBC", sbc, 0xE0D1_0002, Some(0x4190)),
+            ("RSC", rsc, 0xE0F1_0002, None),
+        ];
+
+        for (name, cases, arm_op, thumb_op) in ops {
+            for &(a, b, carry_in, result, c, v) in cases {
+                let mut arm = cpu_with(&[arm_op], false);
+                (arm.regs[1], arm.regs[2]) = (a, b);
+                let mut cpus = vec![("ARM", arm)];
+
+                if let Some(op) = thumb_op {
+                    let mut thumb = cpu_with(&[op], true);
+                    (thumb.regs[0], thumb.regs[2]) = (a, b);
+                    cpus.push(("THUMB", thumb));
+                }
+
+                for (set, mut cpu) in cpus {
+                    cpu.cpsr.set_c(carry_in);
+                    cpu.cycle();
+                    assert_eq!(
+                        (cpu.regs[0], cpu.cpsr.c(), cpu.cpsr.v()),
+                        (result, c, v),
+                        "{set} {name} {a:08X}, {b:08X}, C {carry_in}"
+                    );
+                }
+            }
+        }
+    }
+    #[test]
+    fn fiq_banks_r8_to_r14() {
+        // msr cpsr_c, #0xD1 (FIQ); mov r8-r14, #0x18-#0x1E; msr cpsr_c, #0xDF (System);
+        // msr cpsr_c, #0xD1
+        let mut program = vec![0xE321_F0D1];
+        program.extend((8..=14).map(|r| 0xE3A0_0000 | r << 12 | (r + 0x10)));
+        program.extend([0xE321_F0DF, 0xE321_F0D1]);
+
+        let mut cpu = cpu_with(&program, false);
+        let user: Vec<u32> = (8..=14).collect();
+        cpu.regs[8..=14].copy_from_slice(&user);
+
+        for _ in 0..program.len() - 1 {
+            cpu.cycle();
+        }
+        assert_eq!(cpu.cpsr.mode(), Ok(Mode::System));
+        assert_eq!(cpu.regs[8..=14], user, "User r8-r14");
+
+        cpu.cycle();
+        assert_eq!(cpu.cpsr.mode(), Ok(Mode::Fiq));
+        assert_eq!(cpu.regs[8..=14], [0x18, 0x19, 0x1A, 0x1B, 0x1C, 0x1D, 0x1E], "FIQ r8-r14");
+    }
+
+    #[test]
+    fn fiq_entry() {
+        let mut cpu = cpu_with(&[], false);
+        cpu.regs[8..=14].copy_from_slice(&[8, 9, 10, 11, 12, 13, 14]);
+        let cpsr = cpu.cpsr.0;
+
+        cpu.dispatch_fiq();
+        assert_eq!((cpu.cpsr.mode(), cpu.regs[15]), (Ok(Mode::Fiq), 0x1C));
+        assert!(cpu.cpsr.fiq() && cpu.cpsr.irq(), "FIQs and IRQs not disabled");
+        assert_eq!((cpu.regs[14], cpu.spsr.0), (0x104, cpsr));
+        assert_eq!(cpu.regs[8..=13], [0; 6], "r8-r13 not banked");
+
+        // Masked now, a second FIQ waits.
+        cpu.regs[15] = 0x200;
+        cpu.dispatch_fiq();
+        assert_eq!(cpu.regs[15], 0x200);
+    }
+    fn mem_read(cpu: &Arm7TDMI<FlatMem>, address: usize) -> u32 {
+        u32::from_le_bytes(cpu.bus.0[address..address + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn single_data_transfers() {
+        let mut cpu = cpu_with(
+            &[
+                0xE5B1_0004, // ldr r0, [r1, #4]!
+                0xE402_0008, // str r0, [r2], #-8
+                0xE1D1_30B2, // ldrh r3, [r1, #2]
+                0xE1D1_40D3, // ldrsb r4, [r1, #3]
+                0xE591_5001, // ldr r5, [r1, #1]
+                0xE5C2_0001, // strb r0, [r2, #1]
+            ],
+            false,
+        );
+        mem_write(&mut cpu, 0x204, 0xDEAD_BEEF);
+        (cpu.regs[1], cpu.regs[2]) = (0x200, 0x300);
+
+        for _ in 0..6 {
+            cpu.cycle();
+        }
+        assert_eq!(cpu.regs[0..=5], [0xDEAD_BEEF, 0x204, 0x2F8, 0xDEAD, 0xFFFF_FFDE, 0xEFDE_ADBE]);
+        assert_eq!(mem_read(&cpu, 0x300), 0xDEAD_BEEF);
+        assert_eq!(mem_read(&cpu, 0x2F8), 0xEF00);
+    }
+
+    #[test]
+    fn block_transfers_and_swap() {
+        let mut cpu = cpu_with(
+            &[
+                0xE8A0_000E, // stmia r0!, {r1-r3}
+                0xE930_0070, // ldmdb r0!, {r4-r6}
+                0xE107_8091, // swp r8, r1, [r7]
+                0xE147_9092, // swpb r9, r2, [r7]
+            ],
+            false,
+        );
+        cpu.regs[0..=3].copy_from_slice(&[0x200, 0x1111_1111, 0x2222_2222, 0x3333_3333]);
+        cpu.regs[7] = 0x300;
+        mem_write(&mut cpu, 0x300, 0xCAFE_F00D);
+
+        for _ in 0..4 {
+            cpu.cycle();
+        }
+        assert_eq!([mem_read(&cpu, 0x200), mem_read(&cpu, 0x204), mem_read(&cpu, 0x208)], cpu.regs[1..=3]);
+        assert_eq!(cpu.regs[0], 0x200, "writeback");
+        assert_eq!(cpu.regs[4..=6], cpu.regs[1..=3]);
+        assert_eq!((cpu.regs[8], cpu.regs[9]), (0xCAFE_F00D, 0x11));
+        assert_eq!(mem_read(&cpu, 0x300), 0x1111_1122);
+    }
+
+    #[test]
+    fn multiplies() {
+        let mut cpu = cpu_with(
+            &[
+                0xE081_0392, // umull r0, r1, r2, r3
+                0xE0C5_4392, // smull r4, r5, r2, r3
+                0xE0A5_4392, // umlal r4, r5, r2, r3
+                0xE036_7392, // mlas r6, r2, r3, r7
+            ],
+            false,
+        );
+        (cpu.regs[2], cpu.regs[3], cpu.regs[7]) = (0xFFFF_FFFF, 2, 2);
+
+        for _ in 0..4 {
+            cpu.cycle();
+        }
+        assert_eq!((cpu.regs[0], cpu.regs[1]), (0xFFFF_FFFE, 1));
+        // -2 + 0x1_FFFF_FFFE, the 64-bit sum wraps.
+        assert_eq!((cpu.regs[4], cpu.regs[5]), (0xFFFF_FFFC, 1));
+        assert_eq!(cpu.regs[6], 0);
+        assert!(cpu.cpsr.z() && !cpu.cpsr.n());
+    }
+
+    #[test]
+    fn thumb_stack_and_branches() {
+        let mut cpu = cpu_with(
+            &[
+                0xB503, // push {r0, r1, lr}
+                0xBC0C, // pop {r2, r3}
+                0x4802, // ldr r0, [pc, #8]
+                0xF000, // bl 0x200
+                0xF87B,
+            ],
+            true,
+        );
+        cpu.regs[0..2].copy_from_slice(&[0x1234, 0x5678]);
+        (cpu.regs[13], cpu.regs[14]) = (0x400, 0x0999);
+        mem_write(&mut cpu, 0x110, 0xAAAA_5555);
+
+        for _ in 0..2 {
+            cpu.cycle();
+        }
+        assert_eq!([mem_read(&cpu, 0x3F4), mem_read(&cpu, 0x3F8), mem_read(&cpu, 0x3FC)], [0x1234, 0x5678, 0x0999]);
+        assert_eq!((cpu.regs[2], cpu.regs[3], cpu.regs[13]), (0x1234, 0x5678, 0x3FC));
+
+        cpu.cycle();
+        assert_eq!(cpu.regs[0], 0xAAAA_5555);
+
+        for _ in 0..2 {
+            cpu.cycle();
+        }
+        assert_eq!((cpu.regs[15], cpu.regs[14]), (0x200, 0x10B));
+    }
+    /// The exception one instruction at 0x100 took, if any.
+    fn trap(cpu: &Arm7TDMI<FlatMem>, return_addr: u32) -> Option<Mode> {
+        match (cpu.cpsr.mode(), cpu.regs[15], cpu.regs[14] == return_addr) {
+            (Ok(Mode::Undefined), 0x04, true) => Some(Mode::Undefined),
+            (Ok(Mode::Supervisor), 0x08, true) => Some(Mode::Supervisor),
+            _ => None,
+        }
+    }
+
+    /// Every LUT index (12 bits for ARM, 10 for THUMB) runs a handler of its format, through
+    /// whichever decoder is built. Undefined and coprocessor encodings trap, SWIs enter Supervisor.
+    #[test]
+    fn decoder_covers_every_index() {
+        let mut rng = Rng::new(1);
+        let expected = |format| match format {
+            Format::Undefined | Format::Coprocessor => Some(Mode::Undefined),
+            Format::SoftwareInterrupt | Format::ThumbSoftwareInterrupt => Some(Mode::Supervisor),
+            _ => None,
+        };
+
+        for index in 0..4096 {
+            let opcode = 0xE000_0000 | (index & 0xFF0) << 16 | (index & 0xF) << 4 | rng.u32() & 0x000F_FF0F;
+            let mut cpu = cpu_with(&[opcode], false);
+            cpu.cycle();
+            assert_eq!(trap(&cpu, 0x104), expected(Format::arm(opcode)), "ARM {index:03X}: {opcode:08X}");
+        }
+
+        for index in 0..1024 {
+            let opcode = (index << 6 | rng.below(64)) as u16;
+            let mut cpu = cpu_with(&[opcode as u32], true);
+            cpu.cycle();
+            assert_eq!(trap(&cpu, 0x102), expected(Format::thumb(opcode)), "THUMB {index:03X}: {opcode:04X}");
+        }
+    }
+    /// The generated `match` calls the same handler as the LUT for every index, so both leave
+    /// random CPU states and memory the same.
+    #[cfg(feature = "match-decoder")]
+    #[test]
+    fn match_decoder_equals_luts() {
+        let random_cpu = |seed: u64, thumb: bool| {
+            let mut rng = Rng::new(seed);
+            let mut cpu = cpu_with(&[], thumb);
+            rng.fill(&mut cpu.bus.0);
+            cpu.regs[..15].iter_mut().for_each(|r| *r = rng.below(0x1000));
+            cpu.cpsr.0 = cpu.cpsr.0 & 0x0FFF_FFFF | rng.u32() & 0xF000_0000;
+            (cpu, rng.u32())
+        };
+        let state = |cpu: &Arm7TDMI<FlatMem>| (cpu.regs, cpu.cpsr.0, cpu.spsr.0, cpu.bus.0.clone(), cpu.cycles_taken);
+
+        for index in 0..4096 {
+            let (mut lut, random) = random_cpu(index as u64 + 1, false);
+            let (mut matched, _) = random_cpu(index as u64 + 1, false);
+            let opcode = 0xE000_0000 | (index & 0xFF0) << 16 | (index & 0xF) << 4 | random & 0x000F_FF0F;
+
+            Arm7TDMI::ARM_INSTRUCTIONS[index as usize](&mut lut, opcode);
+            matched.dispatch_arm(index as u16, opcode);
+            assert!(state(&lut) == state(&matched), "ARM {index:03X}: {opcode:08X}");
+        }
+
+        for index in 0..1024 {
+            let (mut lut, random) = random_cpu(index as u64 + 1, true);
+            let (mut matched, _) = random_cpu(index as u64 + 1, true);
+            let opcode = (index << 6 | random & 0x3F) as u16;
+
+            Arm7TDMI::THUMB_INSTRUCTIONS[index as usize](&mut lut, opcode);
+            matched.dispatch_thumb(index as u16, opcode);
+            assert!(state(&lut) == state(&matched), "THUMB {index:03X}: {opcode:04X}");
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use std::time::Instant;
+
+use crate::mmu::{flat_mem::FlatMem, Mcu};
+
+use super::arm7tdmi::{Arm7TDMI, State};
+
+/// Decoder this binary was built with, see the `match-decoder` feature.
+pub const DECODER: &str = if cfg!(feature = "match-decoder") { "match" } else { "lut" };
+
+/// add, add, str, ldr, mov with shift, eor, cmp and a branch back to the start.
+const ARM_LOOP: [u32; 8] = [
+    0xE280_0001, 0xE081_1000, 0xE582_1000, 0xE592_3000,
+    0xE1A0_4103, 0xE025_5004, 0xE350_0000, 0xEAFF_FFF7,
+];
+
+/// The same loop in THUMB, placed at `THUMB_START`.
+const THUMB_LOOP: [u16; 8] = [0x3001, 0x1809, 0x6011, 0x6813, 0x009C, 0x4065, 0x2800, 0xE7F7];
+const THUMB_START: u32 = 0x100;
+
+/// Run `instructions` of a synthetic interpreter-heavy loop per instruction set on `FlatMem`.
+///
+/// Returns millions of instructions per second for ARM and THUMB. Build with and
+/// without `match-decoder` to compare the two dispatch strategies.
+pub fn run(instructions: u64) -> [(&'static str, f64); 2] {
+    let mut mem = FlatMem::new(0x2000);
+    for (i, op) in ARM_LOOP.into_iter().enumerate() {
+        mem.write32(i as u32 * 4, op);
+    }
+    for (i, op) in THUMB_LOOP.into_iter().enumerate() {
+        mem.write16(THUMB_START + i as u32 * 2, op);
+    }
+
+    let mips = |thumb: bool| {
+        let mut cpu = Arm7TDMI::with_bus(mem.clone());
+        cpu.regs[2] = 0x1000;
+
+        if thumb {
+            cpu.cpsr.set_state(State::Thumb);
+            cpu.regs[15] = THUMB_START;
+        }
+
+        let start = Instant::now();
+        for _ in 0..instructions {
+            cpu.cycle();
+        }
+
+        instructions as f64 / start.elapsed().as_secs_f64() / 1e6
+    };
+
+    [("arm", mips(false)), ("thumb", mips(true))]
+}
@@ -0,0 +1,61 @@
+use crate::mmu::Mcu;
+
+use super::arm7tdmi::Arm7TDMI;
+
+/// Interrupt flags the user IRQ handler acknowledges for the BIOS (IWRAM, mirrored at 0x03FFFFF8).
+pub const BIOS_IF: u32 = 0x0300_7FF8;
+
+const IME: u32 = 0x0400_0208;
+const HALTCNT: u32 = 0x0400_0301;
+
+impl<M: Mcu> Arm7TDMI<M> {
+    /// High level emulation of the SWI `comment`, false if it has to go through the BIOS.
+    pub(super) fn hle_swi(&mut self, comment: u32) -> bool {
+        match comment {
+            0x04 => self.intr_wait(false),
+            0x05 => self.intr_wait(true),
+            _ => return false,
+        }
+
+        true
+    }
+
+    /// IntrWait (`r0` = discard old flags, `r1` = flags to wait for) and VBlankIntrWait.
+    ///
+    /// Like the BIOS, this waits on `BIOS_IF` rather than IF: halt, let the IRQ handler run,
+    /// then check again. The SWI is executed again after every interrupt until one of the flags shows up.
+    fn intr_wait(&mut self, vblank: bool) {
+        let flags = match self.intr_wait {
+            Some(flags) => flags,
+            None => {
+                if vblank {
+                    self.regs[0] = 1;
+                    self.regs[1] = 1;
+                }
+
+                let flags = self.regs[1] as u16;
+                self.bus.write8(IME, 1);
+
+                if self.regs[0] != 0 {
+                    let bios_if = self.bus.read16(BIOS_IF);
+                    self.bus.write16(BIOS_IF, bios_if & !flags);
+                }
+
+                flags
+            }
+        };
+
+        let bios_if = self.bus.read16(BIOS_IF);
+        if bios_if & flags != 0 {
+            self.bus.write16(BIOS_IF, bios_if & !flags);
+            self.intr_wait = None;
+            return;
+        }
+
+        self.intr_wait = Some(flags);
+        self.bus.write8(HALTCNT, 0);
+
+        // Stay on the SWI, the IRQ returns to it.
+        self.branch = true;
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt;
+
+/// Instruction formats counted by `InstrProfile`, ARM first then THUMB.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    DataProcessing,
+    PsrTransfer,
+    Multiply,
+    MultiplyLong,
+    Swap,
+    BranchExchange,
+    HalfwordTransfer,
+    SingleDataTransfer,
+    Undefined,
+    BlockDataTransfer,
+    Branch,
+    Coprocessor,
+    SoftwareInterrupt,
+
+    ThumbMoveShifted,
+    ThumbAddSub,
+    ThumbImmediate,
+    ThumbAlu,
+    ThumbHiRegBx,
+    ThumbPcRelativeLoad,
+    ThumbLoadStoreReg,
+    ThumbLoadStoreSignExt,
+    ThumbLoadStoreImm,
+    ThumbLoadStoreHalfword,
+    ThumbSpRelative,
+    ThumbLoadAddress,
+    ThumbAddSp,
+    ThumbPushPop,
+    ThumbMultiple,
+    ThumbCondBranch,
+    ThumbSoftwareInterrupt,
+    ThumbBranch,
+    ThumbLongBranchLink,
+}
+
+const FORMAT_COUNT: usize = Format::ThumbLongBranchLink as usize + 1;
+
+impl Format {
+    pub fn arm(opcode: u32) -> Self {
+        match (opcode >> 25) & 0x7 {
+            _ if opcode & 0x0FFF_FFF0 == 0x012F_FF10 => Self::BranchExchange,
+            _ if opcode & 0x0FC0_00F0 == 0x0000_0090 => Self::Multiply,
+            _ if opcode & 0x0F80_00F0 == 0x0080_0090 => Self::MultiplyLong,
+            _ if opcode & 0x0FB0_0FF0 == 0x0100_0090 => Self::Swap,
+            0b000 if opcode & 0x90 == 0x90 => Self::HalfwordTransfer,
+            0b000 | 0b001 if opcode & 0x0190_0000 == 0x0100_0000 => Self::PsrTransfer,
+            0b000 | 0b001 => Self::DataProcessing,
+            0b011 if opcode & 0x10 != 0 => Self::Undefined,
+            0b010 | 0b011 => Self::SingleDataTransfer,
+            0b100 => Self::BlockDataTransfer,
+            0b101 => Self::Branch,
+            0b111 if opcode & (1 << 24) != 0 => Self::SoftwareInterrupt,
+            _ => Self::Coprocessor,
+        }
+    }
+
+    pub fn thumb(opcode: u16) -> Self {
+        match opcode >> 8 {
+            0x18..=0x1F => Self::ThumbAddSub,
+            0x00..=0x17 => Self::ThumbMoveShifted,
+            0x20..=0x3F => Self::ThumbImmediate,
+            0x40..=0x43 => Self::ThumbAlu,
+            0x44..=0x47 => Self::ThumbHiRegBx,
+            0x48..=0x4F => Self::ThumbPcRelativeLoad,
+            0x50..=0x5F if opcode & (1 << 9) == 0 => Self::ThumbLoadStoreReg,
+            0x50..=0x5F => Self::ThumbLoadStoreSignExt,
+            0x60..=0x7F => Self::ThumbLoadStoreImm,
+            0x80..=0x8F => Self::ThumbLoadStoreHalfword,
+            0x90..=0x9F => Self::ThumbSpRelative,
+            0xA0..=0xAF => Self::ThumbLoadAddress,
+            0xB0 => Self::ThumbAddSp,
+            0xB4 | 0xB5 | 0xBC | 0xBD => Self::ThumbPushPop,
+            0xC0..=0xCF => Self::ThumbMultiple,
+            0xDF => Self::ThumbSoftwareInterrupt,
+            0xD0..=0xDE => Self::ThumbCondBranch,
+            0xE0..=0xE7 => Self::ThumbBranch,
+            0xF0..=0xFF => Self::ThumbLongBranchLink,
+            _ => Self::Undefined,
+        }
+    }
+}
+
+/// How often each instruction format was executed, to see what's worth optimizing.
+///
+/// Only instructions that passed their condition are counted.
+#[derive(Clone, Copy, Debug)]
+pub struct InstrProfile {
+    counts: [u64; FORMAT_COUNT],
+}
+
+impl Default for InstrProfile {
+    fn default() -> Self {
+        Self { counts: [0; FORMAT_COUNT] }
+    }
+}
+
+impl InstrProfile {
+    pub fn record(&mut self, format: Format) {
+        self.counts[format as usize] += 1;
+    }
+
+    pub fn count(&self, format: Format) -> u64 {
+        self.counts[format as usize]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Formats that were executed at all, most frequent first.
+    pub fn sorted(&self) -> Vec<(Format, u64)> {
+        let mut formats: Vec<_> = ALL_FORMATS
+            .iter()
+            .map(|&f| (f, self.count(f)))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+
+        formats.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        formats
+    }
+}
+
+impl fmt::Display for InstrProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total().max(1) as f64;
+
+        for (format, count) in self.sorted() {
+            writeln!(f, "{:<24} {count:>14} {:>6.2}%", format!("{format:?}"), count as f64 / total * 100.0)?;
+        }
+
+        Ok(())
+    }
+}
+
+const ALL_FORMATS: [Format; FORMAT_COUNT] = [
+    Format::DataProcessing,
+    Format::PsrTransfer,
+    Format::Multiply,
+    Format::MultiplyLong,
+    Format::Swap,
+    Format::BranchExchange,
+    Format::HalfwordTransfer,
+    Format::SingleDataTransfer,
+    Format::Undefined,
+    Format::BlockDataTransfer,
+    Format::Branch,
+    Format::Coprocessor,
+    Format::SoftwareInterrupt,
+    Format::ThumbMoveShifted,
+    Format::ThumbAddSub,
+    Format::ThumbImmediate,
+    Format::ThumbAlu,
+    Format::ThumbHiRegBx,
+    Format::ThumbPcRelativeLoad,
+    Format::ThumbLoadStoreReg,
+    Format::ThumbLoadStoreSignExt,
+    Format::ThumbLoadStoreImm,
+    Format::ThumbLoadStoreHalfword,
+    Format::ThumbSpRelative,
+    Format::ThumbLoadAddress,
+    Format::ThumbAddSp,
+    Format::ThumbPushPop,
+    Format::ThumbMultiple,
+    Format::ThumbCondBranch,
+    Format::ThumbSoftwareInterrupt,
+    Format::ThumbBranch,
+    Format::ThumbLongBranchLink,
+];
@@ -1,2 +1,7 @@
 pub mod arm7tdmi;
+pub mod bench;
+pub mod hle;
+pub mod instr_profile;
+pub mod single_step;
+pub mod state;
 pub mod thumb;
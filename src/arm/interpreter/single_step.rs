@@ -0,0 +1,134 @@
+use serde::Deserialize;
+
+use crate::{
+    arm::disasm::{disassemble_arm, disassemble_thumb},
+    mmu::sparse_mem::{SparseMem, Transaction},
+};
+
+use super::{
+    arm7tdmi::{Arm7TDMI, Cpsr, State},
+    state::CpuState,
+};
+
+/// One SingleStepTests ARM7TDMI test: a state, one instruction and the expected outcome.
+#[derive(Deserialize)]
+pub struct TestVector {
+    pub initial: CpuState,
+    #[serde(rename = "final")]
+    pub expected: CpuState,
+    pub transactions: Vec<Transaction>,
+    pub opcode: u32,
+}
+
+/// The vectors model the prefetch, their r15 is two instructions ahead.
+fn pipeline_offset(cpsr: u32) -> u32 {
+    match Cpsr(cpsr).state() {
+        State::Arm => 8,
+        State::Thumb => 4,
+    }
+}
+
+/// Registers with their names for comparing two states.
+fn named_regs(state: &CpuState) -> Vec<(String, u32)> {
+    let banks = [
+        ("fiq", &state.r_fiq[..]),
+        ("svc", &state.r_svc[..]),
+        ("abt", &state.r_abt[..]),
+        ("irq", &state.r_irq[..]),
+        ("und", &state.r_und[..]),
+    ];
+
+    let mut regs: Vec<_> = state.r.iter().enumerate().map(|(i, r)| (format!("r{i}"), *r)).collect();
+    for (name, bank) in banks {
+        let first = 15 - bank.len();
+        regs.extend(bank.iter().enumerate().map(|(i, r)| (format!("r{}_{name}", first + i), *r)));
+    }
+    regs.push(("cpsr".to_string(), state.cpsr));
+    regs.extend(state.spsr.iter().zip(banks).map(|(s, (name, _))| (format!("spsr_{name}"), *s)));
+
+    regs
+}
+
+impl TestVector {
+    fn disassemble(&self) -> String {
+        let pc = self.initial.r[15].wrapping_sub(pipeline_offset(self.initial.cpsr));
+
+        match Cpsr(self.initial.cpsr).state() {
+            State::Arm => disassemble_arm(self.opcode, pc),
+            State::Thumb => disassemble_thumb(self.opcode as u16, pc),
+        }
+    }
+
+    /// Execute the instruction on a sparse memory seeded with all reads of the vector
+    /// and compare the registers and writes. Fetch timing and cycles are not compared.
+    pub fn run(&self) -> Result<(), String> {
+        let mut initial = self.initial;
+        initial.r[15] = initial.r[15].wrapping_sub(pipeline_offset(initial.cpsr));
+
+        let mut cpu = Arm7TDMI::with_bus(SparseMem::default());
+        for t in self.transactions.iter().filter(|t| t.kind != 2) {
+            cpu.bus.poke(t.addr, t.data, t.size);
+        }
+        let opcode_size = match Cpsr(initial.cpsr).state() {
+            State::Arm => 4,
+            State::Thumb => 2,
+        };
+        cpu.bus.poke(initial.r[15], self.opcode, opcode_size);
+        cpu.load_state(&initial);
+
+        cpu.cycle();
+
+        let mut state = cpu.save_state();
+        state.r[15] = state.r[15].wrapping_add(pipeline_offset(state.cpsr));
+
+        let mut errors: Vec<_> = named_regs(&self.expected)
+            .into_iter()
+            .zip(named_regs(&state))
+            .filter(|((_, expected), (_, got))| expected != got)
+            .map(|((name, expected), (_, got))| format!("{name}: expected {expected:08X}, got {got:08X}"))
+            .collect();
+
+        let expected_writes: Vec<_> = self.transactions.iter().filter(|t| t.kind == 2).collect();
+        let writes: Vec<_> = cpu.bus.transactions.iter().filter(|t| t.kind == 2).collect();
+        if expected_writes != writes {
+            errors.push(format!("writes: expected {expected_writes:X?}, got {writes:X?}"));
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(format!("opcode {:08X} ({})\n  {}", self.opcode, self.disassemble(), errors.join("\n  "))),
+        }
+    }
+}
+
+/// Run all vectors of a JSON file, print the failures and return `(passed, total)`.
+pub fn run_file(path: &str) -> Result<(usize, usize), String> {
+    let json = std::fs::read_to_string(path).map_err(|e| format!("Could not read {path}: {e}"))?;
+    let vectors: Vec<TestVector> = serde_json::from_str(&json).map_err(|e| format!("Invalid test file {path}: {e}"))?;
+
+    let mut passed = 0;
+    for (i, vector) in vectors.iter().enumerate() {
+        match vector.run() {
+            Ok(()) => passed += 1,
+            Err(e) => println!("#{i} failed, {e}"),
+        }
+    }
+
+    Ok((passed, vectors.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A few hand-checked vectors in the SingleStepTests format, ARM and THUMB.
+    const VECTORS: &str = include_str!("single_step_vectors.json");
+
+    #[test]
+    fn embedded_vectors() {
+        let vectors: Vec<TestVector> = serde_json::from_str(VECTORS).unwrap();
+
+        let failures: Vec<_> = vectors.iter().filter_map(|vector| vector.run().err()).collect();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
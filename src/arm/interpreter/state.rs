@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mmu::Mcu;
+
+use super::arm7tdmi::{Arm7TDMI, Cpsr, Mode};
+
+/// Snapshot of all CPU registers, laid out like the SingleStepTests ARM7TDMI vectors.
+///
+/// `r` holds the User/System registers, the mode specific banks only hold what the mode
+/// actually banks (r8-r14 for FIQ, r13-r14 otherwise). `spsr` is ordered FIQ, SVC, ABT, IRQ, UND.
+/// Unlike the vectors, `r[15]` is the address of the next instruction to execute.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+pub struct CpuState {
+    #[serde(rename = "R")]
+    pub r: [u32; 16],
+    #[serde(rename = "R_fiq")]
+    pub r_fiq: [u32; 7],
+    #[serde(rename = "R_svc")]
+    pub r_svc: [u32; 2],
+    #[serde(rename = "R_abt")]
+    pub r_abt: [u32; 2],
+    #[serde(rename = "R_irq")]
+    pub r_irq: [u32; 2],
+    #[serde(rename = "R_und")]
+    pub r_und: [u32; 2],
+    #[serde(rename = "CPSR")]
+    pub cpsr: u32,
+    #[serde(rename = "SPSR")]
+    pub spsr: [u32; 5],
+}
+
+const SPSR_MODES: [Mode; 5] = [Mode::Fiq, Mode::Supervisor, Mode::Abort, Mode::Irq, Mode::Undefined];
+
+impl<M: Mcu> Arm7TDMI<M> {
+    pub fn save_state(&self) -> CpuState {
+        let mode = self.cpsr.mode().unwrap_or(Mode::System);
+        let mut banks = self.banked_regs;
+        let mut r = self.regs;
+
+        // Flush the live registers into the bank of the current mode.
+        banks[mode].spsr = self.spsr;
+        banks[mode].bank[5] = self.regs[13];
+        banks[mode].bank[6] = self.regs[14];
+
+        if mode == Mode::Fiq {
+            banks.fiq_regs.bank[0..5].copy_from_slice(&self.regs[8..=12]);
+            r[8..=12].copy_from_slice(&banks.sys_regs.bank[0..5]);
+        }
+
+        r[13] = banks.sys_regs.bank[5];
+        r[14] = banks.sys_regs.bank[6];
+
+        CpuState {
+            r,
+            r_fiq: banks.fiq_regs.bank,
+            r_svc: [banks.svc_regs.bank[5], banks.svc_regs.bank[6]],
+            r_abt: [banks.abt_regs.bank[5], banks.abt_regs.bank[6]],
+            r_irq: [banks.irq_regs.bank[5], banks.irq_regs.bank[6]],
+            r_und: [banks.und_regs.bank[5], banks.und_regs.bank[6]],
+            cpsr: self.cpsr.0,
+            spsr: SPSR_MODES.map(|m| banks[m].spsr.0),
+        }
+    }
+
+    pub fn load_state(&mut self, state: &CpuState) {
+        let banks = &mut self.banked_regs;
+
+        banks.sys_regs.bank[0..5].copy_from_slice(&state.r[8..=12]);
+        banks.sys_regs.bank[5..7].copy_from_slice(&state.r[13..=14]);
+        banks.fiq_regs.bank = state.r_fiq;
+
+        for (mode, regs) in [
+            (Mode::Supervisor, state.r_svc),
+            (Mode::Abort, state.r_abt),
+            (Mode::Irq, state.r_irq),
+            (Mode::Undefined, state.r_und),
+        ] {
+            banks[mode].bank[5..7].copy_from_slice(&regs);
+        }
+
+        for (mode, spsr) in SPSR_MODES.into_iter().zip(state.spsr) {
+            banks[mode].spsr = Cpsr(spsr);
+        }
+
+        self.regs = state.r;
+        self.cpsr = Cpsr(state.cpsr);
+
+        // Bring in the registers of the current mode like a switch from System would.
+        let mode = self.cpsr.mode().unwrap_or(Mode::System);
+        self.spsr = match mode {
+            Mode::System | Mode::User => self.cpsr,
+            _ => self.banked_regs[mode].spsr,
+        };
+
+        if mode == Mode::Fiq {
+            self.regs[8..=12].copy_from_slice(&self.banked_regs.fiq_regs.bank[0..5]);
+        }
+
+        self.regs[13] = self.banked_regs[mode].bank[5];
+        self.regs[14] = self.banked_regs[mode].bank[6];
+    }
+}
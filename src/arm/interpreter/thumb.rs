@@ -1,9 +1,9 @@
 use crate::{fl, mmu::Mcu};
 
-use super::arm7tdmi::{Arm7TDMI, State};
+use super::arm7tdmi::{mul_cycles, Arm7TDMI, Mode, State};
 
 /// Thumb instructions live in this impl block.
-impl Arm7TDMI {
+impl<M: Mcu> Arm7TDMI<M> {
     /// Format 1: move shifted register.
     pub fn mov_shifted_reg(&mut self, opcode: u16) {
         let rd = opcode as usize & 0x7;
@@ -36,7 +36,7 @@ impl Arm7TDMI {
     }
 
     /// Format 2: add/substract.
-    pub fn add_sub<const I: bool>(&mut self, opcode: u16) {
+    pub fn add_sub<const I: bool, const SUB: bool>(&mut self, opcode: u16) {
         let rd = opcode as usize & 0x7;
         let rs = (opcode as usize >> 3) & 0x7;
 
@@ -46,10 +46,9 @@ impl Arm7TDMI {
             self.regs[(opcode as usize >> 6) & 0x7]
         };
 
-        self.regs[rd] = match (opcode >> 9) & 1 {
-            0 => fl!(self.regs[rs], offset, +, self, cpsr),
-            1 => fl!(self.regs[rs], offset, -, self, cpsr),
-            _ => unreachable!(),
+        self.regs[rd] = match SUB {
+            false => fl!(self.regs[rs], offset, +, self, cpsr),
+            true => fl!(self.regs[rs], offset, -, self, cpsr),
         };
 
         self.cpsr.set_z(self.regs[rd] == 0);
@@ -84,45 +83,55 @@ impl Arm7TDMI {
     }
 
     /// Format 4: ALU operations.
-    pub fn alu_ops(&mut self, opcode: u16) {
+    pub fn alu_ops<const OP: u8>(&mut self, opcode: u16) {
         let rd = opcode as usize & 0x7;
         let rs = (opcode as usize >> 3) & 0x7;
 
         // If intermediate: TST, CMP, CMN.
         let mut intmd = false;
 
+        // Shifts by register take an internal cycle, MUL depends on the multiplier (Rd).
+        self.cycles_taken += match OP {
+            0b0010 | 0b0011 | 0b0100 | 0b0111 => 1,
+            0b1101 => mul_cycles(self.regs[rd], true),
+            _ => 0,
+        };
+
+        // Shifts by register only use the low byte of Rs, like the ARM barrel shifter.
+        let amount = self.regs[rs] & 0xFF;
+
         #[rustfmt::skip]
-        let res = match (opcode >> 6) & 0xF {
+        let res = match OP {
             0b0000 => self.regs[rd] & self.regs[rs],
             0b0001 => self.regs[rd] ^ self.regs[rs],
             0b0010 => {
-                let (res, carry) = self.lsl(self.regs[rd], self.regs[rs], true);
+                let (res, carry) = self.lsl(self.regs[rd], amount, true);
                 self.cpsr.set_c(carry);
                 res
             }
             0b0011 => {
-                let (res, carry) = self.lsr(self.regs[rd], self.regs[rs], true);
+                let (res, carry) = self.lsr(self.regs[rd], amount, true);
                 self.cpsr.set_c(carry);
                 res
             }
             0b0100 => {
-                let (res, carry) = self.asr(self.regs[rd], self.regs[rs], true);
+                let (res, carry) = self.asr(self.regs[rd], amount, true);
                 self.cpsr.set_c(carry);
                 res
             }
-            0b0101 => fl!(self.regs[rd], self.regs[rs] + self.cpsr.c() as u32, +, self, cpsr),
+            0b0101 => fl!(self.regs[rd], self.regs[rs], self.cpsr.c() as u32, +, self, cpsr),
             0b0110 => fl!(self.regs[rd], self.regs[rs], !self.cpsr.c() as u32, -, self, cpsr),
             0b0111 => {
-                let (res, carry) = self.ror(self.regs[rd], self.regs[rs], true);
+                let (res, carry) = self.ror(self.regs[rd], amount, true);
                 self.cpsr.set_c(carry);
                 res
             },
             0b1000 => { intmd = true; self.regs[rd] & self.regs[rs] },
-            0b1001 => fl!(0, self.regs[rs], -, self, cpsr),
+            0b1001 => fl!(0u32, self.regs[rs], -, self, cpsr),
             0b1010 => { intmd = true; fl!(self.regs[rd], self.regs[rs], -, self, cpsr) },
             0b1011 => { intmd = true; fl!(self.regs[rd], self.regs[rs], +, self, cpsr) },
             0b1100 => self.regs[rd] | self.regs[rs],
-            0b1101 => self.regs[rd] * self.regs[rs],
+            0b1101 => self.regs[rd].wrapping_mul(self.regs[rs]),
             0b1110 => self.regs[rd] & !self.regs[rs],
             0b1111 => !self.regs[rs],
             _ => unreachable!(),
@@ -138,19 +147,14 @@ impl Arm7TDMI {
 
     /// Format 5: Hi reg ops/bx
     #[rustfmt::skip]
-    pub fn hi_reg_op_bx(&mut self, opcode: u16) {
+    pub fn hi_reg_op_bx<const OP: u8, const H1: bool, const H2: bool>(&mut self, opcode: u16) {
         let rd = opcode as usize & 0x7;
         let rs = (opcode as usize >> 3) & 0x7;
-        let op = (opcode >> 8) & 0x3;
-
-        // No const generic if decoding with 8 bits :(
-        let h1 = opcode & (1 << 7) != 0;
-        let h2 = opcode & (1 << 6) != 0;
 
         // Branch exchange.
-        if op == 0b11 {
-            let mut addr = if !h2 { self.regs[rs] } else { self.regs[rs + 8] };
-            addr += ((rs + 8) == 15 && h2) as u32 * 4;
+        if OP == 0b11 {
+            let mut addr = if !H2 { self.regs[rs] } else { self.regs[rs + 8] };
+            addr = addr.wrapping_add(((rs + 8) == 15 && H2) as u32 * 4);
 
             // Bit 0 of Rn decides decoding of subsequent instructions.
             if addr & 1 == 0 {
@@ -165,18 +169,18 @@ impl Arm7TDMI {
             return;
         }
 
-        let dst = if !h1 { rd } else { rd + 8 };
-        let src = if !h2 { rs } else { rs + 8 };
+        let dst = if !H1 { rd } else { rd + 8 };
+        let src = if !H2 { rs } else { rs + 8 };
         let pc = if src == 15 { 4 } else { 0 };
 
-        self.regs[dst] = match op {
+        self.regs[dst] = match OP {
             0b00 if dst == 15 => {
                 self.branch = true;
-                (self.regs[dst] + self.regs[src] + pc + 4) & !1
+                self.regs[dst].wrapping_add(self.regs[src]).wrapping_add(pc + 4) & !1
             },
-            0b00 if dst != 15 => self.regs[dst] + self.regs[src] + pc,
+            0b00 if dst != 15 => self.regs[dst].wrapping_add(self.regs[src]).wrapping_add(pc),
             0b01 => {
-                let res = fl!(self.regs[dst], self.regs[src] + pc, -, self, cpsr);
+                let res = fl!(self.regs[dst], self.regs[src].wrapping_add(pc), -, self, cpsr);
 
                 self.cpsr.set_z(res == 0);
                 self.cpsr.set_n((res & (1 << 31)) != 0);
@@ -185,10 +189,10 @@ impl Arm7TDMI {
             },
             0b10 if dst == 15 => {
                 self.branch = true;
-                (self.regs[src] + pc) & !1
+                self.regs[src].wrapping_add(pc) & !1
             },
-            0b10 if src == 15 => (self.regs[src] + pc) & !1,
-            0b10 => self.regs[src] + pc,
+            0b10 if src == 15 => self.regs[src].wrapping_add(pc) & !1,
+            0b10 => self.regs[src].wrapping_add(pc),
             _ => unreachable!(),
         };
     }
@@ -198,7 +202,10 @@ impl Arm7TDMI {
         let offset = (opcode as u8 as u32) << 2;
         let rd = (opcode as usize >> 8) & 0x7;
 
-        let address = ((self.regs[15] + 4) & !2) + offset;
+        let address = (self.regs[15].wrapping_add(4) & !2).wrapping_add(offset);
+        // 1S + 1N + 1I.
+        self.cycles_taken += 2;
+
         let (aligned_addr, ror) = if address % 4 != 0 {
             (address & !3, (address & 3) * 8)
         } else {
@@ -214,13 +221,16 @@ impl Arm7TDMI {
         let rb = (opcode as usize >> 3) & 0x7;
         let ro = (opcode as usize >> 6) & 0x7;
 
-        let address = self.regs[rb] + self.regs[ro];
+        let address = self.regs[rb].wrapping_add(self.regs[ro]);
         let (aligned_addr, ror) = if !B && address % 4 != 0 {
             (address & !3, (address & 3) * 8)
         } else {
             (address, 0)
         };
 
+        // Loads are 1S + 1N + 1I, stores 2N.
+        self.cycles_taken += if L { 2 } else { 1 };
+
         if L {
             self.regs[rd] = if B {
                 self.bus.read8(address) as u32
@@ -241,13 +251,16 @@ impl Arm7TDMI {
         let rb = (opcode as usize >> 3) & 0x7;
         let ro = (opcode as usize >> 6) & 0x7;
 
-        let address = self.regs[rb] + self.regs[ro];
+        let address = self.regs[rb].wrapping_add(self.regs[ro]);
         let (aligned_addr, ror) = if address % 2 != 0 {
             (address & !1, 8)
         } else {
             (address, 0)
         };
 
+        // STRH is 2N, the loads 1S + 1N + 1I.
+        self.cycles_taken += if !S && !H { 1 } else { 2 };
+
         match (S, H) {
             (false, false) => self.bus.write16(aligned_addr, self.regs[rd] as u16),
             (false, true) => {
@@ -267,13 +280,16 @@ impl Arm7TDMI {
         let rb = (opcode as usize >> 3) & 0x7;
         let offset = (opcode as u32 >> 6) & 0x1F;
 
-        let address = self.regs[rb] + (offset << if B { 0 } else { 2 });
+        let address = self.regs[rb].wrapping_add(offset << if B { 0 } else { 2 });
         let (aligned_addr, ror) = if !B && address % 4 != 0 {
             (address & !3, (address & 3) * 8)
         } else {
             (address, 0)
         };
 
+        // Loads are 1S + 1N + 1I, stores 2N.
+        self.cycles_taken += if L { 2 } else { 1 };
+
         if L {
             self.regs[rd] = if B {
                 self.bus.read8(address) as u32
@@ -294,13 +310,15 @@ impl Arm7TDMI {
         let rb = (opcode as usize >> 3) & 0x7;
         let offset = (opcode as u32 >> 6) & 0x1F;
 
-        let address = self.regs[rb] + (offset << 1);
+        let address = self.regs[rb].wrapping_add(offset << 1);
         let (aligned_addr, ror) = if address % 2 != 0 {
             (address & !1, 8)
         } else {
             (address, 0)
         };
 
+        self.cycles_taken += if L { 2 } else { 1 };
+
         if L {
             self.regs[rd] = (self.bus.read16(aligned_addr) as u32).rotate_right(ror);
         } else {
@@ -313,13 +331,15 @@ impl Arm7TDMI {
         let offset = opcode as u8 as u32;
         let rd = (opcode as usize >> 8) & 0x7;
 
-        let addr = self.regs[13] + (offset << 2);
+        let addr = self.regs[13].wrapping_add(offset << 2);
         let (aligned_addr, ror) = if addr % 4 != 0 {
             (addr & !3, (addr & 3) * 8)
         } else {
             (addr, 0)
         };
 
+        self.cycles_taken += if L { 2 } else { 1 };
+
         if L {
             self.regs[rd] = self.bus.read32(aligned_addr).rotate_right(ror);
         } else {
@@ -333,22 +353,19 @@ impl Arm7TDMI {
         let rd = (opcode as usize >> 8) & 0x7;
 
         self.regs[rd] = match SP {
-            false => ((self.regs[15] + 4) & !2) + (offset << 2),
-            true => self.regs[13] + (offset << 2),
+            false => (self.regs[15].wrapping_add(4) & !2).wrapping_add(offset << 2),
+            true => self.regs[13].wrapping_add(offset << 2),
         };
     }
 
     /// Format 13: add offset to SP.
-    /// 
-    /// No const generic with current 8bit thumb decoding :(
-    pub fn add_sp(&mut self, opcode: u16) {
+    pub fn add_sp<const S: bool>(&mut self, opcode: u16) {
         let offset = (opcode & 0x7F) as u32;
-        let sign = opcode & (1 << 7) != 0;
 
-        if sign {
-            self.regs[13] -= offset << 2;
+        if S {
+            self.regs[13] = self.regs[13].wrapping_sub(offset << 2);
         } else {
-            self.regs[13] += offset << 2;
+            self.regs[13] = self.regs[13].wrapping_add(offset << 2);
         }
     }
 
@@ -363,17 +380,21 @@ impl Arm7TDMI {
             reg_list.reverse()
         }
 
+        // Like LDM/STM, nS + 1N + 1I or (n - 1)S + 2N with LR/PC counted in.
+        let n = reg_list.len() as u32 + R as u32;
+        self.cycles_taken += if L { n + 1 } else { n };
+
         if R && !L {
-            address -= 4;
+            address = address.wrapping_sub(4);
             self.bus.write32(address, self.regs[14])
         }
 
         for r in &reg_list {
             if L {
                 self.regs[*r] = self.bus.read32(address);
-                address += 4;
+                address = address.wrapping_add(4);
             } else {
-                address -= 4;
+                address = address.wrapping_sub(4);
                 self.bus.write32(address, self.regs[*r]);
             }
         }
@@ -381,7 +402,7 @@ impl Arm7TDMI {
         if R && L {
             self.regs[15] = self.bus.read32(address) & !1;
             self.branch = true;
-            address += 4;
+            address = address.wrapping_add(4);
         }
 
         self.regs[13] = address;
@@ -397,6 +418,10 @@ impl Arm7TDMI {
         let rb = (opcode as usize >> 8) & 0x7;
         let mut address = self.regs[rb];
 
+        // nS + 1N + 1I or (n - 1)S + 2N, an empty list transfers r15 only.
+        let n = reg_list.len().max(1) as u32;
+        self.cycles_taken += if L { n + 1 } else { n };
+
         // Force align address but not directly modify it -- writeback is not aligned.
         let aligned_addr = |address: u32| { if address % 4 != 0 { address & !3 } else { address } };
 
@@ -406,10 +431,10 @@ impl Arm7TDMI {
                 self.regs[15] = self.bus.read32(aligned_addr(address)) & !1;
                 self.branch = true;
             } else {
-                self.bus.write32(aligned_addr(address), (self.regs[15] + 6) & !1);
+                self.bus.write32(aligned_addr(address), (self.regs[15].wrapping_add(6)) & !1);
             }
 
-            self.regs[rb] += 0x40;
+            self.regs[rb] = self.regs[rb].wrapping_add(0x40);
             return;
         }
 
@@ -419,13 +444,13 @@ impl Arm7TDMI {
             } else {
                 // Edge case: rb in reg list and not first.
                 if *r == rb && reg_list[0] != *r {
-                    self.bus.write32(aligned_addr(address), self.regs[rb] + (reg_list.len() as u32 * 4));
+                    self.bus.write32(aligned_addr(address), self.regs[rb].wrapping_add(reg_list.len() as u32 * 4));
                 } else {
                     self.bus.write32(aligned_addr(address), self.regs[*r]);
                 }
             }
 
-            address += 4
+            address = address.wrapping_add(4);
         }
 
         // Writeback if rb not in reg list.
@@ -435,7 +460,7 @@ impl Arm7TDMI {
     }
 
     /// Format 16: conditional branch.
-    pub fn cond_branch(&mut self, opcode: u16) {
+    pub fn cond_branch<const COND: u8>(&mut self, opcode: u16) {
         let signed_offset = (opcode as u32) & 0xFF;
         let signed_offset = if signed_offset & 0x80 != 0 {
             (signed_offset | 0xFFFF_FF00) as i32
@@ -443,8 +468,8 @@ impl Arm7TDMI {
             signed_offset as i32
         };
 
-        if self.cond((opcode >> 8) as u8 & 0xF) {
-            self.regs[15] = (self.regs[15] + 4).wrapping_add_signed(signed_offset << 1);
+        if self.cond(COND) {
+            self.regs[15] = (self.regs[15].wrapping_add(4)).wrapping_add_signed(signed_offset << 1);
             self.regs[15] &= !1;
 
             self.branch = true;
@@ -459,7 +484,7 @@ impl Arm7TDMI {
     /// Format 18: unconditional branch.
     pub fn branch(&mut self, opcode: u16) {
         let signed_offset = ((opcode as u32 & 0x7FF) << 21) as i32 >> 21;
-        self.regs[15] = (self.regs[15] + 4).wrapping_add_signed(signed_offset << 1);
+        self.regs[15] = (self.regs[15].wrapping_add(4)).wrapping_add_signed(signed_offset << 1);
         self.regs[15] &= !1;
 
         self.branch = true;
@@ -469,22 +494,25 @@ impl Arm7TDMI {
     pub fn long_branch<const H: bool>(&mut self, opcode: u16) {
         let offset = opcode & 0x7FF;
 
+        // The first half leaves the upper target in r14 of the current mode. An IRQ taken
+        // between the halves only swaps in r14_irq and banks it back on return, so it survives.
         if !H {
             // Sign extend top half, shift by 12 offset bcs of prev shift.
             let s_off = (((offset as u32) << 21) as i32 >> 21) << 12;
-            self.regs[14] = (self.regs[15] + 4).wrapping_add_signed(s_off);
+            self.regs[14] = (self.regs[15].wrapping_add(4)).wrapping_add_signed(s_off);
         } else {
-            let addr = self.regs[14] + ((offset << 1) as u32);
+            let addr = self.regs[14].wrapping_add((offset << 1) as u32);
 
-            self.regs[14] = (self.regs[15] + 2) | 1;
+            self.regs[14] = (self.regs[15].wrapping_add(2)) | 1;
             self.regs[15] = addr & !1;
 
             self.branch = true;
         }
     }
 
-    /// Dummy for Thumb LUT.
+    /// Undefined THUMB instructions trap to the undefined vector like ARM ones.
     pub fn t_undefined(&mut self, _opcode: u16) {
-        panic!("shouldn't be called!")
+        self.exception(Mode::Undefined, 0x04, self.regs[15].wrapping_add(2));
+        self.branch = true;
     }
 }
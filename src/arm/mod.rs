@@ -1,3 +1,4 @@
+pub mod disasm;
 pub mod interpreter;
 
 /// Fill array with `N` default values besides index `i` which gets `val`.
@@ -9,11 +10,13 @@ pub fn arr_with<const N: usize, T: Copy + Default>(i: usize, val: T) -> [T; N] {
 
 /// Set V (overflow) and C (carry) flag and save repetition.
 ///
-/// - Set C to carry out of bit31 in ALU.
+/// - Set C to carry out of bit31 in ALU, for subtractions that is "no borrow".
 /// - Set (signed) overflow -- check sign bits of operands and result.
+///
+/// Results wrap like on hardware, no arm can panic on overflow.
 #[macro_export]
 macro_rules! fl {
-    // ADD, ADC, CMN
+    // ADD, CMN
     ($a:expr, $b:expr, +, $self:ident, $cpsr:ident $(, $S:expr)?) => {{
         let (res, ov) = $a.overflowing_add($b);
         let set_flags = true $(&& $S)?;
@@ -30,7 +33,7 @@ macro_rules! fl {
 
     // SUB, RSB, CMP
     ($a:expr, $b:expr, -, $self:ident, $cpsr:ident $(, $S:expr)?) => {{
-        let res = $a - $b;
+        let res = $a.wrapping_sub($b);
         let set_flags = true $(&& $S)?;
 
         if set_flags {
@@ -41,13 +44,28 @@ macro_rules! fl {
         res
     }};
 
-    // SBC, RSC
+    // ADC, `$c` is the carry in. Added on 64 bits, bit 32 is the carry out.
+    ($a:expr, $b:expr, $c:expr, +, $self:ident, $cpsr:ident $(, $S:expr)?) => {{
+        let wide = $a as u64 + $b as u64 + $c as u64;
+        let res = wide as u32;
+        let set_flags = true $(&& $S)?;
+
+        if set_flags {
+            $self.$cpsr.set_c(wide >> 32 != 0);
+            $self.$cpsr.set_v((!($a ^ $b) & ($a ^ res)) >> 31 != 0);
+        }
+
+        res
+    }};
+
+    // SBC, RSC, `$c` is the borrow (NOT carry). Subtracted on 64 bits, a borrow wraps into bit 32.
     ($a:expr, $b:expr, $c:expr, -, $self:ident, $cpsr:ident $(, $S:expr)?) => {{
-        let res = $a - ($b + $c);
+        let wide = ($a as u64).wrapping_sub($b as u64).wrapping_sub($c as u64);
+        let res = wide as u32;
         let set_flags = true $(&& $S)?;
 
         if set_flags {
-            $self.$cpsr.set_c($a >= ($b + $c));
+            $self.$cpsr.set_c(wide >> 32 == 0);
             $self.$cpsr.set_v(((($a ^ $b) & ($a ^ res)) >> 31) != 0);
         }
 
@@ -0,0 +1,254 @@
+use std::path::PathBuf;
+
+use crate::{
+    arm::interpreter::arm7tdmi::Mode,
+    mmu::{game_pak::BackupType, io_log::IoFilter},
+    SdlResult,
+};
+
+/// Command line options, `kba <rom> [options]`.
+#[derive(Default)]
+pub struct Args {
+    pub rom_path: String,
+
+    /// Developer option: skip BIOS and ROM entry and start executing here.
+    pub boot_pc: Option<u32>,
+    /// CPU mode to use with `boot_pc`, System if not specified.
+    pub boot_mode: Option<Mode>,
+
+    /// Log every IO register access to this file.
+    pub log_io: Option<String>,
+    /// Restrict the IO log to some registers or address ranges.
+    pub log_io_filter: IoFilter,
+
+    /// Run the SingleStepTests CPU vectors of this JSON file instead of a rom.
+    pub single_step: Option<String>,
+
+    /// KEYINPUT mask of the buttons that auto-fire while held.
+    pub turbo: u16,
+    /// Auto-fire presses per second.
+    pub turbo_rate: u32,
+
+    /// Blend factor of the previous frame for LCD ghosting, off if not set.
+    pub ghosting: Option<f32>,
+
+    /// Write every frame as raw RGBA to this file.
+    pub record_video: Option<String>,
+    /// Write the mixer output as a WAV file, before any output settings.
+    pub dump_wav: Option<String>,
+    /// Write the audio as it is played to this WAV file.
+    pub record_audio: Option<String>,
+
+    /// Render affine backgrounds and sprites at this multiple of the native resolution.
+    pub internal_scale: usize,
+    /// Average the `internal_scale` samples back down to the native resolution.
+    pub supersample: bool,
+
+    /// Don't open an audio device, the emulation then runs unpaced.
+    pub mute: bool,
+    /// Average both sides of the audio output.
+    pub mono: bool,
+    /// Master volume of the audio output in percent.
+    pub volume: u8,
+
+    /// Seed for power-on noise in palette RAM, VRAM and OAM, zero-filled if not set.
+    pub power_on_fill: Option<u64>,
+
+    /// Force a save type, over the game database and detection.
+    pub save_type: Option<BackupType>,
+
+    /// IPS or UPS patch to apply to the rom, `<rom>.ips`/`.ups` is used if not set.
+    pub patch: Option<PathBuf>,
+    /// Apply UPS patches despite checksum mismatches.
+    pub force_patch: bool,
+
+    /// Count memory accesses per region and page, printed on exit.
+    pub access_stats: bool,
+
+    /// Load the rom into EWRAM as a multiboot image, detected by name if not set.
+    pub multiboot: bool,
+
+    /// Emulate the supported BIOS calls instead of running the BIOS, implies `skip_logo_check`.
+    pub hle_bios: bool,
+
+    /// Start at the ROM entry point without the BIOS intro and its Nintendo logo check.
+    pub skip_logo_check: bool,
+
+    /// Count executed instructions per format, printed on exit.
+    pub instr_profile: bool,
+
+    /// Time this many instructions of a synthetic loop on the interpreter instead of running a rom.
+    pub bench_dispatch: Option<u64>,
+    /// Time this many memory reads through the page table and the full memory map instead of running a rom.
+    pub bench_bus: Option<u64>,
+
+    /// Report the CPU looping in place without memory writes or IRQs for this many frames.
+    pub hang_frames: Option<u32>,
+
+    /// Run the bus, PPU and interpreter fuzz targets this many times instead of running a rom.
+    pub fuzz: Option<u32>,
+    /// Seed of the fuzz corpus, the same seed always generates the same inputs.
+    pub fuzz_seed: u64,
+}
+
+impl Args {
+    pub fn parse() -> SdlResult<Self> {
+        let mut args = Args {
+            turbo_rate: 30,
+            volume: 100,
+            internal_scale: 1,
+            fuzz_seed: 1,
+            ..Default::default()
+        };
+        let mut iter = std::env::args().skip(1);
+        // `--supersample` sets the scale itself, it can't be combined with `--internal-scale`.
+        let mut scale_option = None;
+
+        while let Some(arg) = iter.next() {
+            let mut value = || iter.next().ok_or(format!("Missing value for {arg}"));
+
+            match arg.as_str() {
+                "--boot-pc" => args.boot_pc = Some(parse_u32(&value()?)?),
+                "--boot-mode" => args.boot_mode = Some(parse_mode(&value()?)?),
+                "--log-io" => args.log_io = Some(value()?),
+                "--log-io-filter" => args.log_io_filter = parse_io_filter(&value()?)?,
+                "--single-step" => args.single_step = Some(value()?),
+                "--turbo" => args.turbo = parse_buttons(&value()?)?,
+                "--turbo-rate" => args.turbo_rate = parse_u32(&value()?)?,
+                "--ghosting" => args.ghosting = Some(parse_factor(&value()?)?),
+                "--record-video" => args.record_video = Some(value()?),
+                "--dump-wav" => args.dump_wav = Some(value()?),
+                "--record-audio" => args.record_audio = Some(value()?),
+                "--internal-scale" | "--supersample" => {
+                    if let Some(other) = scale_option.replace(arg.clone()) {
+                        return Err(format!("{arg} can't be combined with {other}"));
+                    }
+
+                    args.internal_scale = parse_scale(&value()?)?;
+                    args.supersample = arg == "--supersample";
+                }
+                "--mute" => args.mute = true,
+                "--mono" => args.mono = true,
+                "--volume" => args.volume = parse_volume(&value()?)?,
+                "--power-on-fill" => args.power_on_fill = Some(parse_u32(&value()?)? as u64),
+                "--save-type" => args.save_type = Some(parse_save_type(&value()?)?),
+                "--patch" => args.patch = Some(PathBuf::from(value()?)),
+                "--force-patch" => args.force_patch = true,
+                "--access-stats" => args.access_stats = true,
+                "--multiboot" => args.multiboot = true,
+                "--hle-bios" => args.hle_bios = true,
+                "--skip-logo-check" => args.skip_logo_check = true,
+                "--instr-profile" => args.instr_profile = true,
+                "--bench-dispatch" => args.bench_dispatch = Some(parse_u32(&value()?)? as u64),
+                "--bench-bus" => args.bench_bus = Some(parse_u32(&value()?)? as u64),
+                "--hang-frames" => args.hang_frames = Some(parse_u32(&value()?)?),
+                "--fuzz" => args.fuzz = Some(parse_u32(&value()?)?),
+                "--fuzz-seed" => args.fuzz_seed = parse_u32(&value()?)? as u64,
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {arg}")),
+                _ => args.rom_path = arg,
+            }
+        }
+
+        if args.rom_path.is_empty()
+            && args.single_step.is_none()
+            && args.bench_dispatch.is_none()
+            && args.bench_bus.is_none()
+            && args.fuzz.is_none()
+        {
+            return Err("A rom has to be specified!".to_string());
+        }
+
+        Ok(args)
+    }
+}
+
+/// Parse decimal or `0x` prefixed hexadecimal numbers.
+pub fn parse_u32(value: &str) -> SdlResult<u32> {
+    match value.strip_prefix("0x").or(value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|e| format!("Invalid number {value}: {e}"))
+}
+
+/// Comma separated register names and `start-end` address ranges, e.g. `DISPCNT,0x04000100-0x0400010F`.
+fn parse_io_filter(value: &str) -> SdlResult<IoFilter> {
+    let mut filter = IoFilter::default();
+
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        match entry.split_once('-') {
+            Some((start, end)) => filter.ranges.push(parse_u32(start)?..=parse_u32(end)?),
+            None => filter.names.push(entry.to_string()),
+        }
+    }
+
+    Ok(filter)
+}
+
+fn parse_scale(value: &str) -> SdlResult<usize> {
+    match parse_u32(value)? {
+        scale @ 1..=4 => Ok(scale as usize),
+        _ => Err(format!("Invalid internal scale {value}, expected 1 - 4")),
+    }
+}
+
+fn parse_volume(value: &str) -> SdlResult<u8> {
+    match parse_u32(value)? {
+        volume @ 0..=100 => Ok(volume as u8),
+        _ => Err(format!("Invalid volume {value}, expected 0 - 100")),
+    }
+}
+
+/// Blend factor between 0 and 1.
+fn parse_factor(value: &str) -> SdlResult<f32> {
+    match value.parse::<f32>() {
+        Ok(factor) if (0.0..=1.0).contains(&factor) => Ok(factor),
+        _ => Err(format!("Invalid factor {value}, expected 0.0 - 1.0")),
+    }
+}
+
+/// Comma separated button names to their KEYINPUT mask.
+fn parse_buttons(value: &str) -> SdlResult<u16> {
+    value.split(',').map(str::trim).try_fold(0, |mask, button| {
+        let bit = match button.to_lowercase().as_str() {
+            "a" => 0,
+            "b" => 1,
+            "select" => 2,
+            "start" => 3,
+            "right" => 4,
+            "left" => 5,
+            "up" => 6,
+            "down" => 7,
+            "r" => 8,
+            "l" => 9,
+            _ => return Err(format!("Unknown button {button}")),
+        };
+
+        Ok(mask | (1 << bit))
+    })
+}
+
+fn parse_save_type(value: &str) -> SdlResult<BackupType> {
+    match value.to_lowercase().as_str() {
+        "none" => Ok(BackupType::None),
+        "sram" => Ok(BackupType::Sram),
+        "flash64" | "flash512" => Ok(BackupType::Flash64),
+        "flash128" | "flash1m" => Ok(BackupType::Flash128),
+        "eeprom512" | "eeprom4k" => Ok(BackupType::Eeprom512),
+        "eeprom8k" | "eeprom64k" => Ok(BackupType::Eeprom8K),
+        _ => Err(format!("Unknown save type {value}")),
+    }
+}
+
+fn parse_mode(value: &str) -> SdlResult<Mode> {
+    match value.to_lowercase().as_str() {
+        "usr" | "user" => Ok(Mode::User),
+        "fiq" => Ok(Mode::Fiq),
+        "irq" => Ok(Mode::Irq),
+        "svc" | "supervisor" => Ok(Mode::Supervisor),
+        "abt" | "abort" => Ok(Mode::Abort),
+        "und" | "undefined" => Ok(Mode::Undefined),
+        "sys" | "system" => Ok(Mode::System),
+        _ => Err(format!("Unknown CPU mode {value}")),
+    }
+}
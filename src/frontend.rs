@@ -1,7 +1,13 @@
+use std::{fs::File, io::BufWriter, thread, time::Duration};
+
 use paste::paste;
+
+#[cfg(feature = "profiling")]
+use crate::profiler::FrameProfile;
 use sdl2::{
+    audio::{AudioQueue, AudioSpecDesired},
     event::Event,
-    keyboard::Scancode,
+    keyboard::{Mod, Scancode},
     pixels::PixelFormatEnum,
     render::{Canvas, Texture, TextureCreator},
     video::{Window, WindowContext},
@@ -9,10 +15,27 @@ use sdl2::{
 };
 
 use crate::{
+    apu::{self, Apu, Channel, OutputSettings},
     gba::{Gba, LCD_HEIGHT, LCD_WIDTH},
-    ppu, SdlResult,
+    ppu, profile,
+    recorder::{VideoRecorder, WavWriter},
+    SdlResult,
 };
 
+/// Audio kept queued ahead of the device in `i16` values (stereo), about two frames.
+/// Emulation waits while more is queued, which paces it to the sound card.
+const AUDIO_LATENCY: usize = apu::SAMPLE_RATE as usize / 30 * 2;
+
+/// Shift+1..6 mutes one of the APU channels, Ctrl+1..6 solos it.
+const AUDIO_CHANNEL_KEYS: [Scancode; 6] = [
+    Scancode::Num1,
+    Scancode::Num2,
+    Scancode::Num3,
+    Scancode::Num4,
+    Scancode::Num5,
+    Scancode::Num6,
+];
+
 macro_rules! process_scancodes {
     ($kba:expr, $state:expr; $($name:ident => $code:ident),*) => {
         paste! {
@@ -25,14 +48,88 @@ macro_rules! process_scancodes {
     };
 }
 
+/// Turbo buttons: held buttons in `buttons` get released every other half period.
+#[derive(Default)]
+pub struct AutoFire {
+    /// KEYINPUT mask of the auto-fire buttons.
+    pub buttons: u16,
+    /// Presses per second.
+    pub rate: u32,
+    frame: u32,
+}
+
+impl AutoFire {
+    pub fn new(buttons: u16, rate: u32) -> Self {
+        Self { buttons, rate, frame: 0 }
+    }
+
+    /// Takes the KEYINPUT of this frame (0 = pressed) and releases the auto-fire buttons in the off phase.
+    pub fn apply(&mut self, keyinput: u16) -> u16 {
+        // Half a period in frames at ~60 fps, at least one.
+        let half_period = (30 / self.rate.max(1)).max(1);
+        let off = (self.frame / half_period) % 2 == 1;
+        self.frame = self.frame.wrapping_add(1);
+
+        match off {
+            true => keyinput | self.buttons,
+            false => keyinput,
+        }
+    }
+}
+
+/// LCD ghosting: blends each presented frame with the previous one,
+/// smoothing out games that flicker sprites for transparency.
+pub struct Ghosting {
+    pub enabled: bool,
+    /// Weight of the previous frame, 0.5 is a plain 50/50 blend.
+    pub factor: f32,
+    prev_frame: Vec<[u8; 4]>,
+}
+
+impl Ghosting {
+    pub fn new(enabled: bool, factor: f32) -> Self {
+        Self {
+            enabled,
+            factor,
+            prev_frame: vec![[0; 4]; LCD_WIDTH * LCD_HEIGHT],
+        }
+    }
+
+    /// Blend pixel `i` of the current frame with what was shown before and remember the result.
+    pub fn blend(&mut self, i: usize, px: [u8; 4]) -> [u8; 4] {
+        let prev = self.prev_frame[i];
+        let out = std::array::from_fn(|c| {
+            (px[c] as f32 * (1.0 - self.factor) + prev[c] as f32 * self.factor).round() as u8
+        });
+
+        self.prev_frame[i] = out;
+        out
+    }
+}
+
 pub struct SDLApplication {
     canvas: Canvas<Window>,
     texture_creator: TextureCreator<WindowContext>,
     event_pump: EventPump,
+    pub auto_fire: AutoFire,
+    /// PPU frame sequence number and backdrop of what is in the texture.
+    shown_frame: Option<(u64, u16)>,
+    pub ghosting: Ghosting,
+    /// Dumps every frame while set (`--record-video`).
+    pub video_recorder: Option<VideoRecorder<BufWriter<File>>>,
+    /// Records the played audio while set (`--record-audio`), paused and resumed with F9.
+    pub audio_recorder: Option<WavWriter<BufWriter<File>>>,
+    recording_audio: bool,
+    /// Every sample of the mixer while set (`--dump-wav`).
+    pub wav_dump: Option<WavWriter<BufWriter<File>>>,
+    /// Mono downmix and master volume of what is played and recorded, not of `wav_dump`.
+    pub audio_output: OutputSettings,
+    /// APU output, not opened with `--mute`.
+    audio: Option<AudioQueue<i16>>,
 }
 
 impl SDLApplication {
-    pub fn new(title: &str) -> SdlResult<Self> {
+    pub fn new(title: &str, mute: bool) -> SdlResult<Self> {
         let sdl_context = sdl2::init()?;
         let video_subsystem = sdl_context.video()?;
 
@@ -46,23 +143,71 @@ impl SDLApplication {
         let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
         let texture_creator = canvas.texture_creator();
 
+        let audio = match mute {
+            true => None,
+            false => {
+                let spec = AudioSpecDesired {
+                    freq: Some(apu::SAMPLE_RATE as i32),
+                    channels: Some(2),
+                    samples: Some(1024),
+                };
+                let queue = sdl_context.audio()?.open_queue(None, &spec)?;
+                queue.resume();
+                Some(queue)
+            }
+        };
+
         Ok(Self {
             event_pump,
             canvas,
             texture_creator,
+            auto_fire: AutoFire::default(),
+            shown_frame: None,
+            ghosting: Ghosting::new(false, 0.5),
+            video_recorder: None,
+            audio_recorder: None,
+            recording_audio: true,
+            wav_dump: None,
+            audio_output: OutputSettings::default(),
+            audio,
         })
     }
 
     pub fn run(&mut self, kba: &mut Gba) -> SdlResult<()> {
+        // Supersampling ends at the native resolution.
+        let scale = match kba.cpu.bus.ppu.supersample {
+            true => 1,
+            false => kba.cpu.bus.ppu.internal_scale,
+        };
         let mut texture = self
             .texture_creator
-            .create_texture_streaming(PixelFormatEnum::RGBA32, LCD_WIDTH as u32, LCD_HEIGHT as u32)
+            .create_texture_streaming(
+                PixelFormatEnum::RGBA32,
+                (LCD_WIDTH * scale) as u32,
+                (LCD_HEIGHT * scale) as u32,
+            )
             .map_err(|e| e.to_string())?;
 
+        #[cfg(feature = "profiling")]
+        let mut profile_total = FrameProfile::default();
+
         'main: loop {
             for event in self.event_pump.poll_iter() {
-                if let Event::Quit { .. } = event {
-                    break 'main;
+                match event {
+                    Event::Quit { .. } => break 'main,
+                    Event::KeyDown { scancode: Some(Scancode::G), repeat: false, .. } => {
+                        self.ghosting.enabled = !self.ghosting.enabled;
+                    }
+                    Event::KeyDown { scancode: Some(Scancode::F9), repeat: false, .. } if self.audio_recorder.is_some() => {
+                        self.recording_audio = !self.recording_audio;
+                        eprintln!("Audio recording {}", if self.recording_audio { "resumed" } else { "paused" });
+                    }
+                    Event::KeyDown { scancode: Some(code), keymod, repeat: false, .. } => {
+                        if let Some(i) = AUDIO_CHANNEL_KEYS.iter().position(|&key| key == code) {
+                            Self::toggle_audio_channel(&mut kba.cpu.bus.apu, Channel::ALL[i], keymod);
+                        }
+                    }
+                    _ => {}
                 }
             }
 
@@ -80,44 +225,138 @@ impl SDLApplication {
                 r => S
             );
 
-            // todo: vsync delay / sleep.
-            // For now, update every 266_666 cycles (60 frames).
-            while kba.cycles < 266_666 {
-                kba.run();
+            let keyinput = kba.cpu.bus.key_input.keyinput();
+            kba.cpu.bus.key_input.set_keyinput(self.auto_fire.apply(keyinput));
+
+            if let Some(reason) = kba.run_frame().stop {
+                eprintln!("{reason}");
+            }
+
+            let samples = kba.take_audio();
+            if let Some(wav) = &mut self.wav_dump {
+                wav.write_samples(&samples).map_err(|e| e.to_string())?;
+            }
+
+            let samples = self.audio_output.apply(&samples);
+            if let Some(queue) = &self.audio {
+                Self::queue_audio(queue, &samples)?;
+            }
+            if let (Some(recorder), true) = (&mut self.audio_recorder, self.recording_audio) {
+                recorder.write_samples(&samples).map_err(|e| e.to_string())?;
             }
 
             // Update frame and convert Option pixel values to corresponding colors.
             // Needs backdrop color which is always color 0 of pal 0 for ignored pixels.
-            self.update_texture(
-                &mut texture,
-                &kba.cpu.bus.ppu.buffer[0..(LCD_WIDTH * LCD_HEIGHT)],
-                u16::from_le_bytes([kba.cpu.bus.palette_ram[0], kba.cpu.bus.palette_ram[1]]),
-            )?;
+            // Skipped if neither the PPU output nor the backdrop changed (ghosting still fades).
+            let backdrop = u16::from_le_bytes([kba.cpu.bus.palette_ram[0], kba.cpu.bus.palette_ram[1]]);
+            let frame = Some((kba.cpu.bus.ppu.frame_seq, backdrop));
+
+            if frame != self.shown_frame || self.ghosting.enabled {
+                profile!(Upload);
+
+                let buffer = match scale > 1 {
+                    true => &kba.cpu.bus.ppu.hires_buffer[..],
+                    false => kba.framebuffer(),
+                };
+
+                Self::update_texture(
+                    &mut texture,
+                    buffer,
+                    backdrop,
+                    &mut self.ghosting,
+                )?;
+                self.shown_frame = frame;
+            }
+
+            if let Some(recorder) = &mut self.video_recorder {
+                recorder
+                    .write_frame(&kba.cpu.bus.ppu.buffer, backdrop)
+                    .map_err(|e| e.to_string())?;
+            }
 
-            kba.cycles = 0;
             kba.cpu.bus.key_input.set_keyinput(0x03FF);
 
+            #[cfg(feature = "profiling")]
+            {
+                profile_total.add(&FrameProfile::take());
+                if profile_total.frames == 60 {
+                    let title = format!("κba - {profile_total}");
+                    self.canvas.window_mut().set_title(&title).map_err(|e| e.to_string())?;
+                    profile_total = FrameProfile::default();
+                }
+            }
+
             self.canvas.clear();
             self.canvas.copy(&texture, None, None)?;
             self.canvas.present();
         }
 
+        for wav in [self.audio_recorder.take(), self.wav_dump.take()].into_iter().flatten() {
+            wav.finish().map_err(|e| e.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    /// Shift mutes or unmutes `channel`. Ctrl mutes everything else, or unmutes all if it's
+    /// already the only one left. Prints what's audible afterwards.
+    fn toggle_audio_channel(apu: &mut Apu, channel: Channel, keymod: Mod) {
+        let solo = !(1 << channel as u8) & 0x3F;
+        let ctrl = keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD);
+        let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+
+        match (ctrl, shift) {
+            (true, _) if apu.muted_channels == solo => apu.muted_channels = 0,
+            (true, _) => apu.muted_channels = solo,
+            (false, true) => apu.set_muted(channel, !apu.is_muted(channel)),
+            (false, false) => return,
+        }
+
+        let channels: Vec<_> = Channel::ALL
+            .iter()
+            .map(|&ch| format!("{} {}", ch.name(), if apu.is_muted(ch) { "off" } else { "on" }))
+            .collect();
+        eprintln!("Audio channels: {}", channels.join(", "));
+    }
+
+    /// Queue a frame of samples and wait until the device played down to `AUDIO_LATENCY`.
+    /// A drained queue gets silence first, so a slow frame doesn't crackle or stall.
+    fn queue_audio(queue: &AudioQueue<i16>, samples: &[i16]) -> SdlResult<()> {
+        let queued = || queue.size() as usize / std::mem::size_of::<i16>();
+
+        if queued() == 0 {
+            queue.queue_audio(&[0; AUDIO_LATENCY])?;
+        }
+        queue.queue_audio(samples)?;
+
+        while queued() > AUDIO_LATENCY {
+            thread::sleep(Duration::from_millis(1));
+        }
+
         Ok(())
     }
 
     fn update_texture(
-        &self,
         texture: &mut Texture,
         buffer: &[Option<u16>],
         backdrop: u16,
+        ghosting: &mut Ghosting,
     ) -> SdlResult<()> {
+        ghosting.prev_frame.resize(buffer.len(), [0; 4]);
+
         texture.with_lock(None, |buf: &mut [u8], _: usize| {
-            for (i, px) in buffer[0..(LCD_WIDTH * LCD_HEIGHT)].iter().enumerate() {
-                let [r, g, b, a] = match px {
+            for (i, px) in buffer.iter().enumerate() {
+                let mut rgba = match px {
                     Some(color) => ppu::rgb555_to_color(*color).to_be_bytes(),
                     None => ppu::rgb555_to_color(backdrop).to_be_bytes(),
                 };
 
+                if ghosting.enabled {
+                    rgba = ghosting.blend(i, rgba);
+                }
+
+                let [r, g, b, a] = rgba;
+
                 buf[i * 4] = r;
                 buf[i * 4 + 1] = g;
                 buf[i * 4 + 2] = b;
@@ -126,3 +365,24 @@ impl SDLApplication {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_fire_toggles_every_half_period() {
+        // A and B held, A on turbo at 10 presses per second, which is 3 frames pressed and 3 released.
+        let mut auto_fire = AutoFire::new(0x0001, 10);
+        let keyinput: Vec<u16> = (0..12).map(|_| auto_fire.apply(0x03FC)).collect();
+        assert_eq!(keyinput, [0x03FC, 0x03FC, 0x03FC, 0x03FD, 0x03FD, 0x03FD].repeat(2));
+
+        // A released stays released, the phase keeps running.
+        assert_eq!(auto_fire.apply(0x03FF), 0x03FF);
+
+        // Faster than 30 presses per second toggles every frame.
+        let mut auto_fire = AutoFire::new(0x0001, 60);
+        let keyinput: Vec<u16> = (0..4).map(|_| auto_fire.apply(0x03FE)).collect();
+        assert_eq!(keyinput, [0x03FE, 0x03FF, 0x03FE, 0x03FF]);
+    }
+}
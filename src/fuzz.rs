@@ -0,0 +1,150 @@
+use crate::{
+    arm::interpreter::arm7tdmi::{Arm7TDMI, Cpsr, Mode},
+    gba::{LCD_HEIGHT, LCD_WIDTH},
+    mmu::{bus::Bus, flat_mem::FlatMem, Mcu},
+    rng::Rng,
+};
+
+/// Any address, but mostly the mapped regions and the used part of IO. Also the last bytes
+/// of the address space, where 16 and 32-bit accesses wrap around to 0.
+fn random_address(rng: &mut Rng) -> u32 {
+    match rng.below(18) {
+        0x04 => 0x0400_0000 | rng.below(0x400),
+        0x10 => 0xFFFF_FFFC | rng.below(4),
+        0x11 => rng.u32(),
+        region => region << 24 | rng.u32() & 0x00FF_FFFF,
+    }
+}
+
+/// Run all fuzz targets `iterations` times, any panic is a bug.
+pub fn run(iterations: u32, seed: u64) {
+    let mut rng = Rng::new(seed);
+
+    for i in 0..iterations {
+        bus_traffic(&mut rng, 10_000);
+        ppu_frame(&mut rng);
+        opcode_stream(&mut rng, 10_000);
+
+        println!("{}/{iterations}", i + 1);
+    }
+}
+
+/// Random reads and writes of every width at arbitrary addresses, with DMA and timers ticking.
+pub fn bus_traffic(rng: &mut Rng, accesses: u32) {
+    let mut bus = Bus::default();
+
+    for cycle in 0..accesses as u64 {
+        let address = random_address(rng);
+
+        match rng.below(6) {
+            0 => drop(bus.read8(address)),
+            1 => drop(bus.read16(address)),
+            2 => drop(bus.read32(address)),
+            3 => bus.write8(address, rng.u32() as u8),
+            4 => bus.write16(address, rng.u32() as u16),
+            _ => bus.write32(address, rng.u32()),
+        }
+
+        bus.tick(cycle);
+    }
+}
+
+/// Random VRAM, palette, OAM and LCD registers through one whole frame.
+pub fn ppu_frame(rng: &mut Rng) {
+    let mut bus = Bus::default();
+
+    rng.fill(&mut bus.vram[..]);
+    rng.fill(&mut bus.palette_ram);
+    rng.fill(&mut bus.oam);
+
+    for address in (0x0400_0000..0x0400_0056).step_by(2) {
+        bus.write16(address, rng.u32() as u16);
+    }
+
+    // No interrupts or DMAs, only the PPU.
+    bus.write16(0x0400_0200, 0);
+    for address in [0x0400_00BA, 0x0400_00C6, 0x0400_00D2, 0x0400_00DE] {
+        bus.write16(address, 0);
+    }
+
+    for cycle in 0..(1232 * 228) {
+        bus.tick(cycle);
+    }
+
+    assert_eq!(bus.ppu.buffer.len(), LCD_WIDTH * LCD_HEIGHT);
+}
+
+/// Random opcodes in random memory on `FlatMem`, in ARM and THUMB and every mode.
+pub fn opcode_stream(rng: &mut Rng, steps: u32) {
+    const MODES: [Mode; 7] = [
+        Mode::User,
+        Mode::Fiq,
+        Mode::Irq,
+        Mode::Supervisor,
+        Mode::Abort,
+        Mode::Undefined,
+        Mode::System,
+    ];
+
+    let mut mem = FlatMem::new(0x4000);
+    rng.fill(&mut mem.0);
+
+    let mut cpu = Arm7TDMI::with_bus(mem);
+    for r in 0..15 {
+        cpu.regs[r] = rng.u32();
+    }
+
+    let mut cpsr = Cpsr(rng.u32() & 0xF000_00E0);
+    cpsr.set_mode(MODES[rng.below(7) as usize]);
+    cpu.boot_at(
+        rng.below(0x4000) | cpsr.state() as u32,
+        cpsr.mode().unwrap(),
+    );
+    cpu.cpsr = cpsr;
+
+    for _ in 0..steps {
+        cpu.cycle();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A short run of the fuzz targets with fixed seeds, `--fuzz` runs longer ones.
+    const SEEDS: [u64; 4] = [1, 2, 3, 0xDEAD_BEEF];
+
+    #[test]
+    fn bus_traffic_never_panics() {
+        for seed in SEEDS {
+            bus_traffic(&mut Rng::new(seed), 10_000);
+        }
+    }
+
+    #[test]
+    fn bus_wraps_at_end_of_address_space() {
+        let mut bus = Bus::default();
+        for address in 0xFFFF_FFFC..=0xFFFF_FFFF {
+            bus.read8(address);
+            bus.read16(address);
+            bus.read32(address);
+            bus.write8(address, 0xFF);
+            bus.write16(address, 0xFFFF);
+            bus.write32(address, 0xFFFF_FFFF);
+        }
+    }
+
+    #[test]
+    fn ppu_frame_never_panics() {
+        for seed in SEEDS {
+            ppu_frame(&mut Rng::new(seed));
+        }
+    }
+
+    #[test]
+    fn opcode_stream_never_panics() {
+        for seed in SEEDS {
+            opcode_stream(&mut Rng::new(seed), 10_000);
+        }
+    }
+}
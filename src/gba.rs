@@ -1,12 +1,62 @@
-use crate::arm::interpreter::arm7tdmi::Arm7TDMI;
+use crate::{
+    arm::interpreter::{
+        arm7tdmi::{Arm7TDMI, Mode},
+        instr_profile::InstrProfile,
+    },
+    hang::HangDetector,
+    mmu::access_stats::AccessStats,
+    profile,
+    rom::MULTIBOOT_SIZE,
+};
 
 pub const LCD_WIDTH: usize = 240;
 pub const LCD_HEIGHT: usize = 160;
 
+/// Instructions per `run_block` in `run_frame`.
+const BLOCK_SIZE: u32 = 64;
+
+/// One finished frame, see `Gba::frames`.
+pub struct Frame {
+    /// 240x160 BGR555 pixels, backdrop already filled in.
+    pub pixels: Vec<u16>,
+    /// Interleaved stereo samples (left, right) at `apu::SAMPLE_RATE` since the last frame.
+    pub audio: Vec<i16>,
+}
+
+/// What a `Gba::run_frame` call did.
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub struct FrameInfo {
+    /// Cycles executed for this frame.
+    pub cycles: u64,
+    /// Something the frontend should report, see `StopReason`.
+    pub stop: Option<StopReason>,
+}
+
+/// Why a frame needs attention.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StopReason {
+    /// The CPU stayed in `pc_range` for `frames` frames without writing memory or taking an IRQ.
+    PossibleHang { pc_range: (u32, u32), frames: u32 },
+}
+
+impl std::fmt::Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopReason::PossibleHang { pc_range: (lo, hi), frames } => write!(
+                f,
+                "Possible hang: looping at {lo:#010X}-{hi:#010X} for {frames} frames without memory writes or IRQs"
+            ),
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct Gba {
     pub cpu: Arm7TDMI,
-    pub cycles: usize,
+    /// Cycles since power on, never reset (a u32 would wrap after ~4 minutes).
+    pub cycles: u64,
+    /// Optional spin loop detection (`--hang-frames`), reported through `FrameInfo::stop`.
+    pub hang_detector: Option<HangDetector>,
     rom: Vec<u8>,
 }
 
@@ -19,17 +69,689 @@ impl Gba {
         }
     }
 
+    /// Load a multiboot image into EWRAM and start it at `0x02000000` with an empty Game Pak,
+    /// like the BIOS does after a link cable transfer.
+    pub fn with_multiboot(image: &[u8]) -> Result<Self, String> {
+        if image.len() > MULTIBOOT_SIZE {
+            return Err(format!(
+                "Multiboot image is {} bytes, only {MULTIBOOT_SIZE} fit into EWRAM",
+                image.len()
+            ));
+        }
+
+        let mut gba = Self::with_rom(&[]);
+        gba.cpu.bus.wram[..image.len()].copy_from_slice(image);
+        gba.cpu.boot_at(0x0200_0000, Mode::System);
+        gba.post_bios_io();
+
+        Ok(gba)
+    }
+
+    /// Developer option to jump straight to `pc` (e.g. IWRAM-loaded code), bypassing BIOS and ROM entry.
+    pub fn boot_at(&mut self, pc: u32, mode: Mode) -> Result<(), String> {
+        // BIOS, EWRAM, IWRAM, VRAM and ROM can hold code.
+        let mapped = match pc >> 24 {
+            0x00 => pc < 0x4000,
+            0x02 | 0x03 | 0x06 | 0x08..=0x0D => true,
+            _ => false,
+        };
+
+        if !mapped {
+            return Err(format!("Boot address {pc:#010X} is not in a mapped region"));
+        }
+
+        self.cpu.boot_at(pc, mode);
+        Ok(())
+    }
+
+    /// Skip the BIOS intro and its cartridge header checks (Nintendo logo at `0x04`-`0x9F`,
+    /// header checksum) and start at the ROM entry point, where the BIOS would jump after them.
+    ///
+    /// Homebrew without the logo won't boot on a real BIOS otherwise. With `--hle-bios` this is
+    /// always done, there is no BIOS that could validate the header.
+    pub fn skip_logo_check(&mut self) {
+        self.cpu.boot_at(0x0800_0000, Mode::System);
+        self.post_bios_io();
+    }
+
+    /// IO registers the BIOS leaves different from their reset values when it jumps to the game.
+    fn post_bios_io(&mut self) {
+        self.cpu.bus.soundbias = 0x0200;
+        self.cpu.bus.postflg = 1;
+    }
+
+    /// Start counting memory accesses per page, see `access_stats`.
+    pub fn enable_access_stats(&mut self) {
+        self.cpu.bus.access_stats.get_or_insert_with(AccessStats::default);
+    }
+
+    /// Memory accesses since `enable_access_stats`.
+    pub fn access_stats(&self) -> Option<&AccessStats> {
+        self.cpu.bus.access_stats.as_ref()
+    }
+
+    /// Start counting executed instructions per format, see `instruction_profile`.
+    pub fn enable_instruction_profile(&mut self) {
+        self.cpu.instr_profile.get_or_insert_with(InstrProfile::default);
+    }
+
+    /// Instructions executed since `enable_instruction_profile`.
+    pub fn instruction_profile(&self) -> Option<&InstrProfile> {
+        self.cpu.instr_profile.as_ref()
+    }
+
+    /// Report loops without memory writes or IRQs that last `frames` frames, see `StopReason::PossibleHang`.
+    pub fn enable_hang_detector(&mut self, frames: u32) {
+        self.hang_detector = Some(HangDetector::new(frames));
+    }
+
+    /// Run until the PPU wraps around from the last VBlank line to line 0, the finished frame
+    /// is then in `framebuffer`. Needs no frontend.
+    pub fn run_frame(&mut self) -> FrameInfo {
+        let (frame, start) = (self.cpu.bus.ppu.frame, self.cycles);
+        while self.cpu.bus.ppu.frame == frame {
+            self.run_block(BLOCK_SIZE);
+        }
+
+        let writes = self.cpu.bus.writes;
+        FrameInfo {
+            cycles: self.cycles - start,
+            stop: self.hang_detector.as_mut().and_then(|hang| hang.end_frame(writes)),
+        }
+    }
+
+    /// Run whole instructions until at least `budget` cycles passed, returns the actual cycles.
+    pub fn run_cycles(&mut self, budget: u64) -> u64 {
+        let start = self.cycles;
+        while self.cycles - start < budget {
+            self.run();
+        }
+
+        self.cycles - start
+    }
+
+    /// Lazily runs one frame per `next()`, never ends.
+    pub fn frames(&mut self) -> impl Iterator<Item = Frame> + '_ {
+        std::iter::from_fn(move || {
+            self.run_frame();
+
+            let mut frame = self.frame();
+            frame.audio = self.take_audio();
+            Some(frame)
+        })
+    }
+
+    /// APU samples since the last call, what `Frame::audio` holds for `frames`.
+    pub fn take_audio(&mut self) -> Vec<i16> {
+        self.cpu.bus.apu.samples.drain(..).collect()
+    }
+
+    /// The 240x160 PPU output, `None` where the backdrop shows.
+    pub fn framebuffer(&self) -> &[Option<u16>] {
+        &self.cpu.bus.ppu.buffer[0..(LCD_WIDTH * LCD_HEIGHT)]
+    }
+
+    /// Current PPU output with the backdrop color for empty pixels, without audio.
+    pub fn frame(&self) -> Frame {
+        let palette_ram = &self.cpu.bus.palette_ram;
+        let backdrop = u16::from_le_bytes([palette_ram[0], palette_ram[1]]);
+
+        Frame {
+            pixels: self
+                .framebuffer()
+                .iter()
+                .map(|px| px.unwrap_or(backdrop))
+                .collect(),
+            audio: Vec::new(),
+        }
+    }
+
+    /// Run up to `n` instructions, returns how many cycles passed.
+    ///
+    /// IRQs and HALT are only checked at the start. Whether an IRQ is taken only depends on
+    /// IE, IF, IME, HALTCNT and the CPSR I bit, so the block ends early as soon as one of them
+    /// changes, by an IO write or by a device raising IF. Same result as `n` calls to `run`.
+    /// It also ends with the frame, so `run_frame` doesn't run into the next one.
+    pub fn run_block(&mut self, n: u32) -> u64 {
+        let block_state = |gba: &Gba| {
+            let bus = &gba.cpu.bus;
+            (bus.ie.ie(), bus.iff.iff(), bus.ime.ime(), bus.halt, gba.cpu.cpsr.irq(), bus.ppu.frame)
+        };
+
+        let (start, state) = (self.cycles, block_state(self));
+        self.run();
+
+        for _ in 1..n {
+            if self.cpu.bus.halt || block_state(self) != state {
+                break;
+            }
+
+            {
+                profile!(Cpu);
+                self.cpu.cycle();
+            }
+
+            let cycles = self.cpu.cycles_taken + std::mem::take(&mut self.cpu.bus.stall_cycles);
+            self.tick(cycles);
+        }
+
+        self.cycles - start
+    }
+
     pub fn run(&mut self) {
-        if self.cpu.bus.halt && (self.cpu.bus.ie.0 & self.cpu.bus.iff.0) != 0 {
+        // HALT exits on IE & IF even with IME off or IRQs masked in the CPSR.
+        // The IRQ itself is only taken if enabled, on the instruction boundary right after waking.
+        if self.cpu.bus.halt && self.cpu.bus.irq_pending() {
             self.cpu.bus.halt = false;
         }
 
         if !self.cpu.bus.halt {
-            self.cpu.dispatch_irq();
+            profile!(Cpu);
+            if let Some(hang) = &mut self.hang_detector {
+                hang.sample(self.cpu.regs[15]);
+            }
+
+            let irq_entry = self.cpu.dispatch_irq();
             self.cpu.cycle();
+
+            let cycles = irq_entry + self.cpu.cycles_taken + std::mem::take(&mut self.cpu.bus.stall_cycles);
+            self.tick(cycles);
+        } else {
+            self.tick(1);
+        }
+    }
+
+    /// Let the rest of the system catch up with the CPU, one cycle at a time.
+    fn tick(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            self.cpu.bus.tick(self.cycles);
+            self.cycles += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arm::interpreter::{hle::BIOS_IF, instr_profile::Format},
+        mmu::Mcu,
+        rng::Rng,
+    };
+
+    const FRAME_CYCLES: u64 = 1232 * 228;
+
+    /// `Gba::run_block` has to end up in exactly the state of single `Gba::run` calls.
+    fn block_vs_step(rng: &mut Rng, cycles: u64) {
+        let mut rom = vec![0; 0x4000];
+        rng.fill(&mut rom);
+
+        let (mut step, mut block) = (Gba::with_rom(&rom), Gba::with_rom(&rom));
+        let (pc, mode) = (0x0800_0000 | rng.below(0x4000), Mode::System);
+        step.cpu.boot_at(pc, mode);
+        block.cpu.boot_at(pc, mode);
+
+        while step.cycles < cycles {
+            step.run();
+        }
+        // Instructions take a varying number of cycles, so single steps near the end to stop
+        // on the same instruction boundary as `step`.
+        while block.cycles < cycles {
+            match cycles - block.cycles > 64 * 64 {
+                true => drop(block.run_block(64)),
+                false => block.run(),
+            }
+        }
+
+        let state = |gba: &Gba| {
+            (
+                gba.cpu.regs,
+                gba.cpu.cpsr.0,
+                gba.cpu.bus.iff.iff(),
+                gba.cpu.bus.halt,
+            )
+        };
+        assert_eq!(state(&step), state(&block), "run_block diverged from run");
+    }
+
+    /// Game Pak with `program` at its start, booted into it in ARM or THUMB state.
+    fn booted(program: &[u32], thumb: bool) -> Gba {
+        let size = if thumb { 2 } else { 4 };
+        let rom: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_le_bytes()[..size].to_vec()).collect();
+
+        let mut gba = Gba::with_rom(&rom);
+        gba.cpu.boot_at(0x0800_0000 | thumb as u32, Mode::System);
+        gba
+    }
+
+    /// Write ARM `program` to memory at `address`, e.g. an IRQ handler to IWRAM.
+    fn load(gba: &mut Gba, address: u32, program: &[u32]) {
+        for (i, &opcode) in program.iter().enumerate() {
+            gba.cpu.bus.write32(address + i as u32 * 4, opcode);
+        }
+    }
+
+    /// A BIOS with only the IRQ vector of the real one: save r0-r3, r12 and lr, call `handler`
+    /// through `0x03007FFC` and return from the IRQ. The handler acknowledges IF itself.
+    fn irq_bios(gba: &mut Gba, handler: &[u32]) {
+        let irq: [u32; 6] = [
+            0xE92D_500F, // stmfd sp!, {r0-r3, r12, lr}
+            0xE3A0_0301, // mov r0, #0x04000000
+            0xE28F_E000, // add lr, pc, #0
+            0xE510_F004, // ldr pc, [r0, #-4]
+            0xE8BD_500F, // ldmfd sp!, {r0-r3, r12, lr}
+            0xE25E_F004, // subs pc, lr, #4
+        ];
+
+        let mut bios = vec![0; 0x4000];
+        // b 0x128
+        bios[0x18..0x1C].copy_from_slice(&0xEA00_0042u32.to_le_bytes());
+        for (i, opcode) in irq.iter().enumerate() {
+            bios[0x128 + i * 4..0x12C + i * 4].copy_from_slice(&opcode.to_le_bytes());
+        }
+        gba.cpu.bus.bios = Box::leak(bios.into_boxed_slice());
+
+        load(gba, 0x0300_1000, handler);
+        gba.cpu.bus.write32(0x0300_7FFC, 0x0300_1000);
+    }
+
+    #[test]
+    fn logo_less_rom_boots_with_skip() {
+        // No Nintendo logo or checksum, the entry point stores 0x42 to IWRAM and spins:
+        // mov r0, #0x42; mov r1, #0x03000000; str r0, [r1]; b .
+        let mut rom = vec![0; 0xC0];
+        for (i, opcode) in [0xE3A0_0042u32, 0xE3A0_1403, 0xE581_0000, 0xEAFF_FFFE].iter().enumerate() {
+            rom[i * 4..i * 4 + 4].copy_from_slice(&opcode.to_le_bytes());
+        }
+
+        let mut gba = Gba::with_rom(&rom);
+        gba.skip_logo_check();
+        gba.run_frame();
+
+        let bus = &mut gba.cpu.bus;
+        assert_eq!(bus.read32(0x0300_0000), 0x42);
+        assert_eq!(bus.read16(0x0400_0088), 0x0200, "SOUNDBIAS");
+        assert_eq!(bus.read8(0x0400_0300), 1, "POSTFLG");
+        assert_eq!(gba.cpu.regs[15] >> 24, 0x08, "left the ROM");
+    }
+
+    #[test]
+    fn boot_at_iwram() {
+        let mut gba = Gba::with_rom(&[]);
+        // mov r0, #0x42; b .
+        load(&mut gba, 0x0300_0100, &[0xE3A0_0042, 0xEAFF_FFFE]);
+
+        // Nothing changes when the address can't hold code.
+        for pc in [0x0000_4000, 0x0100_0000, 0x0400_0000, 0x0500_0000, 0x0E00_0000, 0x1000_0000] {
+            assert!(gba.boot_at(pc, Mode::System).is_err(), "{pc:#010X}");
+            assert_eq!(gba.cpu.regs[15], 0, "{pc:#010X}");
+        }
+
+        gba.boot_at(0x0300_0100, Mode::Supervisor).unwrap();
+        assert_eq!(gba.cpu.cpsr.mode(), Ok(Mode::Supervisor));
+        assert_eq!(gba.cpu.regs[13], 0x0300_7FE0);
+        for _ in 0..8 {
+            gba.run();
+        }
+        assert_eq!(gba.cpu.regs[0], 0x42);
+        assert_eq!(gba.cpu.regs[15] >> 24, 0x03, "left IWRAM");
+    }
+
+    #[test]
+    fn instruction_profile_counts_formats() {
+        let mut gba = booted(
+            &[
+                0xE3A0_0000, // mov r0, #0
+                0xE280_0001, // loop: add r0, r0, #1
+                0xE350_0004, // cmp r0, #4
+                0xE001_0090, // mul r1, r0, r0
+                0x1AFF_FFFB, // bne loop
+                0xEAFF_FFFE, // b .
+            ],
+            false,
+        );
+        gba.enable_instruction_profile();
+
+        // 17 instructions up to the `b .`, which then runs three times. The last `bne` fails its
+        // condition and doesn't count.
+        for _ in 0..20 {
+            gba.run();
+        }
+
+        let profile = gba.instruction_profile().unwrap();
+        assert_eq!(
+            profile.sorted(),
+            [(Format::DataProcessing, 9), (Format::Branch, 6), (Format::Multiply, 4)]
+        );
+        assert_eq!(profile.total(), 19);
+        assert_eq!(profile.count(Format::ThumbAlu), 0);
+    }
+
+    #[test]
+    fn hang_detector_fires_on_spin_loop() {
+        // b .
+        let mut gba = booted(&[0xEAFF_FFFE], false);
+        gba.enable_hang_detector(3);
+
+        let stops: Vec<_> = (0..6).map(|_| gba.run_frame().stop).collect();
+        let hang = Some(StopReason::PossibleHang { pc_range: (0x0800_0000, 0x0800_0000), frames: 3 });
+        assert_eq!(stops, [None, None, hang, None, None, hang]);
+
+        // A loop that keeps writing memory is making progress.
+        let mut gba = booted(
+            &[
+                0xE3A0_1403, // mov r1, #0x03000000
+                0xE581_0000, // loop: str r0, [r1]
+                0xEAFF_FFFD, // b loop
+            ],
+            false,
+        );
+        gba.enable_hang_detector(1);
+        assert!((0..4).all(|_| gba.run_frame().stop.is_none()));
+    }
+
+    #[test]
+    fn multiboot_starts_in_ewram() {
+        // mov r0, #0x42; mov r1, #0x03000000; str r0, [r1]; b .
+        let image: Vec<u8> = [0xE3A0_0042u32, 0xE3A0_1403, 0xE581_0000, 0xEAFF_FFFE]
+            .iter()
+            .flat_map(|opcode| opcode.to_le_bytes())
+            .collect();
+
+        let mut gba = Gba::with_multiboot(&image).unwrap();
+        assert_eq!(gba.cpu.regs[15], 0x0200_0000);
+        gba.run_frame();
+
+        let bus = &mut gba.cpu.bus;
+        assert_eq!(bus.read32(0x0300_0000), 0x42);
+        assert_eq!(bus.read16(0x0400_0088), 0x0200, "SOUNDBIAS");
+        assert_eq!(gba.cpu.regs[15] >> 24, 0x02, "left EWRAM");
+
+        // All of EWRAM fits, a byte more doesn't.
+        assert!(Gba::with_multiboot(&vec![0; MULTIBOOT_SIZE]).is_ok());
+        assert!(Gba::with_multiboot(&vec![0; MULTIBOOT_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn run_block_matches_run() {
+        for seed in [1, 2, 3, 4] {
+            block_vs_step(&mut Rng::new(seed), 100_000);
+        }
+    }
+
+    #[test]
+    fn unmapped_reads_return_the_last_opcode() {
+        // mov r1, #0x10000000; ldr r0, [r1]; ldrb r2, [r1, #3]
+        let mut gba = booted(&[0xE3A0_1201, 0xE591_0000, 0xE5D1_2003], false);
+        (0..3).for_each(|_| gba.run());
+        assert_eq!(gba.cpu.regs[0], 0xE591_0000);
+        assert_eq!(gba.cpu.regs[2], 0xE5);
+
+        // THUMB opcodes are on both halves: mov r1, #1; lsl r1, r1, #28; ldr r0, [r1]; ldrb r2, [r1, #1]
+        let mut gba = booted(&[0x2101, 0x0709, 0x6808, 0x784A], true);
+        (0..4).for_each(|_| gba.run());
+        assert_eq!(gba.cpu.regs[0], 0x6808_6808);
+        assert_eq!(gba.cpu.regs[2], 0x78);
+    }
+
+    #[test]
+    fn bios_mirror_and_io_gaps_are_open_bus() {
+        let mut gba = booted(
+            &[
+                0xE3A0_1A05, // mov r1, #0x5000
+                0xE591_0000, // ldr r0, [r1]
+                0xE1D1_20B2, // ldrh r2, [r1, #2]
+                0xE5D1_3001, // ldrb r3, [r1, #1]
+                0xE3A0_4301, // mov r4, #0x04000000
+                0xE1D4_55BA, // ldrh r5, [r4, #0x5A]
+            ],
+            false,
+        );
+        (0..6).for_each(|_| gba.run());
+        assert_eq!(gba.cpu.regs[0..4], [0xE591_0000, 0x5000, 0xE1D1, 0x30]);
+        assert_eq!(gba.cpu.regs[5], 0xE1D4);
+
+        // mov r1, #0x50; lsl r1, r1, #8; ldr r0, [r1]; ldrh r2, [r1, #2]; ldrb r3, [r1, #3]
+        let mut gba = booted(&[0x2150, 0x0209, 0x6808, 0x884A, 0x78CB], true);
+        (0..5).for_each(|_| gba.run());
+        assert_eq!(gba.cpu.regs[0..4], [0x6808_6808, 0x5000, 0x884A, 0x78]);
+    }
+
+    #[test]
+    fn hblank_dma_aborted_from_vcount_irq() {
+        let mut gba = booted(&[0xEAFF_FFFE], false);
+        irq_bios(
+            &mut gba,
+            &[
+                0xE3A0_0301, // mov r0, #0x04000000
+                0xE3A0_1000, // mov r1, #0
+                0xE1C0_1DBE, // strh r1, [r0, #0xDE]
+                0xE3A0_1004, // mov r1, #4
+                0xE280_2C02, // add r2, r0, #0x200
+                0xE1C2_10B2, // strh r1, [r2, #2]
+                0xE12F_FF1E, // bx lr
+            ],
+        );
+
+        // Repeating HBlank DMA 3 of one halfword, the VCount IRQ on line 5 disables it.
+        let bus = &mut gba.cpu.bus;
+        for i in 0..16 {
+            bus.write16(0x0200_0000 + i * 2, i as u16 + 1);
+        }
+        bus.write32(0x0400_00D4, 0x0200_0000);
+        bus.write32(0x0400_00D8, 0x0200_0100);
+        bus.write16(0x0400_00DC, 1);
+        bus.write16(0x0400_00DE, 0xA200);
+        bus.write16(0x0400_0004, 0x0520);
+        bus.write16(0x0400_0200, 0x0004);
+        bus.write16(0x0400_0208, 1);
+        gba.run_frame();
+
+        // HBlanks of lines 0 to 4 transferred, the one of line 5 comes after the handler.
+        let bus = &mut gba.cpu.bus;
+        let units: Vec<u16> = (0..8).map(|i| bus.read16(0x0200_0100 + i * 2)).collect();
+        assert_eq!(units, [1, 2, 3, 4, 5, 0, 0, 0]);
+        assert_eq!(bus.read16(0x0400_00DE), 0);
+        assert_eq!(bus.read16(0x0400_0202) & 0x0004, 0, "IRQ acknowledged");
+        assert_eq!(gba.cpu.regs[15], 0x0800_0000, "back in the main loop");
+    }
+
+    #[test]
+    fn halt_wakes_without_ime() {
+        for ime in [false, true] {
+            let mut gba = booted(
+                &[
+                    0xE3A0_0301, // mov r0, #0x04000000
+                    0xE280_0C03, // add r0, r0, #0x300
+                    0xE3A0_1000, // mov r1, #0
+                    0xE5C0_1001, // strb r1, [r0, #1] (HALTCNT)
+                    0xE3A0_2001, // mov r2, #1
+                    0xEAFF_FFFE, // b .
+                ],
+                false,
+            );
+            // Acknowledge VBlank and leave a mark at 0x03000000.
+            irq_bios(
+                &mut gba,
+                &[
+                    0xE3A0_0301, // mov r0, #0x04000000
+                    0xE280_2C02, // add r2, r0, #0x200
+                    0xE3A0_1001, // mov r1, #1
+                    0xE1C2_10B2, // strh r1, [r2, #2]
+                    0xE3A0_3403, // mov r3, #0x03000000
+                    0xE583_3000, // str r3, [r3]
+                    0xE12F_FF1E, // bx lr
+                ],
+            );
+            gba.cpu.bus.write16(0x0400_0004, 0x0008);
+            gba.cpu.bus.write16(0x0400_0200, 0x0001);
+            gba.cpu.bus.write16(0x0400_0208, ime as u16);
+
+            while gba.cpu.regs[2] != 1 {
+                assert!(gba.cycles < FRAME_CYCLES, "IME {ime}: never woke up");
+                gba.run();
+            }
+
+            // Halted until VBlank starts, the handler only runs with IME set, right after waking.
+            assert!((160 * 1232..160 * 1232 + 100).contains(&gba.cycles), "IME {ime}: woke at {}", gba.cycles);
+            let bus = &mut gba.cpu.bus;
+            assert_eq!(bus.read32(0x0300_0000) == 0x0300_0000, ime, "IME {ime}: handler");
+            assert_eq!(bus.read16(0x0400_0202) & 1 == 0, ime, "IME {ime}: IF");
+            assert_eq!(gba.cpu.cpsr.mode(), Ok(Mode::System));
+        }
+    }
+
+    #[test]
+    fn vblank_intr_wait_once_per_frame() {
+        // mov r4, #0; loop: swi 0x05 (VBlankIntrWait); add r4, r4, #1; b loop
+        let mut gba = booted(&[0xE3A0_4000, 0xEF05_0000, 0xE284_4001, 0xEAFF_FFFC], false);
+        gba.cpu.hle = true;
+        // Acknowledge IF and OR the flags into BIOS_IF through its mirror at 0x03FFFFF8.
+        irq_bios(
+            &mut gba,
+            &[
+                0xE3A0_0301, // mov r0, #0x04000000
+                0xE280_2C02, // add r2, r0, #0x200
+                0xE3A0_1001, // mov r1, #1
+                0xE1C2_10B2, // strh r1, [r2, #2]
+                0xE150_30B8, // ldrh r3, [r0, #-8]
+                0xE383_3001, // orr r3, r3, #1
+                0xE140_30B8, // strh r3, [r0, #-8]
+                0xE12F_FF1E, // bx lr
+            ],
+        );
+        gba.cpu.bus.write16(0x0400_0004, 0x0008);
+        gba.cpu.bus.write16(0x0400_0200, 0x0001);
+
+        let mut returns = Vec::new();
+        while gba.cycles < 5 * FRAME_CYCLES {
+            let count = gba.cpu.regs[4];
+            gba.run();
+            if gba.cpu.regs[4] != count {
+                returns.push(gba.cycles);
+            }
+        }
+
+        assert_eq!(returns.len(), 5, "returned at {returns:?}");
+        for (frame, cycles) in returns.into_iter().enumerate() {
+            let vblank = frame as u64 * FRAME_CYCLES + 160 * 1232;
+            assert!((vblank..vblank + 200).contains(&cycles), "frame {frame} returned at {cycles}");
+        }
+        assert_eq!(gba.cpu.bus.read16(BIOS_IF), 0, "BIOS_IF flags taken");
+    }
+
+    #[test]
+    fn irq_between_thumb_bl_halves() {
+        // bl 0x0800000C; b .; b .; b .; b .; mov r5, #1; b .
+        let mut gba = booted(&[0xF000, 0xF804, 0xE7FE, 0xE7FE, 0xE7FE, 0xE7FE, 0x2501, 0xE7FE], true);
+        irq_bios(
+            &mut gba,
+            &[
+                0xE3A0_0301, // mov r0, #0x04000000
+                0xE280_2C02, // add r2, r0, #0x200
+                0xE3A0_1001, // mov r1, #1
+                0xE1C2_10B2, // strh r1, [r2, #2]
+                0xE12F_FF1E, // bx lr
+            ],
+        );
+
+        gba.run();
+        assert_eq!((gba.cpu.regs[15], gba.cpu.regs[14]), (0x0800_0002, 0x0800_0004), "first half");
+
+        // VBlank requested right before the second half.
+        let bus = &mut gba.cpu.bus;
+        bus.iff.0 = 0x0001;
+        bus.write16(0x0400_0200, 0x0001);
+        bus.write16(0x0400_0208, 1);
+
+        let mut irq_taken = false;
+        for _ in 0..100 {
+            gba.run();
+            irq_taken |= gba.cpu.cpsr.mode() == Ok(Mode::Irq);
+            if gba.cpu.regs[5] == 1 {
+                break;
+            }
+        }
+
+        assert!(irq_taken, "no IRQ between the halves");
+        assert_eq!(gba.cpu.regs[5], 1, "never reached the target");
+        assert_eq!(gba.cpu.regs[14], 0x0800_0005, "return address of the BL");
+        assert_eq!(gba.cpu.cpsr.mode(), Ok(Mode::System));
+        assert!(bool::from(gba.cpu.cpsr.state()), "left THUMB");
+    }
+
+    #[test]
+    fn clock_past_u32() {
+        // Timer 0 overflows every 256 * 64 cycles, VBlank once per frame, across 2^32 cycles.
+        // `b .` in IWRAM takes 3 cycles, requests are seen up to 2 cycles late.
+        let mut gba = Gba::with_rom(&[]);
+        load(&mut gba, 0x0300_0000, &[0xEAFF_FFFE]);
+        gba.cpu.boot_at(0x0300_0000, Mode::System);
+        gba.cycles = (1 << 32) - 2 * FRAME_CYCLES;
+        let bus = &mut gba.cpu.bus;
+        bus.write16(0x0400_0100, 0xFF00);
+        bus.write16(0x0400_0102, 0x00C1);
+        bus.write16(0x0400_0004, 0x0008);
+
+        let (mut timer, mut vblank) = (Vec::new(), Vec::new());
+        while gba.cycles < (1 << 32) + 2 * FRAME_CYCLES {
+            gba.run();
+            let iff = gba.cpu.bus.iff.0;
+            if iff & 0x0008 != 0 {
+                timer.push(gba.cycles);
+            }
+            if iff & 0x0001 != 0 {
+                vblank.push(gba.cycles);
+            }
+            gba.cpu.bus.write16(0x0400_0202, iff);
         }
 
-        self.cpu.bus.tick(self.cycles);
-        self.cycles += 1;
+        let spaced = |stamps: &[u64], period: u64| stamps.windows(2).all(|w| w[1].abs_diff(w[0] + period) <= 2);
+        assert!(timer.iter().any(|&t| t < 1 << 32) && timer.iter().any(|&t| t > 1 << 32));
+        assert!(spaced(&timer, 256 * 64), "timer at {timer:?}");
+        assert_eq!(vblank.len(), 4, "VBlank at {vblank:?}");
+        assert!(spaced(&vblank, FRAME_CYCLES), "VBlank at {vblank:?}");
+        assert_eq!(gba.cpu.bus.ppu.frame, 4);
+    }
+
+    #[test]
+    fn run_frame_matches_run_cycles() {
+        // mov r1, #0x03000000; loop: add r0, r0, #1; str r0, [r1]; b loop
+        let program = [0xE3A0_1403, 0xE280_0001, 0xE581_0000, 0xEAFF_FFFC];
+        let (mut frames, mut cycles) = (booted(&program, false), booted(&program, false));
+
+        let mut total = 0;
+        for _ in 0..10 {
+            let info = frames.run_frame();
+            assert!(info.cycles.abs_diff(FRAME_CYCLES) < 16, "frame of {} cycles", info.cycles);
+            total += info.cycles;
+        }
+
+        // Both stop on the first instruction boundary at or past the same cycle.
+        assert_eq!(cycles.run_cycles(total), total);
+        assert_eq!((cycles.cycles, cycles.cpu.regs), (frames.cycles, frames.cpu.regs));
+        assert_eq!(cycles.cpu.bus.ppu.frame, 10);
+    }
+
+    #[test]
+    fn frames_iterator() {
+        // Mode 3 with a red pixel at (1, 0).
+        let mut gba = booted(&[0xEAFF_FFFE], false);
+        gba.cpu.bus.write16(0x0600_0002, 0x001F);
+        gba.cpu.bus.write16(0x0400_0000, 0x0403);
+
+        // Lazy, nothing runs until a frame is asked for.
+        drop(gba.frames());
+        assert_eq!(gba.cycles, 0);
+
+        let frames: Vec<Frame> = gba.frames().take(3).collect();
+        assert_eq!(frames.len(), 3);
+        for frame in &frames {
+            assert_eq!(frame.pixels.len(), LCD_WIDTH * LCD_HEIGHT);
+            assert_eq!(frame.pixels[..2], [0x0000, 0x001F]);
+            // A sample pair every 512 cycles.
+            assert!(frame.audio.len().abs_diff(2 * 280_896 / 512) <= 2, "{} samples", frame.audio.len());
+        }
+        assert_eq!(gba.cpu.bus.ppu.frame, 3);
     }
 }
@@ -0,0 +1,78 @@
+use crate::gba::StopReason;
+
+/// Widest loop in bytes that still counts as spinning in place.
+const MAX_LOOP_SPAN: u32 = 0x40;
+
+/// Flags the CPU spinning in a small PC range without writing memory for a number of frames.
+///
+/// Any IRQ handler pushes to the stack, so no writes also means no interrupts were taken.
+/// PCs are sampled at every `Gba::run` call, i.e. at least once per instruction block.
+pub struct HangDetector {
+    /// Frames without progress before reporting.
+    pub frames: u32,
+    frame_range: Option<(u32, u32)>,
+    loop_range: Option<(u32, u32)>,
+    writes: u64,
+    stuck: u32,
+}
+
+impl HangDetector {
+    pub fn new(frames: u32) -> Self {
+        Self {
+            frames,
+            frame_range: None,
+            loop_range: None,
+            writes: 0,
+            stuck: 0,
+        }
+    }
+
+    pub fn sample(&mut self, pc: u32) {
+        self.frame_range = Some(match self.frame_range {
+            Some((lo, hi)) => (lo.min(pc), hi.max(pc)),
+            None => (pc, pc),
+        });
+    }
+
+    /// Call once per frame with the bus write counter, reports the loop once it
+    /// was stuck for `frames` frames in a row and then starts counting anew.
+    pub fn end_frame(&mut self, writes: u64) -> Option<StopReason> {
+        let progress = writes != self.writes;
+        self.writes = writes;
+
+        // A frame spent in HALT has no samples, waiting for an IRQ isn't a hang.
+        let Some((lo, hi)) = self.frame_range.take().filter(|_| !progress) else {
+            self.loop_range = None;
+            self.stuck = 0;
+            return None;
+        };
+
+        let (lo, hi) = match self.loop_range {
+            Some((start, end)) if end.max(hi) - start.min(lo) <= MAX_LOOP_SPAN => (start.min(lo), end.max(hi)),
+            // Moved on to a different loop (or none at all), start over from this frame.
+            _ => {
+                self.stuck = 0;
+                (lo, hi)
+            }
+        };
+
+        if hi - lo > MAX_LOOP_SPAN {
+            self.loop_range = None;
+            return None;
+        }
+
+        self.loop_range = Some((lo, hi));
+        self.stuck += 1;
+
+        if self.stuck < self.frames {
+            return None;
+        }
+
+        self.loop_range = None;
+        self.stuck = 0;
+        Some(StopReason::PossibleHang {
+            pc_range: (lo, hi),
+            frames: self.frames,
+        })
+    }
+}
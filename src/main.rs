@@ -1,25 +1,151 @@
 #![allow(dead_code)]
 use std::path::Path;
 
-use frontend::SDLApplication;
+use apu::OutputSettings;
+use arm::interpreter::{arm7tdmi::Mode, bench, single_step};
+use cli::Args;
+use frontend::{AutoFire, Ghosting, SDLApplication};
 use gba::Gba;
+use mmu::{io_log::IoLog, page_table};
+use recorder::{VideoRecorder, WavWriter};
+use rom::RomFile;
 
+mod apu;
 mod arm;
+mod cli;
 mod frontend;
+mod fuzz;
 mod gba;
+mod hang;
 mod mmu;
+mod patch;
 mod ppu;
+mod profiler;
+mod recorder;
+mod rng;
+mod rom;
 
 pub type SdlResult<T> = Result<T, String>;
 
 fn main() -> SdlResult<()> {
-    let file_path = std::env::args().nth(1).expect("A rom has to be specified!");
-    let file_name = Path::new(&file_path).file_name().unwrap_or_default();
+    let args = Args::parse()?;
 
-    let mut sdl_application = SDLApplication::new(&format!("κba - {:?}", file_name))?;
+    if let Some(path) = &args.single_step {
+        let (passed, total) = single_step::run_file(path)?;
+        println!("{passed}/{total} passed");
+        return Ok(());
+    }
 
-    let rom = std::fs::read(&file_path).map_err(|e| e.to_string())?;
-    let mut kba = Gba::with_rom(&rom);
+    if let Some(instructions) = args.bench_dispatch {
+        for (set, mips) in bench::run(instructions) {
+            println!("{} decoder, {set}: {mips:.1} MIPS", bench::DECODER);
+        }
+        return Ok(());
+    }
 
-    sdl_application.run(&mut kba)
+    if let Some(accesses) = args.bench_bus {
+        for (path, mreads) in page_table::bench(accesses) {
+            println!("{path}: {mreads:.1} M reads/s");
+        }
+        return Ok(());
+    }
+
+    if let Some(iterations) = args.fuzz {
+        fuzz::run(iterations, args.fuzz_seed);
+        return Ok(());
+    }
+
+    let file_name = Path::new(&args.rom_path).file_name().unwrap_or_default();
+
+    let mut sdl_application = SDLApplication::new(&format!("κba - {:?}", file_name), args.mute)?;
+    sdl_application.auto_fire = AutoFire::new(args.turbo, args.turbo_rate);
+    sdl_application.ghosting = Ghosting::new(args.ghosting.is_some(), args.ghosting.unwrap_or(0.5));
+    sdl_application.audio_output = OutputSettings { mono: args.mono, volume: args.volume };
+
+    if let Some(path) = &args.record_video {
+        sdl_application.video_recorder = Some(VideoRecorder::create(path)?);
+    }
+
+    if let Some(path) = &args.record_audio {
+        sdl_application.audio_recorder = Some(WavWriter::create(path)?);
+    }
+
+    if let Some(path) = &args.dump_wav {
+        sdl_application.wav_dump = Some(WavWriter::create(path)?);
+    }
+
+    let rom_file = RomFile::load(&args.rom_path)?;
+    let multiboot = args.multiboot || rom_file.is_multiboot();
+    // Multiboot images have no Game Pak to save to.
+    let save_path = (!multiboot).then(|| rom_file.save_path());
+    let mut rom = rom_file.data;
+
+    if let Some(path) = args.patch.clone().or_else(|| patch::find_patch(&args.rom_path)) {
+        patch::apply(&mut rom, &path, args.force_patch)?;
+    }
+
+    let mut kba = match multiboot {
+        true => Gba::with_multiboot(&rom)?,
+        false => Gba::with_rom(&rom),
+    };
+    kba.cpu.bus.ppu.set_internal_scale(args.internal_scale);
+    kba.cpu.bus.ppu.supersample = args.supersample;
+    kba.cpu.hle = args.hle_bios;
+
+    if let Some(seed) = args.power_on_fill {
+        kba.cpu.bus.fill_power_on(seed);
+    }
+
+    if let Some(save_type) = args.save_type {
+        kba.cpu.bus.game_pak.set_backup(save_type);
+    }
+
+    if let Some(path) = &save_path {
+        kba.cpu.bus.game_pak.load_save(path)?;
+    }
+
+    // Multiboot images already start in EWRAM, past the BIOS. An HLE BIOS has no intro
+    // that could check the header, roms always start at their entry point.
+    if (args.skip_logo_check || args.hle_bios) && !multiboot {
+        kba.skip_logo_check();
+    }
+
+    if let Some(pc) = args.boot_pc {
+        kba.boot_at(pc, args.boot_mode.unwrap_or(Mode::System))?;
+    }
+
+    if let Some(path) = &args.log_io {
+        kba.cpu.bus.io_log = Some(IoLog::create(path, args.log_io_filter.clone())?);
+    }
+
+    if args.access_stats {
+        kba.enable_access_stats();
+    }
+
+    if args.instr_profile {
+        kba.enable_instruction_profile();
+    }
+
+    if let Some(frames) = args.hang_frames {
+        kba.enable_hang_detector(frames);
+    }
+
+    // Save even if the frontend failed, the game state is still valid.
+    let result = sdl_application.run(&mut kba);
+
+    if let Some(path) = &save_path {
+        kba.cpu.bus.game_pak.write_save(path)?;
+    }
+
+    result?;
+
+    if let Some(stats) = kba.access_stats() {
+        print!("{stats}");
+    }
+
+    if let Some(profile) = kba.instruction_profile() {
+        print!("{profile}");
+    }
+
+    Ok(())
 }
@@ -0,0 +1,131 @@
+use std::{collections::HashMap, fmt};
+
+/// Bytes covered by one counter.
+pub const STATS_PAGE_SIZE: u32 = 0x1000;
+
+/// Read and write counters per 4 KiB page, for finding the hot parts of memory.
+///
+/// Every CPU/DMA access counts once, whatever its width.
+#[derive(Default, Clone, Debug)]
+pub struct AccessStats {
+    pages: HashMap<u32, Counts>,
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub struct Counts {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+impl AccessStats {
+    pub fn read(&mut self, address: u32) {
+        self.pages.entry(address / STATS_PAGE_SIZE).or_default().reads += 1;
+    }
+
+    pub fn write(&mut self, address: u32) {
+        self.pages.entry(address / STATS_PAGE_SIZE).or_default().writes += 1;
+    }
+
+    /// Counts of the page containing `address`.
+    pub fn page(&self, address: u32) -> Counts {
+        self.pages.get(&(address / STATS_PAGE_SIZE)).copied().unwrap_or_default()
+    }
+
+    /// Counts summed up per memory region (`address >> 24`), sorted by region.
+    pub fn regions(&self) -> Vec<(u32, Counts)> {
+        let mut regions = HashMap::<u32, Counts>::new();
+
+        for (page, counts) in &self.pages {
+            let region = regions.entry((page * STATS_PAGE_SIZE) >> 24).or_default();
+            region.reads += counts.reads;
+            region.writes += counts.writes;
+        }
+
+        let mut regions: Vec<_> = regions.into_iter().collect();
+        regions.sort_by_key(|(region, _)| *region);
+        regions
+    }
+
+    /// Start addresses and counts of the `n` most accessed pages.
+    pub fn hottest_pages(&self, n: usize) -> Vec<(u32, Counts)> {
+        let mut pages: Vec<_> = self.pages.iter().map(|(p, c)| (p * STATS_PAGE_SIZE, *c)).collect();
+        pages.sort_by_key(|(page, c)| (std::cmp::Reverse(c.reads + c.writes), *page));
+        pages.truncate(n);
+        pages
+    }
+
+    pub fn clear(&mut self) {
+        self.pages.clear();
+    }
+}
+
+fn region_name(region: u32) -> &'static str {
+    match region {
+        0x00 => "BIOS",
+        0x02 => "EWRAM",
+        0x03 => "IWRAM",
+        0x04 => "IO",
+        0x05 => "Palette",
+        0x06 => "VRAM",
+        0x07 => "OAM",
+        0x08..=0x0D => "ROM",
+        0x0E | 0x0F => "SRAM",
+        _ => "Unmapped",
+    }
+}
+
+impl fmt::Display for AccessStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<10} {:<10} {:>12} {:>14}", "Region", "Address", "Reads", "Writes")?;
+        for (region, counts) in self.regions() {
+            writeln!(f, "{:<10} {:#04X}xxxxxx {:>12} {:>14}", region_name(region), region, counts.reads, counts.writes)?;
+        }
+
+        writeln!(f, "\nHottest pages:")?;
+        for (page, counts) in self.hottest_pages(16) {
+            writeln!(f, "{:<10} {page:#010X} {:>12} {:>14}", region_name(page >> 24), counts.reads, counts.writes)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::{bus::Bus, Mcu};
+
+    #[test]
+    fn counts_bus_accesses() {
+        let mut bus = Bus::default();
+        // Nothing is counted before the stats are enabled.
+        bus.read32(0x0300_0000);
+        bus.access_stats = Some(AccessStats::default());
+
+        // Three widths into the first IWRAM page, two reads of the next one and a VRAM read.
+        bus.write32(0x0300_0000, 1);
+        bus.write16(0x0300_0FFE, 2);
+        bus.write8(0x0300_0010, 3);
+        bus.read32(0x0300_0000);
+        bus.read8(0x0300_1000);
+        bus.read16(0x0300_1FFE);
+        bus.read16(0x0601_0000);
+
+        let stats = bus.access_stats.as_ref().unwrap();
+        assert_eq!(stats.page(0x0300_0800), Counts { reads: 1, writes: 3 });
+        assert_eq!(stats.page(0x0300_1000), Counts { reads: 2, writes: 0 });
+        assert_eq!(stats.page(0x0300_2000), Counts::default());
+        assert_eq!(
+            stats.regions(),
+            [(0x03, Counts { reads: 3, writes: 3 }), (0x06, Counts { reads: 1, writes: 0 })]
+        );
+        assert_eq!(
+            stats.hottest_pages(2),
+            [(0x0300_0000, Counts { reads: 1, writes: 3 }), (0x0300_1000, Counts { reads: 2, writes: 0 })]
+        );
+
+        let mut stats = stats.clone();
+        stats.clear();
+        assert!(stats.regions().is_empty());
+    }
+}
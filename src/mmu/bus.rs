@@ -1,14 +1,19 @@
+use std::ops::RangeInclusive;
+
 use proc_bitfield::{bitfield, BitRange};
 
 use super::{
+    access_stats::AccessStats,
     dma::{AddrControl, DMAChannels, StartTiming},
     game_pak::GamePak,
+    io_log::{IoAccess, IoLog},
     irq::{IE, IF, IME},
+    page_table::{self, Page, PAGE_COUNT, PAGE_MASK, PAGE_SHIFT},
     timer::Timers,
     Mcu,
 };
 
-use crate::{bits, box_arr, ppu::lcd::Ppu, set_bits};
+use crate::{apu::Apu, bits, box_arr, ppu::lcd::Ppu, profile, rng::Rng, set_bits};
 
 pub struct Bus {
     /// BIOS - System ROM (needs to be provided).
@@ -16,6 +21,8 @@ pub struct Bus {
 
     /// Picture Processing Unit, owns LCD IO registers.
     pub ppu: Ppu,
+    /// Audio Processing Unit, owns sound IO registers.
+    pub apu: Apu,
     /// Key Status.
     pub key_input: KEYINPUT,
     /// Interrupt Master Enable Register.
@@ -40,9 +47,32 @@ pub struct Bus {
     pub oam: [u8; 0x400],
     /// External Memory (Cartridge).
     pub game_pak: GamePak,
+    /// Fast lookup for the plain memory regions, everything else takes the slow path.
+    pub pages: Box<[Page; PAGE_COUNT]>,
 
     pub halt: bool,
     pub soundbias: u32,
+    /// Set by the BIOS once it has booted, 0 right after a reset.
+    pub postflg: u8,
+    /// Internal Memory Control, mirrored across the whole IO region.
+    pub imc: u32,
+    /// Game Pak wait states.
+    pub waitcnt: WAITCNT,
+    /// Address right after the last access, an access starting there is sequential.
+    pub next_seq: u32,
+
+    /// Last fetched opcode, returned by reads from unmapped memory.
+    pub open_bus: u32,
+
+    /// Optional log of every IO register access (`--log-io`).
+    pub io_log: Option<IoLog>,
+    /// Optional per page access counters (`--access-stats`).
+    pub access_stats: Option<AccessStats>,
+    /// CPU and DMA writes since power on, the hang detector's sign of progress.
+    pub writes: u64,
+    /// Extra cycles accesses waited on top of the CPU's own count, taken by `Gba::run`.
+    /// DMA accesses count too, a stand-in for the CPU being paused while DMA runs.
+    pub stall_cycles: u32,
 }
 
 impl Default for Bus {
@@ -51,6 +81,7 @@ impl Default for Bus {
             bios: include_bytes!("gba_bios.bin"),
 
             ppu: Ppu::default(),
+            apu: Apu::default(),
             key_input: KEYINPUT(0x03FF),
             ime: IME(0),
             ie: IE(0),
@@ -64,22 +95,150 @@ impl Default for Bus {
             vram: box_arr![0x00; 0x18000],
             oam: [0x00; 0x400],
             game_pak: GamePak::default(),
+            pages: page_table::build_pages(&WAITCNT(0), 0x0D00_0020),
 
             halt: false,
             soundbias: 0,
+            postflg: 0,
+            imc: 0x0D00_0020,
+            waitcnt: WAITCNT(0),
+            next_seq: 0,
+            open_bus: 0,
+            io_log: None,
+            access_stats: None,
+            writes: 0,
+            stall_cycles: 0,
         }
     }
 }
 
 impl Bus {
-    pub fn tick(&mut self, cycles: usize) {
+    /// Offset into the IO region, Internal Memory Control (`0x04000800`) repeats every 64 KiB.
+    fn io_offset(address: u32) -> u32 {
+        match address & 0xFFFF {
+            addr @ 0x0800..=0x0803 => addr,
+            _ => address - 0x0400_0000,
+        }
+    }
+
+    /// Offset into VRAM. The 96 KiB repeat every 128 KiB, with the last 32 KiB of each block
+    /// mirroring the OBJ tiles (`0x06010000-0x06017FFF`).
+    pub fn vram_offset(address: u32) -> usize {
+        match address as usize & 0x0001_FFFF {
+            offset @ 0x0001_8000.. => offset - 0x8000,
+            offset => offset,
+        }
+    }
+
+    /// Byte of the last fetched opcode at the alignment of `address`, what undriven reads return.
+    fn read_open_bus(&self, address: u32) -> u8 {
+        (self.open_bus >> ((address & 3) * 8)) as u8
+    }
+
+    fn page(&self, address: u32) -> Page {
+        match address >> 28 {
+            0 => self.pages[address as usize >> PAGE_SHIFT],
+            _ => Page::Slow,
+        }
+    }
+
+    /// Recompute the pages of `regions` after a write to WAITCNT or IMC, which they depend on.
+    fn rebuild_pages(&mut self, regions: RangeInclusive<usize>) {
+        page_table::update_pages(&mut self.pages, regions, &self.waitcnt, self.imc);
+    }
+
+    /// Fill palette RAM, VRAM and OAM like real hardware powers on, with noise instead of zeros.
+    /// The same `seed` always gives the same contents.
+    pub fn fill_power_on(&mut self, seed: u64) {
+        let mut rng = Rng::new(seed);
+
+        rng.fill(&mut self.palette_ram);
+        rng.fill(&mut self.vram[..]);
+        rng.fill(&mut self.oam);
+    }
+
+    /// Any enabled interrupt requested, independent of IME and the CPSR I bit.
+    pub fn irq_pending(&self) -> bool {
+        (self.ie.ie() & self.iff.iff() & 0x3FFF) != 0
+    }
+
+    /// Hands IO region accesses to the IO log if there is one, `size` in bits.
+    fn log_io(&mut self, access: IoAccess, address: u32, value: u32, size: u32) {
+        if let Some(log) = &mut self.io_log {
+            if address >> 24 == 0x04 {
+                let position = (self.ppu.frame, self.ppu.vcount.ly(), self.ppu.cycle);
+
+                if let Err(e) = log.record(position, access, address, value, size) {
+                    eprintln!("Stopping IO log: {e}");
+                    self.io_log = None;
+                }
+            }
+        }
+    }
+
+    /// Wait states of a Game Pak access of `size` bits on top of its one cycle.
+    ///
+    /// ROM has a 16-bit bus, so a 32-bit access is a first and a second access. Accesses right
+    /// after the previous one are sequential, except at 128 KiB boundaries. SRAM has an 8-bit bus
+    /// and no sequential timing. The prefetch buffer isn't emulated.
+    fn wait_states(&mut self, address: u32, size: u32) -> u32 {
+        let sequential = address == self.next_seq && address & 0x1_FFFF != 0;
+        self.next_seq = address.wrapping_add(size / 8);
+
+        // ROM pages keep their wait states from the last WAITCNT write.
+        let (first, second) = match (address >> 24, self.page(address)) {
+            (_, Page::Rom(_, waits)) => waits,
+            (0x0E | 0x0F, _) => return self.waitcnt.sram(),
+            _ => return 0,
+        };
+
+        match (sequential, size) {
+            (false, 32) => first + second + 1,
+            (true, 32) => 2 * second + 1,
+            (false, _) => first,
+            (true, _) => second,
+        }
+    }
+
+    /// Reads with side effects, `None` for everything that can just be `peek`ed.
+    fn read_device(&mut self, address: u32) -> Option<u16> {
+        self.game_pak.is_eeprom(address).then(|| self.game_pak.read_eeprom())
+    }
+
+    fn count_access(&mut self, access: IoAccess, address: u32, size: u32) {
+        if matches!(access, IoAccess::Write) {
+            self.writes += 1;
+        }
+
+        self.stall_cycles += self.ppu.video_stall(address) + self.wait_states(address, size);
+
+        if let Some(stats) = &mut self.access_stats {
+            match access {
+                IoAccess::Read => stats.read(address),
+                IoAccess::Write => stats.write(address),
+            }
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u64) {
         self.ppu.cycle(
             &*self.vram, 
             &self.palette_ram, 
             &self.oam, 
             &mut self.iff,
         );
-        self.timers.tick(&mut self.iff, cycles);
+        let overflows = self.timers.tick(&mut self.iff, cycles);
+        self.apu.tick(cycles);
+
+        // Timer 0 and 1 clock DirectSound, one sample per overflow.
+        for (timer, &count) in overflows.iter().take(2).enumerate() {
+            for _ in 0..count {
+                let refill = self.apu.timer_overflow(timer);
+                for fifo in (0..2).filter(|&fifo| refill[fifo]) {
+                    self.sound_dma(0x0400_00A0 + fifo as u32 * 4);
+                }
+            }
+        }
 
         /* 
         The following DMA checks can still be optimized if they are only called
@@ -102,39 +261,95 @@ impl Bus {
             self.ppu.prev_mode = self.ppu.current_mode;
         }
 
-        // On enable transition for immediate DMAs.
-        if (0..4).any(|ch| self.dma_channels[ch].enable_edge()) {
+        // On enable transition for immediate DMAs, edges are latched per write.
+        let mut enabled = false;
+        for ch in 0..4 {
+            enabled |= self.dma_channels[ch].take_enable_edge();
+        }
+
+        if enabled {
             self.dma_transfer(StartTiming::Immediate);
         }
     }
 
+    /// Refill the DirectSound FIFO at `fifo` from DMA 1 or 2 with Special timing and `fifo`
+    /// as destination: 4 words to the fixed FIFO address, whatever word count and size say.
+    fn sound_dma(&mut self, fifo: u32) {
+        profile!(Dma);
+
+        for ch in 1..=2 {
+            let channel = self.dma_channels[ch];
+            if !channel.enable || channel.start_timing != StartTiming::Special || channel.dst & !3 != fifo {
+                continue;
+            }
+
+            let mut src_addr = channel.src & !3;
+            for _ in 0..4 {
+                let data = self.read32(src_addr);
+                self.write32(fifo, data);
+
+                src_addr = match channel.src_addr_ctrl {
+                    AddrControl::Increment => src_addr.wrapping_add(4),
+                    AddrControl::Decrement => src_addr.wrapping_sub(4),
+                    _ => src_addr,
+                };
+            }
+
+            if !channel.repeat {
+                self.dma_channels[ch].enable = false;
+            }
+
+            if channel.dma_irq {
+                self.iff.set_dma(ch);
+            }
+
+            self.dma_channels[ch].src = src_addr;
+        }
+    }
+
+    /// Run every enabled channel waiting for `dma_type`, in priority order 0 > 1 > 2 > 3.
+    ///
+    /// Each channel transfers all of its units before the next one starts and reads its registers
+    /// only then, so it sees what higher priority channels wrote. There is no preemption: a channel
+    /// triggered while another one runs (e.g. an immediate DMA enabled by a DMA write) starts after it.
     fn dma_transfer(&mut self, dma_type: StartTiming) {
-        let channels = self.dma_channels;
+        profile!(Dma);
 
         for ch in 0..4 {
-            let src_addr_control = channels[ch].src_addr_ctrl;
-            let dst_addr_control = channels[ch].dst_addr_ctrl;
-            let start_timing = channels[ch].start_timing;
+            let channel = self.dma_channels[ch];
+            let src_addr_control = channel.src_addr_ctrl;
+            let dst_addr_control = channel.dst_addr_ctrl;
+            let start_timing = channel.start_timing;
 
-            let addr_delta = if channels[ch].transfer_type { 4 } else { 2 };
+            let addr_delta = if channel.transfer_type { 4 } else { 2 };
 
-            let mut src_addr = channels[ch].src;
-            let mut dst_addr = channels[ch].dst;
-            let word_count = match channels[ch].word_count == 0 {
+            // Addresses are forced to the transfer width.
+            let mut src_addr = channel.src & !(addr_delta - 1);
+            let mut dst_addr = channel.dst & !(addr_delta - 1);
+            let word_count = match channel.word_count == 0 {
                 true if ch == 3 => 0xFFFF,
                 true => 0x3FFF,
-                false => channels[ch].word_count,
+                false => channel.word_count,
             };
 
+            // Special start of DMA 1/2 is sound DMA (`sound_dma`).
             // TODO: Special start (Video Capture) timing and wow, this would be nicer with a scheduler.
-            if channels[ch].enable {
+            if channel.enable {
                 if start_timing == dma_type
                     || start_timing == dma_type && self.ppu.dispstat.hblank() && !self.ppu.dispstat.vblank()
                     || start_timing == dma_type && self.ppu.dispstat.vblank() 
                     // || start_timing == StartTiming::Special && ch == 3 && self.ppu.vcount.ly() >= 2 && self.ppu.vcount.ly() <= 162 && self.ppu.vid_capture
                 {
+                    // Control changes mid-transfer only apply to the next trigger,
+                    // but clearing enable stops the channel after the current unit.
+                    let mut aborted = false;
                     for _ in 0..word_count {
-                        if channels[ch].transfer_type {
+                        if !self.dma_channels[ch].enable {
+                            aborted = true;
+                            break;
+                        }
+
+                        if channel.transfer_type {
                             let data = self.read32(src_addr);
                             self.write32(dst_addr, data);
                         } else {
@@ -143,29 +358,29 @@ impl Bus {
                         }
                         
                         src_addr = match src_addr_control {
-                            AddrControl::Increment => src_addr + addr_delta,
-                            AddrControl::Decrement => src_addr - addr_delta,
+                            AddrControl::Increment => src_addr.wrapping_add(addr_delta),
+                            AddrControl::Decrement => src_addr.wrapping_sub(addr_delta),
                             _ => src_addr,
                         };
 
                         dst_addr = match dst_addr_control {
-                            AddrControl::Increment | AddrControl::IncReload => dst_addr + addr_delta,
-                            AddrControl::Decrement => dst_addr - addr_delta,
+                            AddrControl::Increment | AddrControl::IncReload => dst_addr.wrapping_add(addr_delta),
+                            AddrControl::Decrement => dst_addr.wrapping_sub(addr_delta),
                             AddrControl::Fixed => dst_addr,
                         };
                     }
 
-                    if !channels[ch].repeat || start_timing == StartTiming::Immediate {
+                    if !channel.repeat || start_timing == StartTiming::Immediate {
                         self.dma_channels[ch].enable = false;
                     }
 
-                    if channels[ch].dma_irq {
+                    if channel.dma_irq && !aborted {
                         self.iff.set_dma(ch);
                     }
 
                     // self.ppu.vid_capture = false;
                     self.dma_channels[ch].src = src_addr;
-                    self.dma_channels[ch].dst = if dst_addr_control == AddrControl::IncReload { channels[ch].dst } else { dst_addr };
+                    self.dma_channels[ch].dst = if dst_addr_control == AddrControl::IncReload { channel.dst } else { dst_addr };
                 }
             }
         }
@@ -173,78 +388,228 @@ impl Bus {
 }
 
 impl Mcu for Bus {
-    #[rustfmt::skip]
+    fn read32(&mut self, address: u32) -> u32 {
+        let value = match self.read_device(address) {
+            Some(value) => value as u32,
+            None => self.peek32(address),
+        };
+        self.count_access(IoAccess::Read, address, 32);
+        self.log_io(IoAccess::Read, address, value, 32);
+        value
+    }
+
+    fn write32(&mut self, address: u32, value: u32) {
+        self.store16(address, value as u16);
+        self.store16(address.wrapping_add(2), (value >> 16) as u16);
+        self.count_access(IoAccess::Write, address, 32);
+        self.log_io(IoAccess::Write, address, value, 32);
+    }
+
+    fn read16(&mut self, address: u32) -> u16 {
+        let value = self.read_device(address).unwrap_or_else(|| self.peek16(address));
+        self.count_access(IoAccess::Read, address, 16);
+        self.log_io(IoAccess::Read, address, value as u32, 16);
+        value
+    }
+
+    fn write16(&mut self, address: u32, value: u16) {
+        self.store16(address, value);
+        self.count_access(IoAccess::Write, address, 16);
+        self.log_io(IoAccess::Write, address, value as u32, 16);
+    }
+
     fn read8(&mut self, address: u32) -> u8 {
+        let value = match self.read_device(address) {
+            Some(value) => value as u8,
+            None => self.peek8(address),
+        };
+        self.count_access(IoAccess::Read, address, 8);
+        self.log_io(IoAccess::Read, address, value as u32, 8);
+        value
+    }
+
+    fn write8(&mut self, address: u32, value: u8) {
+        self.store8(address, value);
+        self.count_access(IoAccess::Write, address, 8);
+        self.log_io(IoAccess::Write, address, value as u32, 8);
+    }
+
+    fn peek16(&self, address: u32) -> u16 {
+        u16::from_le_bytes([self.peek8(address), self.peek8(address.wrapping_add(1))])
+    }
+
+    fn peek32(&self, address: u32) -> u32 {
+        // Aligned words never cross a page, plain memory is read in one go.
+        let offset = address as usize & PAGE_MASK;
+        let memory = match (address & 3, self.page(address)) {
+            (0, Page::Bios) => &self.bios[offset..],
+            (0, Page::Wram(base)) => &self.wram[base + offset..],
+            (0, Page::Vram(base)) => &self.vram[base + offset..],
+            (0, Page::Rom(base, _)) => &self.game_pak.rom[base + offset..],
+            _ => {
+                return u32::from_le_bytes([
+                    self.peek8(address),
+                    self.peek8(address.wrapping_add(1)),
+                    self.peek8(address.wrapping_add(2)),
+                    self.peek8(address.wrapping_add(3)),
+                ])
+            }
+        };
+
+        u32::from_le_bytes([memory[0], memory[1], memory[2], memory[3]])
+    }
+
+    fn set_open_bus(&mut self, value: u32) {
+        self.open_bus = value;
+    }
+
+    fn peek8(&self, address: u32) -> u8 {
+        let offset = address as usize & PAGE_MASK;
+
+        match self.page(address) {
+            Page::Bios => self.bios[offset],
+            Page::Wram(base) => self.wram[base + offset],
+            Page::Vram(base) => self.vram[base + offset],
+            Page::Rom(base, _) => self.game_pak.rom[base + offset],
+            Page::Slow => self.peek8_slow(address),
+        }
+    }
+}
+
+impl Bus {
+    /// `peek32` through the full memory map only, without the page table.
+    pub(super) fn peek32_slow(&self, address: u32) -> u32 {
+        u32::from_le_bytes(std::array::from_fn(|i| self.peek8_slow(address.wrapping_add(i as u32))))
+    }
+
+    /// Full memory map, also the reference for what the page table maps.
+    #[rustfmt::skip]
+    fn peek8_slow(&self, address: u32) -> u8 {
         match address >> 24 {
             0x00 if address < 0x4000 => self.bios[address as usize],
+            // IMC bit 0 disables both WRAMs.
+            0x02 | 0x03 if self.imc & 1 != 0 => self.read_open_bus(address),
             0x02 => self.wram[address as usize % 0x0004_0000],
             0x03 => self.wram[(address as usize % 0x0000_8000) + 0x0004_0000],
-            0x04 => match address - 0x0400_0000 {
-                addr @ 0x0000..=0x0051 => self.ppu.read8(addr),
-                addr @ 0x00B0..=0x00DF => self.dma_channels.read8(addr),
-                addr @ 0x0100..=0x010F => self.timers.read8(addr),
+            0x04 => match Bus::io_offset(address) {
+                // Write-only LCD registers (scroll, affine, window, MOSAIC, BLDY) and the
+                // unused gaps around them aren't driven, the bus keeps what it last fetched.
+                0x0010..=0x0047 | 0x004C..=0x004F | 0x0054..=0x005F => self.read_open_bus(address),
+                addr @ 0x0000..=0x0053 => self.ppu.peek8(addr),
+                addr @ (0x0060..=0x0087 | 0x0090..=0x009F) => self.apu.peek8(addr),
+                addr @ 0x00B0..=0x00DF => self.dma_channels.peek8(addr),
+                addr @ 0x0100..=0x010F => self.timers.peek8(addr),
                 0x0088 => bits!(self.soundbias, 0..=7),
                 0x0089 => bits!(self.soundbias, 8..=15),
-                0x008A => bits!(self.soundbias, 16..=23),
-                0x008B => bits!(self.soundbias, 24..=31),
                 0x0130 => self.key_input.keyinput() as u8,
                 0x0131 => (self.key_input.keyinput() >> 8) as u8,
                 0x0200 => bits!(self.ie.0, 0..=7),
                 0x0201 => bits!(self.ie.0, 8..=15),
                 0x0202 => bits!(self.iff.0, 0..=7),
                 0x0203 => bits!(self.iff.0, 8..=15),
+                0x0204 => bits!(self.waitcnt.0, 0..=7),
+                0x0205 => bits!(self.waitcnt.0, 8..=15),
                 0x0208 => self.ime.enabled() as u8,
-                0x0209 => bits!(self.ime.0, 8..=15),
-                0x020A => bits!(self.ime.0, 16..=23),
-                0x020B => bits!(self.ime.0, 24..=31),
+                0x0300 => self.postflg,
+                0x0800 => bits!(self.imc, 0..=7),
+                0x0801 => bits!(self.imc, 8..=15),
+                0x0802 => bits!(self.imc, 16..=23),
+                0x0803 => bits!(self.imc, 24..=31),
+                // Past the IO registers, only Internal Memory Control is mirrored.
+                0x0400.. => self.read_open_bus(address),
                 _ => 0x00,
             },
             0x05 => self.palette_ram[address as usize % 0x400],
-            0x06 => self.vram[address as usize % 0x0001_8000],
+            0x06 => self.vram[Bus::vram_offset(address)],
             0x07 => self.oam[address as usize % 0x400],
             0x08..=0x0D => self.game_pak.rom[address as usize & 0x00FF_FFFF],
-            0x0E..=0x0F => {
-                // Flash ID workaround.
-                if address == 0x0E00_0000 {
-                    0x62
-                } else if address == 0x0E00_0001 {
-                    0x13
-                } else {
-                    self.game_pak.sram[address as usize % 0x0001_0000]   
-                }
+            0x0E..=0x0F => self.game_pak.read_backup(address),
+            // Past the BIOS, 0x01 and 0x10+ are unmapped.
+            _ => self.read_open_bus(address),
+        }
+    }
+
+
+    /// Aligned IO halfwords go to the devices as a whole, so e.g. an IF acknowledge or
+    /// a DMA control write is applied once and never half-written. 32-bit writes are
+    /// two of these, low half first.
+    fn store16(&mut self, address: u32, value: u16) {
+        if address >> 24 == 0x04 && address & 1 == 0 {
+            match Bus::io_offset(address) {
+                addr @ (0x0000..=0x004C | 0x0050..=0x0054) => return self.ppu.write16(addr, value),
+                addr @ (0x0060..=0x0087 | 0x0090..=0x00A7) => return self.apu.write16(addr, value),
+                addr @ 0x00B0..=0x00DF => return self.dma_channels.write16(addr, value),
+                addr @ 0x0100..=0x010F => return self.timers.write16(addr, value),
+                0x0202 => return self.iff.acknowledge(value),
+                _ => {}
             }
-            _ => 0,
+        }
+
+        let [lo, hi] = value.to_le_bytes();
+        self.store8(address, lo);
+        self.store8(address.wrapping_add(1), hi);
+    }
+
+    fn store8(&mut self, address: u32, value: u8) {
+        let offset = address as usize & PAGE_MASK;
+
+        match self.page(address) {
+            Page::Wram(base) => self.wram[base + offset] = value,
+            Page::Vram(base) => self.vram[base + offset] = value,
+            _ => self.store8_slow(address, value),
         }
     }
 
     #[rustfmt::skip]
-    fn write8(&mut self, address: u32, value: u8) {
+    fn store8_slow(&mut self, address: u32, value: u8) {
         match address >> 24 {
+            0x02 | 0x03 if self.imc & 1 != 0 => {}
             0x02 => self.wram[address as usize % 0x0004_0000] = value,
             0x03 => self.wram[(address as usize % 0x8000) + 0x0004_0000] = value,
-            0x04 => match address - 0x0400_0000 {
+            0x04 => match Bus::io_offset(address) {
                 addr @ (0x0000..=0x004D | 0x0050..=0x0054) => self.ppu.write8(addr, value),
+                addr @ (0x0060..=0x0087 | 0x0090..=0x00A7) => self.apu.write8(addr, value),
                 addr @ 0x00B0..=0x00DF => self.dma_channels.write8(addr, value),
                 addr @ 0x0100..=0x010F => self.timers.write8(addr, value),
-                0x0088 => set_bits!(self.soundbias, 0..=7, value),
-                0x0089 => set_bits!(self.soundbias, 8..=15, value),
-                0x008A => set_bits!(self.soundbias, 16..=23, value),
-                0x008B => set_bits!(self.soundbias, 24..=31, value),
+                // SOUNDBIAS bits 0 and 10-13 are unused, the upper halfword isn't a register.
+                0x0088 => set_bits!(self.soundbias, 0..=7, value & 0xFE),
+                0x0089 => set_bits!(self.soundbias, 8..=15, value & 0xC3),
                 0x0200 => set_bits!(self.ie.0, 0..=7, value),
-                0x0201 => set_bits!(self.ie.0, 8..=15, value),
-                0x0202 => self.iff.set_iff((self.iff.iff() & !(value as u16)) & 0x3FFF),
-                0x0203 => self.iff.set_iff((self.iff.iff() & !((value as u16) << 8)) & 0x3FFF),
+                0x0201 => set_bits!(self.ie.0, 8..=15, value & 0x3F),
+                // Byte writes to IF, aligned halfwords are acknowledged whole in `store16`.
+                0x0202 => self.iff.acknowledge(value as u16),
+                0x0203 => self.iff.acknowledge((value as u16) << 8),
+                // Bit 15 (Game Pak type) reads as 0 for GBA cartridges.
+                0x0204 => {
+                    set_bits!(self.waitcnt.0, 0..=7, value);
+                    self.rebuild_pages(0x08..=0x0D);
+                }
+                0x0205 => {
+                    set_bits!(self.waitcnt.0, 8..=15, value & 0x5F);
+                    self.rebuild_pages(0x08..=0x0D);
+                }
+                // Only bit 0 of IME exists.
                 0x0208 => self.ime.set_enabled(value & 1 != 0),
-                0x0209 => set_bits!(self.ime.0, 8..=15, value),
-                0x020A => set_bits!(self.ime.0, 16..=23, value),
-                0x020B => set_bits!(self.ime.0, 24..=31, value),
+                // Only bit 0 of POSTFLG exists.
+                0x0300 => self.postflg = value & 1,
                 0x0301 => self.halt = (value >> 7) == 0,
+                0x0800 => {
+                    set_bits!(self.imc, 0..=7, value);
+                    self.rebuild_pages(0x02..=0x03);
+                }
+                0x0801 => set_bits!(self.imc, 8..=15, value),
+                0x0802 => set_bits!(self.imc, 16..=23, value),
+                0x0803 => set_bits!(self.imc, 24..=31, value),
                 _ => {}
             },
             0x05 => self.palette_ram[address as usize % 0x400] = value,
-            0x06 => self.vram[address as usize % 0x0001_8000] = value,
+            0x06 => self.vram[Bus::vram_offset(address)] = value,
             0x07 => self.oam[address as usize % 0x400] = value,
-            0x0E..=0x0F => self.game_pak.sram[address as usize % 0x0001_0000] = value,
+            // One bit per halfword, in bit 0.
+            0x0D if self.game_pak.is_eeprom(address) && address & 1 == 0 => {
+                self.game_pak.write_eeprom(value as u16)
+            }
+            0x0E..=0x0F => self.game_pak.write_backup(address, value),
             _ => {} // eprintln!("Write to ROM/unknown addr: {address:X}"),
         }
     }
@@ -266,3 +631,356 @@ bitfield! {
         pub l: bool @ 9,
     }
 }
+
+bitfield! {
+    /// Game Pak Waitstate Control (r/w).
+    ///
+    /// Wait states per access on top of its one cycle, decoded by the `ws*`/`sram` getters.
+    pub struct WAITCNT(pub u16) {
+        pub waitcnt: u16 @ ..,
+        pub sram_wait: u8 @ 0..=1,
+        pub ws0_first_wait: u8 @ 2..=3,
+        pub ws0_second_wait: bool @ 4,
+        pub ws1_first_wait: u8 @ 5..=6,
+        pub ws1_second_wait: bool @ 7,
+        pub ws2_first_wait: u8 @ 8..=9,
+        pub ws2_second_wait: bool @ 10,
+        pub phi_output: u8 @ 11..=12,
+        pub prefetch: bool @ 14,
+        pub cgb_type: bool @ 15,
+    }
+}
+
+impl WAITCNT {
+    /// First (non-sequential) access wait states for every setting of the two bits.
+    const FIRST: [u32; 4] = [4, 3, 2, 8];
+
+    /// Wait states of a first and a sequential access to the Game Pak ROM at `address`.
+    pub fn rom(&self, address: u32) -> (u32, u32) {
+        match address >> 24 {
+            0x08 | 0x09 => (self.ws0_first(), self.ws0_second()),
+            0x0A | 0x0B => (self.ws1_first(), self.ws1_second()),
+            _ => (self.ws2_first(), self.ws2_second()),
+        }
+    }
+
+    pub fn sram(&self) -> u32 {
+        Self::FIRST[self.sram_wait() as usize]
+    }
+
+    pub fn ws0_first(&self) -> u32 {
+        Self::FIRST[self.ws0_first_wait() as usize]
+    }
+
+    pub fn ws0_second(&self) -> u32 {
+        if self.ws0_second_wait() { 1 } else { 2 }
+    }
+
+    pub fn ws1_first(&self) -> u32 {
+        Self::FIRST[self.ws1_first_wait() as usize]
+    }
+
+    pub fn ws1_second(&self) -> u32 {
+        if self.ws1_second_wait() { 1 } else { 4 }
+    }
+
+    pub fn ws2_first(&self) -> u32 {
+        Self::FIRST[self.ws2_first_wait() as usize]
+    }
+
+    pub fn ws2_second(&self) -> u32 {
+        if self.ws2_second_wait() { 1 } else { 8 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::game_pak::BackupType;
+
+    const FRAME_CYCLES: u64 = 1232 * 228;
+
+    fn power_on(seed: u64) -> Bus {
+        let mut bus = Bus::default();
+        bus.fill_power_on(seed);
+        bus
+    }
+
+    /// Program DMA 3, its control register last.
+    fn dma3(bus: &mut Bus, src: u32, dst: u32, count: u16, control: u16) {
+        bus.write32(0x0400_00D4, src);
+        bus.write32(0x0400_00D8, dst);
+        bus.write16(0x0400_00DC, count);
+        bus.write16(0x0400_00DE, control);
+    }
+
+    fn halfwords(bus: &mut Bus, address: u32) -> [u16; 4] {
+        std::array::from_fn(|i| bus.read16(address + i as u32 * 2))
+    }
+
+    /// Halfwords `1..=count` at the start of EWRAM.
+    fn ewram_source(bus: &mut Bus, count: u16) {
+        for i in 0..count {
+            bus.write16(0x0200_0000 + i as u32 * 2, i + 1);
+        }
+    }
+
+    #[test]
+    fn power_on_fill_is_reproducible() {
+        let (a, b, c) = (power_on(7), power_on(7), power_on(8));
+        let contents = |bus: &Bus| (bus.palette_ram, bus.vram.clone(), bus.oam);
+
+        assert!(contents(&a) == contents(&b), "same seed, different contents");
+        assert!(contents(&a) != contents(&c), "different seeds, same contents");
+        assert!(Bus::default().vram.iter().all(|&b| b == 0), "not zero without a fill");
+    }
+
+    #[test]
+    fn dma_enable_after_transfer() {
+        // Immediate DMAs end disabled, repeat or not. So do non-repeating VBlank DMAs,
+        // repeating ones stay enabled for the next VBlank.
+        for (control, enabled_after) in [(0x8000, false), (0x8200, false), (0x9000, false), (0x9200, true)] {
+            let mut bus = Bus::default();
+            ewram_source(&mut bus, 4);
+            dma3(&mut bus, 0x0200_0000, 0x0200_0100, 4, control);
+            assert_eq!(bus.read16(0x0400_00DE), control, "{control:#06X} before the transfer");
+
+            for cycle in 0..FRAME_CYCLES {
+                bus.tick(cycle);
+            }
+
+            assert_eq!(bus.read16(0x0200_0106), 4, "{control:#06X} transfer");
+            assert_eq!(bus.read16(0x0400_00DE) & 0x8000 != 0, enabled_after, "{control:#06X} after");
+            assert_eq!(bus.read16(0x0400_00DE) & 0x7FFF, control & 0x7FFF, "{control:#06X} other bits");
+        }
+    }
+
+    #[test]
+    fn dma_enable_polled() {
+        // A game polls DMA3CNT_H every cycle after starting an HBlank DMA. The transfer runs
+        // whole, the enable bit only clears with all units written.
+        let mut bus = Bus::default();
+        ewram_source(&mut bus, 4);
+        dma3(&mut bus, 0x0200_0000, 0x0200_0100, 4, 0xA000);
+
+        let done = (0..FRAME_CYCLES)
+            .find(|&cycle| {
+                bus.tick(cycle);
+                bus.read16(0x0400_00DE) & 0x8000 == 0
+            })
+            .expect("DMA never finished");
+        assert_eq!(done, 1006, "first HBlank");
+        assert_eq!(halfwords(&mut bus, 0x0200_0100), [1, 2, 3, 4]);
+
+        // Repeating, one unit per HBlank, enabled whenever polled.
+        let mut bus = Bus::default();
+        ewram_source(&mut bus, 4);
+        dma3(&mut bus, 0x0200_0000, 0x0200_0100, 1, 0xA200);
+
+        for cycle in 0..3 * 1232 {
+            bus.tick(cycle);
+            assert_ne!(bus.read16(0x0400_00DE) & 0x8000, 0, "cycle {cycle}");
+        }
+        assert_eq!(halfwords(&mut bus, 0x0200_0100), [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn sram_reads_back_writes() {
+        let mut bus = Bus::default();
+        bus.game_pak.set_backup(BackupType::Flash128);
+        for (address, value) in [(0x0E00_5555, 0xAA), (0x0E00_2AAA, 0x55), (0x0E00_5555, 0x90)] {
+            bus.write8(address, value);
+        }
+        assert_eq!(bus.read8(0x0E00_0000), 0x62, "Flash ID");
+
+        // `--save-type sram`: flat memory, no Flash ID in the first two bytes.
+        bus.game_pak.set_backup(BackupType::Sram);
+        for (offset, value) in [(0, 0x12), (1, 0x34), (0x7FFF, 0x56)] {
+            bus.write8(0x0E00_0000 + offset, value);
+        }
+        assert_eq!([bus.read8(0x0E00_0000), bus.read8(0x0E00_0001)], [0x12, 0x34]);
+        assert_eq!(bus.read8(0x0E00_7FFF), 0x56);
+        // 32 KiB mirrored over the 64 KiB window and into 0x0F.
+        assert_eq!(bus.read8(0x0E00_8000), 0x12);
+        assert_eq!(bus.read8(0x0F00_0001), 0x34);
+        assert_eq!(bus.game_pak.sram[..2], [0x12, 0x34]);
+    }
+
+    #[test]
+    fn peeks_have_no_side_effects() {
+        // Timer 0 overflows every 256 cycles with an IRQ, EEPROM has a read command pending.
+        let setup = || {
+            let mut bus = Bus::default();
+            bus.game_pak.set_backup(BackupType::Eeprom8K);
+            bus.write16(0x0400_0200, 0x0008);
+            bus.write16(0x0400_0100, 0xFF00);
+            bus.write16(0x0400_0102, 0x00C0);
+            for bit in [1, 1, 0, 0, 0, 0, 0, 1, 0] {
+                bus.write16(0x0D00_0000, bit);
+            }
+            bus
+        };
+        let state = |bus: &Bus| {
+            let timers: Vec<u16> = (0x0100..0x0110).step_by(2).map(|addr| bus.timers.peek16(addr)).collect();
+            (timers, bus.iff.0, bus.stall_cycles, bus.next_seq, bus.open_bus, bus.writes)
+        };
+
+        let (mut peeked, mut untouched) = (setup(), setup());
+        for cycle in 0..1000 {
+            peeked.tick(cycle);
+            untouched.tick(cycle);
+
+            let (iff, counter) = (peeked.peek16(0x0400_0202), peeked.peek32(0x0400_0100));
+            assert_eq!(iff, untouched.iff.0, "IF at cycle {cycle}");
+            assert_eq!(counter as u16, untouched.timers[0].counter, "TM0CNT_L at cycle {cycle}");
+            assert_eq!(peeked.peek8(0x0400_0203), (iff >> 8) as u8);
+            peeked.peek16(0x0D00_0000);
+            assert!(state(&peeked) == state(&untouched), "cycle {cycle}");
+        }
+
+        // The timer IRQ was requested, and the EEPROM still answers the whole command.
+        assert_eq!(peeked.read16(0x0400_0202), 0x0008);
+        untouched.read16(0x0400_0202);
+        let bits = |bus: &mut Bus| (0..68).map(|_| bus.read16(0x0D00_0000)).collect::<Vec<_>>();
+        assert_eq!(bits(&mut peeked), bits(&mut untouched));
+        assert!(state(&peeked) == state(&untouched));
+    }
+
+    #[test]
+    fn io_mirror_and_unused_io() {
+        let mut bus = Bus::default();
+        bus.set_open_bus(0x1122_3344);
+
+        // Internal Memory Control repeats every 64 KiB, both ways.
+        bus.write32(0x0400_0800, 0x0D00_0020);
+        assert_eq!(bus.read32(0x0401_0800), 0x0D00_0020);
+        assert_eq!(bus.read32(0x04FF_0800), 0x0D00_0020);
+        bus.write32(0x0402_0800, 0x0E00_0020);
+        assert_eq!(bus.read32(0x0400_0800), 0x0E00_0020);
+        assert_eq!(bus.read8(0x0403_0803), 0x0E);
+
+        // No other register is mirrored, unused IO keeps the last fetched opcode.
+        bus.write16(0x0400_0200, 0x0001);
+        assert_eq!(bus.read16(0x0401_0200), 0x3344);
+        assert_eq!(bus.read8(0x0400_0400), 0x44);
+        assert_eq!(bus.read8(0x0400_0401), 0x33);
+        assert_eq!(bus.read16(0x0400_0402), 0x1122);
+        assert_eq!(bus.read32(0x0400_1000), 0x1122_3344);
+        assert_eq!(bus.read16(0x0400_0200), 0x0001);
+    }
+
+    #[test]
+    fn dma_aligns_to_transfer_width() {
+        // 32-bit from 0x02000002 to 0x02000101 reads and writes whole words at 0x02000000 and 0x02000100.
+        let mut bus = Bus::default();
+        ewram_source(&mut bus, 4);
+        dma3(&mut bus, 0x0200_0002, 0x0200_0101, 2, 0x8400);
+        bus.tick(0);
+        assert_eq!(halfwords(&mut bus, 0x0200_0100), [1, 2, 3, 4]);
+        assert_eq!((bus.dma_channels[3].src, bus.dma_channels[3].dst), (0x0200_0008, 0x0200_0108));
+
+        // 16-bit only drops bit 0.
+        let mut bus = Bus::default();
+        ewram_source(&mut bus, 4);
+        dma3(&mut bus, 0x0200_0003, 0x0200_0103, 2, 0x8000);
+        bus.tick(0);
+        assert_eq!(halfwords(&mut bus, 0x0200_0100), [0, 2, 3, 0]);
+    }
+
+    #[test]
+    fn io_writes_apply_whole_halfwords() {
+        // A 32-bit DMA3CNT write sets the count before the enable in the upper half starts
+        // the transfer, a torn write would start it with a count of 0 (64 Ki units).
+        let mut bus = Bus::default();
+        ewram_source(&mut bus, 4);
+        bus.write32(0x0400_00D4, 0x0200_0000);
+        bus.write32(0x0400_00D8, 0x0200_0100);
+        bus.write32(0x0400_00DC, 0x8000_0003);
+        bus.tick(0);
+        assert_eq!(halfwords(&mut bus, 0x0200_0100), [1, 2, 3, 0]);
+        assert_eq!(bus.dma_channels[3].src, 0x0200_0006);
+
+        // IF acknowledges exactly the bits written, halfwords and bytes alike.
+        bus.iff.0 = 0x3FFF;
+        bus.write16(0x0400_0202, 0x0105);
+        assert_eq!(bus.iff.0, 0x3EFA);
+        bus.write8(0x0400_0203, 0x02);
+        assert_eq!(bus.iff.0, 0x3CFA);
+        bus.write16(0x0400_0202, 0);
+        assert_eq!(bus.iff.0, 0x3CFA);
+
+        // IE and IF in one word.
+        bus.write32(0x0400_0200, 0x00F0_0003);
+        assert_eq!((bus.ie.0, bus.iff.0), (0x0003, 0x3C0A));
+    }
+
+    #[test]
+    fn dma_priority_on_the_same_hblank() {
+        // DMA 2 copies what DMA 1 writes, both on HBlank. Only if DMA 1 goes first does
+        // the data make it through within the first HBlank.
+        let mut bus = Bus::default();
+        ewram_source(&mut bus, 4);
+        bus.write32(0x0400_00C8, 0x0200_0100);
+        bus.write32(0x0400_00CC, 0x0200_0200);
+        bus.write32(0x0400_00D0, 0xA000_0004);
+        bus.write32(0x0400_00BC, 0x0200_0000);
+        bus.write32(0x0400_00C0, 0x0200_0100);
+        bus.write32(0x0400_00C4, 0xA000_0004);
+
+        for cycle in 0..=1006 {
+            bus.tick(cycle);
+        }
+        assert_eq!(halfwords(&mut bus, 0x0200_0100), [1, 2, 3, 4], "DMA 1");
+        assert_eq!(halfwords(&mut bus, 0x0200_0200), [1, 2, 3, 4], "DMA 2");
+    }
+
+    #[test]
+    fn timer_irqs_acknowledged_through_if() {
+        // Timer 0 overflows every 16 cycles, a handler acknowledges every request it sees.
+        let mut bus = Bus::default();
+        bus.write16(0x0400_0100, 0xFFF0);
+        bus.write16(0x0400_0102, 0x00C0);
+
+        let mut requests = 0;
+        for cycle in 0..1600 {
+            bus.tick(cycle);
+            let iff = bus.read16(0x0400_0202);
+            if iff & 0x0008 != 0 {
+                requests += 1;
+                // Writing 0 bits acknowledges nothing.
+                bus.write16(0x0400_0202, !iff & 0x3FFF);
+                assert_eq!(bus.read16(0x0400_0202), iff);
+                bus.write16(0x0400_0202, iff);
+            }
+            assert_eq!(bus.read16(0x0400_0202), 0, "cycle {cycle}");
+        }
+        assert_eq!(requests, 100);
+    }
+
+    #[test]
+    fn vram_word_reads_across_mirrors() {
+        let mut bus = Bus::default();
+        for (i, byte) in bus.vram.iter_mut().enumerate() {
+            *byte = (i ^ i >> 8 ^ i >> 16) as u8;
+        }
+
+        // 96 KiB every 128 KiB, the last 32 KiB of a block repeat the OBJ tiles.
+        let vram = bus.vram.clone();
+        let byte = |address: u32| match (address & 0x1_FFFF) as usize {
+            offset @ 0x1_8000.. => vram[offset - 0x8000],
+            offset => vram[offset],
+        };
+
+        for address in [0x0601_7FFC, 0x0601_8000, 0x0601_FFFC, 0x0602_0000, 0x0603_8004, 0x06FF_FFFC] {
+            let word = u32::from_le_bytes([0, 1, 2, 3].map(|i| byte(address + i)));
+            let bytewise = u32::from_le_bytes([0, 1, 2, 3].map(|i| bus.read8(address + i)));
+            assert_eq!(bus.read32(address), word, "{address:08X}");
+            assert_eq!(bytewise, word, "{address:08X} bytewise");
+        }
+
+        // Writes go through the same mirroring.
+        bus.write32(0x0601_8000, 0x1122_3344);
+        assert_eq!(bus.read32(0x0601_0000), 0x1122_3344);
+        assert_eq!(bus.read16(0x0603_0002), 0x1122);
+    }
+}
@@ -7,6 +7,10 @@ pub struct DMAChannels([DMA; 4]);
 
 impl Mcu for DMAChannels {
     fn read16(&mut self, address: u32) -> u16 {
+        self.peek16(address)
+    }
+
+    fn peek16(&self, address: u32) -> u16 {
         match address {
             0x00BA => u16::from(self[0]),
             0x00C6 => u16::from(self[1]),
@@ -129,19 +133,24 @@ pub struct DMA {
     pub dma_irq: bool,
     pub enable: bool,
 
-    prev_enable: bool,
+    /// A DMAxCNT_H write turned the enable bit on, not yet seen by the bus.
+    enable_edge: bool,
 }
 
 impl DMA {
-    /// Did an edge transition (0 -> 1) happen for the enable bit?
-    pub fn enable_edge(&self) -> bool {
-        !self.prev_enable && self.enable
+    /// Did a write turn the enable bit on (0 -> 1) since the last call?
+    ///
+    /// Rewriting the register with enable already set is no edge and doesn't retrigger.
+    pub fn take_enable_edge(&mut self) -> bool {
+        std::mem::take(&mut self.enable_edge)
     }
 
     /// Update all the bits from the DMAxCNT_H register.
     fn apply_dma_cnt(&mut self, value: u16) {
+        let was_enabled = self.enable;
+
         self.dst_addr_ctrl = AddrControl::try_from((value & 0x60) >> 5).unwrap();
-        self.src_addr_ctrl = AddrControl::try_from((value & 0x110) >> 7).unwrap();
+        self.src_addr_ctrl = AddrControl::try_from((value & 0x180) >> 7).unwrap();
         self.start_timing = StartTiming::try_from((value & 0x3000) >> 12).unwrap();
 
         self.repeat = value & (1 << 9) != 0;
@@ -149,6 +158,8 @@ impl DMA {
         self.pak_drq = value & (1 << 11) != 0;
         self.dma_irq = value & (1 << 14) != 0;
         self.enable = value & (1 << 15) != 0;
+
+        self.enable_edge |= !was_enabled && self.enable;
     }
 }
 
@@ -183,3 +194,34 @@ pub enum StartTiming {
     HBlank,
     Special,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dma_cnt_round_trip() {
+        for src in 0..4u16 {
+            for dst in 0..4u16 {
+                for timing in 0..4u16 {
+                    for flags in 0..32u16 {
+                        // Repeat, 32-bit, Game Pak DRQ, IRQ and enable.
+                        let [repeat, word, drq, irq, enable] = std::array::from_fn(|i| flags >> i & 1);
+                        let value = dst << 5
+                            | src << 7
+                            | repeat << 9
+                            | word << 10
+                            | drq << 11
+                            | timing << 12
+                            | irq << 14
+                            | enable << 15;
+
+                        let mut dma = DMA::default();
+                        dma.apply_dma_cnt(value);
+                        assert_eq!(u16::from(dma), value, "DMAxCNT_H {value:#06X}");
+                    }
+                }
+            }
+        }
+    }
+}
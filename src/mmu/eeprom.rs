@@ -0,0 +1,186 @@
+/// Serial EEPROM (512 B or 8 KiB), accessed one bit at a time through bit 0 of halfwords,
+/// usually by DMA 3.
+///
+/// Commands start with `11` (read) or `10` (write), followed by the address of a 64-bit block
+/// (6 bits for 512 B, 14 bits for 8 KiB), the 64 data bits of a write and a final `0`.
+/// A read command is followed by 4 ignored bits and the 64 data bits, MSB first.
+#[derive(Default)]
+pub struct Eeprom {
+    /// 6 or 14 bits, taken from the length of the first command.
+    pub addr_bits: Option<usize>,
+    /// Bits of the command being sent, first one first.
+    command: Vec<bool>,
+    /// Block of the last read command.
+    read_data: u64,
+    /// Bits of `read_data` left to read, including the 4 ignored ones.
+    read_left: u32,
+}
+
+/// Longest command, a write with a 14-bit address.
+const MAX_COMMAND: usize = 2 + 14 + 64 + 1;
+
+impl Eeprom {
+    /// Next bit of a command. Writes are done as soon as all of their bits are in,
+    /// once the address width is known.
+    pub fn write(&mut self, value: u16, memory: &mut Vec<u8>) {
+        self.command.push(value & 1 != 0);
+
+        if let Some(bits) = self.addr_bits {
+            if self.command.starts_with(&[true, false]) && self.command.len() == bits + 67 {
+                self.execute(memory);
+            }
+        }
+
+        // Garbage, no command is this long.
+        if self.command.len() > MAX_COMMAND {
+            self.command.clear();
+        }
+    }
+
+    /// Next bit of the last read, or 1 (ready) if there is none. Reading ends the command
+    /// being sent, which is how the address width is told from its length at first.
+    pub fn read(&mut self, memory: &mut Vec<u8>) -> u16 {
+        if !self.command.is_empty() {
+            self.execute(memory);
+        }
+
+        match self.read_left {
+            // Writes finish instantly.
+            0 => 1,
+            left => {
+                self.read_left -= 1;
+                match left > 64 {
+                    true => 0,
+                    false => (self.read_data >> (left - 1)) as u16 & 1,
+                }
+            }
+        }
+    }
+
+    fn execute(&mut self, memory: &mut Vec<u8>) {
+        let command = std::mem::take(&mut self.command);
+
+        let (write, addr_bits) = match (command.len(), command.starts_with(&[true, true])) {
+            (9, true) => (false, 6),
+            (17, true) => (false, 14),
+            (73, false) => (true, 6),
+            (81, false) => (true, 14),
+            _ => return,
+        };
+
+        if !command.starts_with(&[true]) {
+            return;
+        }
+
+        match self.addr_bits {
+            None => {
+                self.addr_bits = Some(addr_bits);
+                memory.resize(if addr_bits == 6 { 0x200 } else { 0x2000 }, 0);
+            }
+            Some(bits) if bits != addr_bits => return,
+            Some(_) => {}
+        }
+
+        let value = |bits: &[bool]| bits.iter().fold(0u64, |acc, &bit| acc << 1 | bit as u64);
+
+        // 8 KiB chips only use the low 10 bits of the address.
+        let block = value(&command[2..2 + addr_bits]) as usize % (memory.len() / 8);
+        let block = &mut memory[block * 8..block * 8 + 8];
+
+        if write {
+            block.copy_from_slice(&value(&command[2 + addr_bits..66 + addr_bits]).to_be_bytes());
+        } else {
+            self.read_data = u64::from_be_bytes(block.try_into().unwrap());
+            self.read_left = 68;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: u64 = 0x0123_4567_89AB_CDEF;
+
+    /// Bits of a read (`data` is `None`) or write command, including the final `0`.
+    fn command(block: usize, addr_bits: usize, data: Option<u64>) -> Vec<bool> {
+        let mut bits = vec![true, data.is_none()];
+        bits.extend((0..addr_bits).rev().map(|i| block >> i & 1 != 0));
+        if let Some(data) = data {
+            bits.extend((0..64).rev().map(|i| data >> i & 1 != 0));
+        }
+        bits.push(false);
+        bits
+    }
+
+    fn send(eeprom: &mut Eeprom, memory: &mut Vec<u8>, bits: &[bool]) {
+        bits.iter()
+            .for_each(|&bit| eeprom.write(bit as u16, memory));
+    }
+
+    /// Read a block like a game: the command, then 4 ignored bits and 64 data bits.
+    fn read_block(
+        eeprom: &mut Eeprom,
+        memory: &mut Vec<u8>,
+        block: usize,
+        addr_bits: usize,
+    ) -> u64 {
+        send(eeprom, memory, &command(block, addr_bits, None));
+        let bits: Vec<u16> = (0..68).map(|_| eeprom.read(memory)).collect();
+
+        assert_eq!(bits[..4], [0; 4]);
+        bits[4..].iter().fold(0, |acc, &bit| acc << 1 | bit as u64)
+    }
+
+    #[test]
+    fn round_trips() {
+        for (addr_bits, size, block, stored_at) in [(6, 0x200, 5, 5), (14, 0x2000, 0x13FF, 0x3FF)] {
+            let (mut eeprom, mut memory) = (Eeprom::default(), Vec::new());
+
+            // The first write is only done once the game polls for ready, which ends it.
+            send(
+                &mut eeprom,
+                &mut memory,
+                &command(block, addr_bits, Some(DATA)),
+            );
+            assert_eq!(eeprom.addr_bits, None);
+            assert_eq!(eeprom.read(&mut memory), 1);
+            assert_eq!((eeprom.addr_bits, memory.len()), (Some(addr_bits), size));
+            assert_eq!(
+                memory[stored_at * 8..stored_at * 8 + 8],
+                DATA.to_be_bytes(),
+                "{addr_bits}-bit"
+            );
+
+            assert_eq!(
+                read_block(&mut eeprom, &mut memory, block, addr_bits),
+                DATA,
+                "{addr_bits}-bit"
+            );
+            assert_eq!(eeprom.read(&mut memory), 1, "ready after the read");
+        }
+    }
+
+    #[test]
+    fn width_is_locked_by_the_first_command() {
+        let (mut eeprom, mut memory) = (Eeprom::default(), Vec::new());
+        assert_eq!(read_block(&mut eeprom, &mut memory, 0, 14), 0);
+        assert_eq!((eeprom.addr_bits, memory.len()), (Some(14), 0x2000));
+
+        // Known width, the write is done with its last bit.
+        send(&mut eeprom, &mut memory, &command(1, 14, Some(DATA)));
+        assert_eq!(memory[8..16], DATA.to_be_bytes());
+
+        // 6-bit commands don't fit anymore and are dropped.
+        send(&mut eeprom, &mut memory, &command(1, 6, Some(!DATA)));
+        assert_eq!(eeprom.read(&mut memory), 1);
+        send(&mut eeprom, &mut memory, &command(1, 6, None));
+        assert_eq!(
+            eeprom.read(&mut memory),
+            1,
+            "read data of an ignored command"
+        );
+        assert_eq!((eeprom.addr_bits, memory.len()), (Some(14), 0x2000));
+        assert_eq!(read_block(&mut eeprom, &mut memory, 1, 14), DATA);
+    }
+}
@@ -0,0 +1,33 @@
+use super::Mcu;
+
+/// Plain Vec-backed memory without any IO, mirroring or wait states.
+///
+/// Stand-in for `Bus` to run the CPU in isolation, out of range accesses read 0 and ignore writes.
+#[derive(Default, Clone)]
+pub struct FlatMem(pub Vec<u8>);
+
+impl FlatMem {
+    pub fn new(size: usize) -> Self {
+        Self(vec![0; size])
+    }
+}
+
+impl Mcu for FlatMem {
+    fn read8(&mut self, address: u32) -> u8 {
+        self.peek8(address)
+    }
+
+    fn write8(&mut self, address: u32, value: u8) {
+        if let Some(byte) = self.0.get_mut(address as usize) {
+            *byte = value;
+        }
+    }
+
+    fn peek16(&self, address: u32) -> u16 {
+        u16::from_le_bytes([self.peek8(address), self.peek8(address.wrapping_add(1))])
+    }
+
+    fn peek8(&self, address: u32) -> u8 {
+        self.0.get(address as usize).copied().unwrap_or(0)
+    }
+}
@@ -0,0 +1,105 @@
+use super::game_pak::BackupType;
+
+/// Known per-game overrides, keyed by the game code at `0xAC` in the ROM header.
+#[derive(Debug)]
+pub struct GameInfo {
+    pub code: &'static str,
+    pub backup: BackupType,
+    pub rtc: bool,
+    pub solar_sensor: bool,
+    pub rumble: bool,
+    pub gyro: bool,
+    pub tilt: bool,
+    /// Small ROM mirrored over the whole cartridge space (Classic NES Series).
+    pub rom_mirror: bool,
+}
+
+const fn game(code: &'static str, backup: BackupType) -> GameInfo {
+    GameInfo {
+        code,
+        backup,
+        rtc: false,
+        solar_sensor: false,
+        rumble: false,
+        gyro: false,
+        tilt: false,
+        rom_mirror: false,
+    }
+}
+
+#[rustfmt::skip]
+pub const GAMES: &[GameInfo] = &[
+    // Pokémon Ruby / Sapphire / Emerald.
+    GameInfo { rtc: true, ..game("AXVE", BackupType::Flash128) },
+    GameInfo { rtc: true, ..game("AXPE", BackupType::Flash128) },
+    GameInfo { rtc: true, ..game("BPEE", BackupType::Flash128) },
+    // Pokémon FireRed / LeafGreen.
+    game("BPRE", BackupType::Flash128),
+    game("BPGE", BackupType::Flash128),
+    // Boktai 1 and 2.
+    GameInfo { rtc: true, solar_sensor: true, ..game("U3IE", BackupType::Eeprom8K) },
+    GameInfo { rtc: true, solar_sensor: true, ..game("U32E", BackupType::Eeprom8K) },
+    // Yoshi Topsy-Turvy.
+    GameInfo { tilt: true, ..game("KYGE", BackupType::Eeprom8K) },
+    // WarioWare: Twisted!
+    GameInfo { gyro: true, rumble: true, ..game("RZWE", BackupType::Sram) },
+    // Drill Dozer.
+    GameInfo { rumble: true, ..game("V49E", BackupType::Sram) },
+    // Classic NES Series: Super Mario Bros.
+    GameInfo { rom_mirror: true, ..game("FSME", BackupType::Eeprom512) },
+];
+
+/// Game code from the ROM header, e.g. `AXVE`.
+pub fn game_code(rom: &[u8]) -> Option<&str> {
+    rom.get(0xAC..0xB0).and_then(|code| std::str::from_utf8(code).ok())
+}
+
+pub fn lookup(rom: &[u8]) -> Option<&'static GameInfo> {
+    let code = game_code(rom)?;
+    GAMES.iter().find(|game| game.code == code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arm::interpreter::arm7tdmi::Arm7TDMI;
+
+    /// ROM with a game code and the ID string of the SRAM save library.
+    fn rom(code: &[u8; 4]) -> Vec<u8> {
+        let mut rom = vec![0; 0x200];
+        rom[0xAC..0xB0].copy_from_slice(code);
+        rom[0x100..0x106].copy_from_slice(b"SRAM_V");
+        rom
+    }
+
+    fn backup(cpu: &Arm7TDMI) -> (BackupType, usize) {
+        (cpu.bus.game_pak.backup, cpu.bus.game_pak.sram.len())
+    }
+
+    #[test]
+    fn lookup_by_game_code() {
+        let info = lookup(&rom(b"U3IE")).unwrap();
+        assert!(info.rtc && info.solar_sensor);
+        assert_eq!(info.backup, BackupType::Eeprom8K);
+
+        assert!(lookup(&rom(b"ZZZZ")).is_none());
+        assert!(lookup(&[0; 0xAE]).is_none(), "header cut short");
+    }
+
+    #[test]
+    fn precedence() {
+        // Unknown game, the save library's ID string is found.
+        let cpu = Arm7TDMI::new(&rom(b"ZZZZ"));
+        assert!(cpu.bus.game_pak.info.is_none());
+        assert_eq!(backup(&cpu), (BackupType::Sram, 0x8000));
+
+        // The database wins over the ID string.
+        let mut cpu = Arm7TDMI::new(&rom(b"AXVE"));
+        assert!(cpu.bus.game_pak.info.is_some_and(|info| info.rtc));
+        assert_eq!(backup(&cpu), (BackupType::Flash128, 0x2_0000));
+
+        // `--save-type` wins over both, like `main` applies it after loading.
+        cpu.bus.game_pak.set_backup(BackupType::Eeprom512);
+        assert_eq!(backup(&cpu), (BackupType::Eeprom512, 0x200));
+    }
+}
@@ -1,12 +1,394 @@
+use std::{io::ErrorKind, path::Path};
+
 use crate::box_arr;
 
+use super::{eeprom::Eeprom, game_db::GameInfo};
+
 pub struct GamePak {
     pub rom: Box<[u8; 0x0200_0000]>,
+    /// Size of the ROM before it was padded to 32 MiB.
+    pub rom_size: usize,
     pub sram: Vec<u8>,
+    /// Kind of save memory on the cartridge.
+    pub backup: BackupType,
+    /// Protocol state of EEPROM backup, `sram` holds its memory.
+    pub eeprom: Eeprom,
+    /// Command state of Flash backup, `sram` holds its memory.
+    pub flash: Flash,
+    /// Game database entry, if the game code is known.
+    pub info: Option<&'static GameInfo>,
 }
 
 impl Default for GamePak {
     fn default() -> Self {
-        Self { rom: box_arr![0xFF; 0x0200_0000], sram: Default::default() }
+        Self {
+            rom: box_arr![0xFF; 0x0200_0000],
+            rom_size: 0,
+            sram: vec![0; BackupType::default().size()],
+            backup: BackupType::default(),
+            eeprom: Eeprom::default(),
+            flash: Flash::default(),
+            info: None,
+        }
+    }
+}
+
+impl GamePak {
+    /// Switch to another kind of backup memory, with empty memory of its size.
+    pub fn set_backup(&mut self, backup: BackupType) {
+        self.backup = backup;
+        self.sram = vec![0; backup.size()];
+        self.eeprom = Eeprom::default();
+        self.flash = Flash::default();
+    }
+
+    /// EEPROM answers in the whole `0x0D` region, or only in its last 256 bytes
+    /// if the ROM is bigger than 16 MiB and needs the rest.
+    pub fn is_eeprom(&self, address: u32) -> bool {
+        matches!(self.backup, BackupType::Eeprom512 | BackupType::Eeprom8K)
+            && address >> 24 == 0x0D
+            && (self.rom_size <= 0x0100_0000 || address >= 0x0DFF_FF00)
+    }
+
+    pub fn read_eeprom(&mut self) -> u16 {
+        let bit = self.eeprom.read(&mut self.sram);
+        self.sync_eeprom_size();
+        bit
+    }
+
+    pub fn write_eeprom(&mut self, value: u16) {
+        self.eeprom.write(value, &mut self.sram);
+        self.sync_eeprom_size();
+    }
+
+    /// The EEPROM size is only certain once the game sent its first command.
+    fn sync_eeprom_size(&mut self) {
+        match self.eeprom.addr_bits {
+            Some(6) => self.backup = BackupType::Eeprom512,
+            Some(_) => self.backup = BackupType::Eeprom8K,
+            None => {}
+        }
+    }
+
+    /// Read from the backup region (`0x0E000000-0x0FFFFFFF`).
+    pub fn read_backup(&self, address: u32) -> u8 {
+        let offset = address as usize % 0x0001_0000;
+
+        match self.backup {
+            // 32 KiB mirrored over the 64 KiB window.
+            BackupType::Sram => self.sram.get(offset % 0x8000).copied().unwrap_or(0xFF),
+            BackupType::Flash64 | BackupType::Flash128 => self.flash.read(offset, &self.sram),
+            // EEPROM sits in the ROM region, nothing drives the bus here (pulled up).
+            BackupType::None | BackupType::Eeprom512 | BackupType::Eeprom8K => 0xFF,
+        }
+    }
+
+    /// Write to the backup region (`0x0E000000-0x0FFFFFFF`).
+    pub fn write_backup(&mut self, address: u32, value: u8) {
+        let offset = address as usize % 0x0001_0000;
+
+        match self.backup {
+            BackupType::Sram => {
+                if let Some(byte) = self.sram.get_mut(offset % 0x8000) {
+                    *byte = value;
+                }
+            }
+            BackupType::Flash64 | BackupType::Flash128 => {
+                self.flash.write(offset, value, &mut self.sram)
+            }
+            BackupType::None | BackupType::Eeprom512 | BackupType::Eeprom8K => {}
+        }
+    }
+
+    /// Load a save file into `sram`, shorter files leave the rest zeroed. No save file yet is fine.
+    pub fn load_save(&mut self, path: &Path) -> Result<(), String> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(format!("{}: {e}", path.display())),
+        };
+
+        let len = data.len().min(self.sram.len());
+        self.sram[..len].copy_from_slice(&data[..len]);
+        Ok(())
+    }
+
+    /// Write the backup memory to a save file, nothing for carts without one.
+    pub fn write_save(&self, path: &Path) -> Result<(), String> {
+        let size = self.backup.size().min(self.sram.len());
+        if size == 0 {
+            return Ok(());
+        }
+
+        std::fs::write(path, &self.sram[..size]).map_err(|e| format!("{}: {e}", path.display()))
+    }
+}
+
+/// Flash chip (64 or 128 KiB, told by the size of its memory), driven by commands.
+///
+/// Every command is written to `0x5555` after the unlock sequence `0xAA` to `0x5555`,
+/// `0x55` to `0x2AAA`. Erasing takes two commands, `0x80` and then `0x10` (chip) or
+/// `0x30` written to the sector to erase. 128 KiB chips show one 64 KiB bank at a time.
+#[derive(Default)]
+pub struct Flash {
+    phase: FlashPhase,
+    /// Manufacturer and device ID are read at 0 and 1 instead of memory.
+    id_mode: bool,
+    /// The last command was `0x80`, the next one may erase.
+    erase: bool,
+    /// 64 KiB bank of 128 KiB chips.
+    bank: usize,
+}
+
+#[derive(Default, Clone, Copy, PartialEq)]
+enum FlashPhase {
+    #[default]
+    Ready,
+    /// Got `0xAA` at `0x5555`.
+    Unlock,
+    /// Got `0x55` at `0x2AAA`, the next write is a command.
+    Command,
+    /// The next write programs a byte.
+    Write,
+    /// The next write to 0 switches the bank.
+    Bank,
+}
+
+impl Flash {
+    /// Sanyo for 128 KiB, Macronix for 64 KiB, games tell the size from the ID.
+    fn id(memory: &[u8]) -> [u8; 2] {
+        match memory.len() > 0x1_0000 {
+            true => [0x62, 0x13],
+            false => [0xC2, 0x1C],
+        }
+    }
+
+    pub fn read(&self, offset: usize, memory: &[u8]) -> u8 {
+        match (self.id_mode, offset) {
+            (true, 0..=1) => Self::id(memory)[offset],
+            _ => memory.get(self.bank * 0x1_0000 + offset).copied().unwrap_or(0xFF),
+        }
+    }
+
+    pub fn write(&mut self, offset: usize, value: u8, memory: &mut [u8]) {
+        let bank = self.bank * 0x1_0000;
+
+        self.phase = match (self.phase, offset, value) {
+            (FlashPhase::Ready, 0x5555, 0xAA) => FlashPhase::Unlock,
+            (FlashPhase::Ready, _, 0xF0) => {
+                self.id_mode = false;
+                FlashPhase::Ready
+            }
+            (FlashPhase::Unlock, 0x2AAA, 0x55) => FlashPhase::Command,
+            (FlashPhase::Command, _, _) => {
+                let erase = std::mem::take(&mut self.erase);
+
+                match (offset, value) {
+                    (0x5555, 0xA0) => FlashPhase::Write,
+                    (0x5555, 0xB0) if memory.len() > 0x1_0000 => FlashPhase::Bank,
+                    (0x5555, 0x90) => {
+                        self.id_mode = true;
+                        FlashPhase::Ready
+                    }
+                    (0x5555, 0xF0) => {
+                        self.id_mode = false;
+                        FlashPhase::Ready
+                    }
+                    (0x5555, 0x80) => {
+                        self.erase = true;
+                        FlashPhase::Ready
+                    }
+                    (0x5555, 0x10) if erase => {
+                        memory.fill(0xFF);
+                        FlashPhase::Ready
+                    }
+                    (sector, 0x30) if erase => {
+                        let start = bank + (sector & 0xF000);
+                        if let Some(sector) = memory.get_mut(start..start + 0x1000) {
+                            sector.fill(0xFF);
+                        }
+                        FlashPhase::Ready
+                    }
+                    _ => FlashPhase::Ready,
+                }
+            }
+            (FlashPhase::Write, _, _) => {
+                if let Some(byte) = memory.get_mut(bank + offset) {
+                    *byte = value;
+                }
+                FlashPhase::Ready
+            }
+            (FlashPhase::Bank, 0, _) => {
+                self.bank = value as usize & 1;
+                FlashPhase::Ready
+            }
+            _ => FlashPhase::Ready,
+        };
     }
-}
\ No newline at end of file
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub enum BackupType {
+    None,
+    #[default]
+    Sram,
+    Flash64,
+    Flash128,
+    Eeprom512,
+    Eeprom8K,
+}
+
+impl BackupType {
+    /// ID strings the save libraries of the SDK leave in the ROM, always word aligned.
+    const MARKERS: [(&'static [u8], BackupType); 6] = [
+        (b"EEPROM_V", BackupType::Eeprom8K),
+        (b"SRAM_V", BackupType::Sram),
+        (b"SRAM_F_V", BackupType::Sram),
+        (b"FLASH_V", BackupType::Flash64),
+        (b"FLASH512_V", BackupType::Flash64),
+        (b"FLASH1M_V", BackupType::Flash128),
+    ];
+
+    /// Find the save library's ID string in `rom`, `None` if the game links none of them.
+    ///
+    /// The string doesn't tell the EEPROM size, EEPROM is detected as the larger 8 KiB one
+    /// until the game's first command tells (see `Eeprom::addr_bits`).
+    pub fn detect(rom: &[u8]) -> Option<Self> {
+        (0..rom.len()).step_by(4).find_map(|i| {
+            Self::MARKERS
+                .iter()
+                .find(|(marker, _)| rom[i..].starts_with(marker))
+                .map(|(_, backup)| *backup)
+        })
+    }
+
+    /// Size of the backup memory and its save file in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            BackupType::None => 0,
+            BackupType::Sram => 0x8000,
+            BackupType::Flash64 => 0x10000,
+            BackupType::Flash128 => 0x20000,
+            BackupType::Eeprom512 => 0x200,
+            BackupType::Eeprom8K => 0x2000,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(flash: &mut Flash, memory: &mut [u8], command: u8) {
+        flash.write(0x5555, 0xAA, memory);
+        flash.write(0x2AAA, 0x55, memory);
+        flash.write(0x5555, command, memory);
+    }
+
+    fn program(flash: &mut Flash, memory: &mut [u8], offset: usize, value: u8) {
+        command(flash, memory, 0xA0);
+        flash.write(offset, value, memory);
+    }
+
+    fn switch_bank(flash: &mut Flash, memory: &mut [u8], bank: u8) {
+        command(flash, memory, 0xB0);
+        flash.write(0, bank, memory);
+    }
+
+    #[test]
+    fn flash_id() {
+        for (size, id) in [(0x1_0000, [0xC2, 0x1C]), (0x2_0000, [0x62, 0x13])] {
+            let (mut flash, mut memory) = (Flash::default(), vec![0xFF; size]);
+            memory[..2].copy_from_slice(&[0x12, 0x34]);
+
+            command(&mut flash, &mut memory, 0x90);
+            assert_eq!([flash.read(0, &memory), flash.read(1, &memory)], id);
+            assert_eq!(flash.read(2, &memory), 0xFF);
+
+            command(&mut flash, &mut memory, 0xF0);
+            assert_eq!(
+                [flash.read(0, &memory), flash.read(1, &memory)],
+                [0x12, 0x34]
+            );
+
+            // A lone 0xF0 leaves ID mode as well.
+            command(&mut flash, &mut memory, 0x90);
+            flash.write(0x1234, 0xF0, &mut memory);
+            assert_eq!(flash.read(0, &memory), 0x12);
+        }
+    }
+
+    #[test]
+    fn flash_program_needs_unlock() {
+        let (mut flash, mut memory) = (Flash::default(), vec![0xFF; 0x1_0000]);
+
+        flash.write(0x1234, 0x42, &mut memory);
+        flash.write(0x5555, 0xAA, &mut memory);
+        flash.write(0x2AAB, 0x55, &mut memory);
+        flash.write(0x5555, 0xA0, &mut memory);
+        flash.write(0x1234, 0x42, &mut memory);
+        assert_eq!(flash.read(0x1234, &memory), 0xFF, "no or broken unlock");
+
+        program(&mut flash, &mut memory, 0x1234, 0x42);
+        assert_eq!(flash.read(0x1234, &memory), 0x42);
+
+        // Only the one byte after the command is programmed.
+        flash.write(0x1235, 0x43, &mut memory);
+        assert_eq!(flash.read(0x1235, &memory), 0xFF);
+    }
+
+    #[test]
+    fn flash_erase() {
+        let (mut flash, mut memory) = (Flash::default(), vec![0; 0x1_0000]);
+
+        // 0x30 without 0x80 before erases nothing.
+        command(&mut flash, &mut memory, 0x30);
+        assert!(memory.iter().all(|&byte| byte == 0));
+
+        command(&mut flash, &mut memory, 0x80);
+        flash.write(0x5555, 0xAA, &mut memory);
+        flash.write(0x2AAA, 0x55, &mut memory);
+        flash.write(0x3456, 0x30, &mut memory);
+        assert!(memory[0x3000..0x4000].iter().all(|&byte| byte == 0xFF));
+        assert!(memory[..0x3000]
+            .iter()
+            .chain(&memory[0x4000..])
+            .all(|&byte| byte == 0));
+
+        // The erase flag is used up by the sector erase.
+        command(&mut flash, &mut memory, 0x10);
+        assert_eq!(flash.read(0, &memory), 0);
+
+        command(&mut flash, &mut memory, 0x80);
+        command(&mut flash, &mut memory, 0x10);
+        assert!(memory.iter().all(|&byte| byte == 0xFF));
+    }
+
+    #[test]
+    fn flash_banks() {
+        let (mut flash, mut memory) = (Flash::default(), vec![0xFF; 0x2_0000]);
+        switch_bank(&mut flash, &mut memory, 1);
+        program(&mut flash, &mut memory, 0x10, 0x42);
+        assert_eq!(flash.read(0x10, &memory), 0x42);
+        assert_eq!((memory[0x10], memory[0x1_0010]), (0xFF, 0x42));
+
+        switch_bank(&mut flash, &mut memory, 0);
+        assert_eq!(flash.read(0x10, &memory), 0xFF);
+        program(&mut flash, &mut memory, 0x10, 0x24);
+        assert_eq!((memory[0x10], memory[0x1_0010]), (0x24, 0x42));
+
+        // Sectors are erased in the current bank.
+        switch_bank(&mut flash, &mut memory, 1);
+        command(&mut flash, &mut memory, 0x80);
+        flash.write(0x5555, 0xAA, &mut memory);
+        flash.write(0x2AAA, 0x55, &mut memory);
+        flash.write(0, 0x30, &mut memory);
+        assert_eq!((memory[0x10], memory[0x1_0010]), (0x24, 0xFF));
+
+        // 64 KiB chips have no banks.
+        let (mut flash, mut memory) = (Flash::default(), vec![0xFF; 0x1_0000]);
+        switch_bank(&mut flash, &mut memory, 1);
+        program(&mut flash, &mut memory, 0x10, 0x42);
+        assert_eq!(memory[0x10], 0x42);
+    }
+}
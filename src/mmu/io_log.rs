@@ -0,0 +1,182 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    ops::RangeInclusive,
+};
+
+/// IO register names by offset from `0x04000000` with their size in bytes.
+#[rustfmt::skip]
+pub const IO_REGISTERS: &[(u32, u32, &str)] = &[
+    (0x000, 2, "DISPCNT"), (0x002, 2, "GREENSWAP"), (0x004, 2, "DISPSTAT"), (0x006, 2, "VCOUNT"),
+    (0x008, 2, "BG0CNT"), (0x00A, 2, "BG1CNT"), (0x00C, 2, "BG2CNT"), (0x00E, 2, "BG3CNT"),
+    (0x010, 2, "BG0HOFS"), (0x012, 2, "BG0VOFS"), (0x014, 2, "BG1HOFS"), (0x016, 2, "BG1VOFS"),
+    (0x018, 2, "BG2HOFS"), (0x01A, 2, "BG2VOFS"), (0x01C, 2, "BG3HOFS"), (0x01E, 2, "BG3VOFS"),
+    (0x020, 2, "BG2PA"), (0x022, 2, "BG2PB"), (0x024, 2, "BG2PC"), (0x026, 2, "BG2PD"),
+    (0x028, 4, "BG2X"), (0x02C, 4, "BG2Y"),
+    (0x030, 2, "BG3PA"), (0x032, 2, "BG3PB"), (0x034, 2, "BG3PC"), (0x036, 2, "BG3PD"),
+    (0x038, 4, "BG3X"), (0x03C, 4, "BG3Y"),
+    (0x040, 2, "WIN0H"), (0x042, 2, "WIN1H"), (0x044, 2, "WIN0V"), (0x046, 2, "WIN1V"),
+    (0x048, 2, "WININ"), (0x04A, 2, "WINOUT"), (0x04C, 2, "MOSAIC"),
+    (0x050, 2, "BLDCNT"), (0x052, 2, "BLDALPHA"), (0x054, 2, "BLDY"),
+    (0x060, 2, "SOUND1CNT_L"), (0x062, 2, "SOUND1CNT_H"), (0x064, 2, "SOUND1CNT_X"),
+    (0x068, 2, "SOUND2CNT_L"), (0x06C, 2, "SOUND2CNT_H"),
+    (0x070, 2, "SOUND3CNT_L"), (0x072, 2, "SOUND3CNT_H"), (0x074, 2, "SOUND3CNT_X"),
+    (0x078, 2, "SOUND4CNT_L"), (0x07C, 2, "SOUND4CNT_H"),
+    (0x080, 2, "SOUNDCNT_L"), (0x082, 2, "SOUNDCNT_H"), (0x084, 2, "SOUNDCNT_X"), (0x088, 4, "SOUNDBIAS"),
+    (0x090, 16, "WAVE_RAM"), (0x0A0, 4, "FIFO_A"), (0x0A4, 4, "FIFO_B"),
+    (0x0B0, 4, "DMA0SAD"), (0x0B4, 4, "DMA0DAD"), (0x0B8, 2, "DMA0CNT_L"), (0x0BA, 2, "DMA0CNT_H"),
+    (0x0BC, 4, "DMA1SAD"), (0x0C0, 4, "DMA1DAD"), (0x0C4, 2, "DMA1CNT_L"), (0x0C6, 2, "DMA1CNT_H"),
+    (0x0C8, 4, "DMA2SAD"), (0x0CC, 4, "DMA2DAD"), (0x0D0, 2, "DMA2CNT_L"), (0x0D2, 2, "DMA2CNT_H"),
+    (0x0D4, 4, "DMA3SAD"), (0x0D8, 4, "DMA3DAD"), (0x0DC, 2, "DMA3CNT_L"), (0x0DE, 2, "DMA3CNT_H"),
+    (0x100, 2, "TM0CNT_L"), (0x102, 2, "TM0CNT_H"), (0x104, 2, "TM1CNT_L"), (0x106, 2, "TM1CNT_H"),
+    (0x108, 2, "TM2CNT_L"), (0x10A, 2, "TM2CNT_H"), (0x10C, 2, "TM3CNT_L"), (0x10E, 2, "TM3CNT_H"),
+    (0x120, 4, "SIODATA32"), (0x128, 2, "SIOCNT"), (0x12A, 2, "SIODATA8"),
+    (0x130, 2, "KEYINPUT"), (0x132, 2, "KEYCNT"), (0x134, 2, "RCNT"),
+    (0x140, 2, "JOYCNT"), (0x150, 4, "JOY_RECV"), (0x154, 4, "JOY_TRANS"), (0x158, 2, "JOYSTAT"),
+    (0x200, 2, "IE"), (0x202, 2, "IF"), (0x204, 2, "WAITCNT"), (0x208, 2, "IME"),
+    (0x300, 1, "POSTFLG"), (0x301, 1, "HALTCNT"), (0x800, 4, "IMC"),
+];
+
+/// Name of the IO register that `address` belongs to, if any.
+pub fn io_register_name(address: u32) -> Option<&'static str> {
+    let offset = match address & 0xFFFF {
+        addr @ 0x0800..=0x0803 => addr,
+        _ => address.wrapping_sub(0x0400_0000),
+    };
+
+    IO_REGISTERS
+        .iter()
+        .find(|(start, size, _)| (*start..start + size).contains(&offset))
+        .map(|(_, _, name)| *name)
+}
+
+#[derive(Clone, Copy)]
+pub enum IoAccess {
+    Read,
+    Write,
+}
+
+/// Only log accesses to these register names or address ranges, everything if empty.
+#[derive(Default, Clone)]
+pub struct IoFilter {
+    pub names: Vec<String>,
+    pub ranges: Vec<RangeInclusive<u32>>,
+}
+
+impl IoFilter {
+    pub fn matches(&self, address: u32, name: Option<&str>) -> bool {
+        if self.names.is_empty() && self.ranges.is_empty() {
+            return true;
+        }
+
+        self.ranges.iter().any(|r| r.contains(&address))
+            || name.is_some_and(|name| self.names.iter().any(|n| n.eq_ignore_ascii_case(name)))
+    }
+}
+
+/// Appends every IO register access to a file as
+/// `frame:line:cycle RW addr value size name`.
+pub struct IoLog {
+    writer: BufWriter<File>,
+    filter: IoFilter,
+}
+
+impl IoLog {
+    pub fn create(path: &str, filter: IoFilter) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("Could not create {path}: {e}"))?;
+
+        Ok(Self {
+            writer: BufWriter::new(file),
+            filter,
+        })
+    }
+
+    /// `size` in bits, `(frame, line, cycle)` is the current PPU position.
+    pub fn record(
+        &mut self,
+        (frame, line, cycle): (u64, u8, u16),
+        access: IoAccess,
+        address: u32,
+        value: u32,
+        size: u32,
+    ) -> std::io::Result<()> {
+        let name = io_register_name(address);
+        if !self.filter.matches(address, name) {
+            return Ok(());
+        }
+
+        writeln!(
+            self.writer,
+            "{}",
+            format_entry((frame, line, cycle), access, address, value, size, name)
+        )
+    }
+}
+
+pub fn format_entry(
+    (frame, line, cycle): (u64, u8, u16),
+    access: IoAccess,
+    address: u32,
+    value: u32,
+    size: u32,
+    name: Option<&str>,
+) -> String {
+    let rw = match access {
+        IoAccess::Read => 'R',
+        IoAccess::Write => 'W',
+    };
+
+    format!(
+        "{frame}:{line}:{cycle} {rw} {address:08X} {value:0width$X} {size} {}",
+        name.unwrap_or("?"),
+        width = size as usize / 4
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_names() {
+        assert_eq!(io_register_name(0x0400_0000), Some("DISPCNT"));
+        assert_eq!(io_register_name(0x0400_0029), Some("BG2X"));
+        assert_eq!(io_register_name(0x0400_0095), Some("WAVE_RAM"));
+        assert_eq!(io_register_name(0x0400_0301), Some("HALTCNT"));
+        assert_eq!(io_register_name(0x0400_0056), None);
+        // IMC is mirrored every 64 KiB, nothing else is.
+        assert_eq!(io_register_name(0x0400_0802), Some("IMC"));
+        assert_eq!(io_register_name(0x04FF_0800), Some("IMC"));
+        assert_eq!(io_register_name(0x0401_0000), None);
+    }
+
+    #[test]
+    fn filter() {
+        assert!(IoFilter::default().matches(0x0400_0056, None));
+
+        let filter = IoFilter {
+            names: vec!["dispcnt".to_string(), "IF".to_string()],
+            ranges: vec![0x0400_0100..=0x0400_010F],
+        };
+        let matches = |address| filter.matches(address, io_register_name(address));
+
+        assert!(matches(0x0400_0000), "names ignore the case");
+        assert!(matches(0x0400_0203), "any byte of a named register");
+        assert!(matches(0x0400_0100) && matches(0x0400_010F), "range ends are inclusive");
+        assert!(!matches(0x0400_0110));
+        assert!(!matches(0x0400_0004));
+        assert!(!matches(0x0400_0200));
+    }
+
+    #[test]
+    fn entries() {
+        let entry = |access, address, value, size| {
+            format_entry((12, 159, 1231), access, address, value, size, io_register_name(address))
+        };
+
+        assert_eq!(entry(IoAccess::Write, 0x0400_0000, 0x0403, 16), "12:159:1231 W 04000000 0403 16 DISPCNT");
+        assert_eq!(entry(IoAccess::Read, 0x0400_0202, 0x1, 8), "12:159:1231 R 04000202 01 8 IF");
+        assert_eq!(entry(IoAccess::Write, 0x0400_00D4, 0x0800_0000, 32), "12:159:1231 W 040000D4 08000000 32 DMA3SAD");
+        assert_eq!(entry(IoAccess::Read, 0x0400_0056, 0, 16), "12:159:1231 R 04000056 0000 16 ?");
+    }
+}
@@ -80,4 +80,37 @@ impl IF {
     pub fn set_dma(&mut self, id: usize) {
         self.0 |= 1 << (id + 8);
     }
+
+    /// Writing 1 to a bit acknowledges (clears) that request, 0 leaves it as is.
+    /// Only the 14 IRQ bits exist, the upper two always read 0.
+    pub fn acknowledge(&mut self, mask: u16) {
+        self.0 &= !mask & 0x3FFF;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acknowledge_clears_written_ones() {
+        let mut iff = IF(0x3FFF);
+        iff.acknowledge(0x0000);
+        assert_eq!(iff.0, 0x3FFF);
+
+        iff.acknowledge(0x0109);
+        assert_eq!(iff.0, 0x3EF6);
+        iff.acknowledge(0x0109);
+        assert_eq!(iff.0, 0x3EF6, "already clear");
+
+        // Requests after an acknowledge stay until acknowledged themselves.
+        iff.set_timer(0);
+        iff.set_dma(0);
+        assert_eq!(iff.0, 0x3FFE);
+
+        // Bits 14 and 15 don't exist.
+        iff.0 = 0xFFFF;
+        iff.acknowledge(0x0001);
+        assert_eq!(iff.0, 0x3FFE);
+    }
 }
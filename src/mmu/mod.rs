@@ -1,7 +1,14 @@
+pub mod access_stats;
 pub mod bus;
 pub mod dma;
+pub mod eeprom;
+pub mod flat_mem;
+pub mod game_db;
 pub mod game_pak;
+pub mod io_log;
 pub mod irq;
+pub mod page_table;
+pub mod sparse_mem;
 pub mod timer;
 
 /// Create array on the heap, ideally without blowing the stack first.
@@ -43,9 +50,9 @@ pub trait Mcu {
     fn read32(&mut self, address: u32) -> u32 {
         u32::from_le_bytes([
             self.read8(address),
-            self.read8(address + 1),
-            self.read8(address + 2),
-            self.read8(address + 3),
+            self.read8(address.wrapping_add(1)),
+            self.read8(address.wrapping_add(2)),
+            self.read8(address.wrapping_add(3)),
         ])
     }
 
@@ -53,27 +60,48 @@ pub trait Mcu {
         let [a, b, c, d] = value.to_le_bytes();
 
         self.write8(address, a);
-        self.write8(address + 1, b);
-        self.write8(address + 2, c);
-        self.write8(address + 3, d);
+        self.write8(address.wrapping_add(1), b);
+        self.write8(address.wrapping_add(2), c);
+        self.write8(address.wrapping_add(3), d);
     }
 
     fn read16(&mut self, address: u32) -> u16 {
-        u16::from_le_bytes([self.read8(address), self.read8(address + 1)])
+        u16::from_le_bytes([self.read8(address), self.read8(address.wrapping_add(1))])
     }
     fn write16(&mut self, address: u32, value: u16) {
         let [a, b] = value.to_le_bytes();
 
         self.write8(address, a);
-        self.write8(address + 1, b);
+        self.write8(address.wrapping_add(1), b);
     }
 
     fn read8(&mut self, address: u32) -> u8;
     fn write8(&mut self, address: u32, value: u8);
 
+    /// Called with every opcode the CPU fetches (THUMB opcodes twice, as the bus sees them),
+    /// which is what reads from unmapped memory return (open bus).
+    fn set_open_bus(&mut self, _value: u32) {}
+
     /// Raw read - meaning no effect on the bus and will also
     /// read non-readable values, just for convenience (I/O).
     fn raw_read16(&mut self, _address: u32) -> u16 {
         unimplemented!()
     }
+
+    /// Debug read - returns what a bus read would return
+    /// but is guaranteed to have no side effects (I/O).
+    fn peek16(&self, address: u32) -> u16;
+
+    fn peek8(&self, address: u32) -> u8 {
+        (self.peek16(address & !1) >> ((address & 1) * 8)) as u8
+    }
+
+    fn peek32(&self, address: u32) -> u32 {
+        u32::from_le_bytes([
+            self.peek8(address),
+            self.peek8(address.wrapping_add(1)),
+            self.peek8(address.wrapping_add(2)),
+            self.peek8(address.wrapping_add(3)),
+        ])
+    }
 }
@@ -0,0 +1,148 @@
+use std::{hint::black_box, ops::RangeInclusive, time::Instant};
+
+use crate::box_arr;
+
+use super::{
+    bus::{Bus, WAITCNT},
+    Mcu,
+};
+
+/// 16 KiB pages covering the 28-bit address space.
+pub const PAGE_SHIFT: u32 = 14;
+pub const PAGE_MASK: usize = (1 << PAGE_SHIFT) - 1;
+pub const PAGE_COUNT: usize = 1 << (28 - PAGE_SHIFT);
+
+/// Where a page of the address space lives, offsets are already mirrored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Page {
+    /// IO, palette, OAM, backup and unmapped memory, goes through the full memory map.
+    Slow,
+    Bios,
+    /// EWRAM and IWRAM share one buffer.
+    Wram(usize),
+    Vram(usize),
+    /// Offset and the wait states of a first and a sequential access, see `WAITCNT::rom`.
+    Rom(usize, (u32, u32)),
+}
+
+/// Page table for all regions that are plain (mirrored) buffers.
+pub fn build_pages(waitcnt: &WAITCNT, imc: u32) -> Box<[Page; PAGE_COUNT]> {
+    let mut pages: Box<[Page; PAGE_COUNT]> = box_arr![Page::Slow; PAGE_COUNT];
+    update_pages(&mut pages, 0x00..=0x0F, waitcnt, imc);
+
+    pages
+}
+
+/// Recompute the pages of `regions` (address bits 24-27), WAITCNT changes the ROM
+/// wait states and IMC whether WRAM is mapped at all.
+pub fn update_pages(pages: &mut [Page; PAGE_COUNT], regions: RangeInclusive<usize>, waitcnt: &WAITCNT, imc: u32) {
+    const PAGES_PER_REGION: usize = 1 << (24 - PAGE_SHIFT);
+
+    // IMC bit 0 disables both WRAMs, the slow path then reads open bus.
+    let wram = imc & 1 == 0;
+
+    let range = regions.start() * PAGES_PER_REGION..(regions.end() + 1) * PAGES_PER_REGION;
+    for (i, page) in pages[range.clone()].iter_mut().enumerate() {
+        let address = (range.start + i) << PAGE_SHIFT;
+
+        *page = match address >> 24 {
+            0x00 if address < 0x4000 => Page::Bios,
+            0x02 if wram => Page::Wram(address % 0x0004_0000),
+            0x03 if wram => Page::Wram((address % 0x8000) + 0x0004_0000),
+            0x06 => Page::Vram(Bus::vram_offset(address as u32)),
+            0x08..=0x0D => Page::Rom(address & 0x00FF_FFFF, waitcnt.rom(address as u32)),
+            _ => Page::Slow,
+        };
+    }
+}
+
+/// Time `accesses` word reads spread over BIOS, WRAM, VRAM and ROM, once through the page
+/// table and once through the full memory map.
+///
+/// Returns millions of reads per second for both.
+pub fn bench(accesses: u64) -> [(&'static str, f64); 2] {
+    const BASES: [u32; 5] = [0x0000_0000, 0x0200_0000, 0x0300_0000, 0x0600_0000, 0x0800_0000];
+
+    let bus = Bus::default();
+    let address = |i: u64| BASES[i as usize % BASES.len()] + ((i as u32 * 4) & 0x3FFC);
+
+    let mreads = |read: &dyn Fn(u32) -> u32| {
+        let start = Instant::now();
+        for i in 0..accesses {
+            black_box(read(black_box(address(i))));
+        }
+
+        accesses as f64 / start.elapsed().as_secs_f64() / 1e6
+    };
+
+    [
+        ("page table", mreads(&|address| bus.peek32(address))),
+        ("slow path", mreads(&|address| bus.peek32_slow(address))),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    /// Random memory contents, so a wrong offset can't read the same bytes.
+    fn random_bus() -> Bus {
+        let mut bus = Bus::default();
+        let mut rng = Rng::new(1);
+
+        rng.fill(&mut bus.wram[..]);
+        rng.fill(&mut bus.vram[..]);
+        rng.fill(&mut bus.game_pak.rom[..0x0100_0000]);
+        bus.set_open_bus(rng.u32());
+        bus
+    }
+
+    /// Every page start and end plus random addresses, aligned and not.
+    fn assert_matches_slow_path(bus: &Bus, rng: &mut Rng) {
+        let page_edges = (0..PAGE_COUNT as u32).flat_map(|i| [i << PAGE_SHIFT, ((i + 1) << PAGE_SHIFT) - 1]);
+        let random = (0..0x10000).map(|_| rng.below(0x1000_0000));
+
+        for address in page_edges.chain(random) {
+            assert_eq!(bus.peek32(address), bus.peek32_slow(address), "at {address:#010X}");
+        }
+    }
+
+    #[test]
+    fn pages_match_slow_path() {
+        let bus = random_bus();
+        assert_matches_slow_path(&bus, &mut Rng::new(2));
+    }
+
+    #[test]
+    fn waitcnt_and_imc_writes_rebuild_pages() {
+        let mut bus = random_bus();
+        let mut rng = Rng::new(3);
+
+        for _ in 0..16 {
+            // Bytewise too, both halves of WAITCNT set ROM wait states.
+            match rng.below(2) {
+                0 => bus.write16(0x0400_0204, rng.u32() as u16),
+                _ => bus.write8(0x0400_0204 + rng.below(2), rng.u32() as u8),
+            }
+
+            for address in (0x0800_0000..0x0E00_0000).step_by(1 << PAGE_SHIFT) {
+                let Page::Rom(_, waits) = bus.pages[address as usize >> PAGE_SHIFT] else {
+                    panic!("no ROM page at {address:#010X}");
+                };
+                assert_eq!(waits, bus.waitcnt.rom(address), "at {address:#010X}");
+            }
+        }
+
+        // Disabled WRAM isn't in the page table, it reads open bus.
+        bus.write32(0x0400_0800, 0x0D00_0021);
+        assert_eq!(bus.pages[0x0200_0000 >> PAGE_SHIFT], Page::Slow);
+        assert_matches_slow_path(&bus, &mut rng);
+        assert_eq!(bus.peek32(0x0300_0000), bus.peek32(0x0000_4000));
+
+        bus.write32(0x0400_0800, 0x0D00_0020);
+        assert_eq!(bus.pages[0x0200_0000 >> PAGE_SHIFT], Page::Wram(0));
+        assert_matches_slow_path(&bus, &mut rng);
+        assert_eq!(*bus.pages, *build_pages(&bus.waitcnt, bus.imc));
+    }
+}
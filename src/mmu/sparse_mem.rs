@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use super::Mcu;
+
+/// A single bus access, `kind` as in the SingleStepTests vectors.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct Transaction {
+    /// 0 = instruction read, 1 = data read, 2 = write.
+    pub kind: u8,
+    /// Access width in bytes.
+    pub size: u8,
+    pub addr: u32,
+    pub data: u32,
+}
+
+/// Sparse byte memory that records every access, for running single instructions.
+///
+/// Unmapped addresses read 0. Reads are recorded as data reads since the CPU
+/// does not tell instruction fetches apart.
+#[derive(Default, Clone)]
+pub struct SparseMem {
+    pub mem: HashMap<u32, u8>,
+    pub transactions: Vec<Transaction>,
+}
+
+impl SparseMem {
+    /// Store `size` bytes of `value` little-endian at `address` without recording it.
+    pub fn poke(&mut self, address: u32, value: u32, size: u8) {
+        for (i, byte) in value.to_le_bytes().into_iter().take(size as usize).enumerate() {
+            self.mem.insert(address.wrapping_add(i as u32), byte);
+        }
+    }
+
+    fn record(&mut self, kind: u8, size: u8, addr: u32, data: u32) {
+        self.transactions.push(Transaction { kind, size, addr, data });
+    }
+}
+
+impl Mcu for SparseMem {
+    fn read32(&mut self, address: u32) -> u32 {
+        let value = self.peek32(address);
+        self.record(1, 4, address, value);
+        value
+    }
+
+    fn write32(&mut self, address: u32, value: u32) {
+        self.poke(address, value, 4);
+        self.record(2, 4, address, value);
+    }
+
+    fn read16(&mut self, address: u32) -> u16 {
+        let value = self.peek16(address);
+        self.record(1, 2, address, value as u32);
+        value
+    }
+
+    fn write16(&mut self, address: u32, value: u16) {
+        self.poke(address, value as u32, 2);
+        self.record(2, 2, address, value as u32);
+    }
+
+    fn read8(&mut self, address: u32) -> u8 {
+        let value = self.peek8(address);
+        self.record(1, 1, address, value as u32);
+        value
+    }
+
+    fn write8(&mut self, address: u32, value: u8) {
+        self.poke(address, value as u32, 1);
+        self.record(2, 1, address, value as u32);
+    }
+
+    fn peek16(&self, address: u32) -> u16 {
+        u16::from_le_bytes([self.peek8(address), self.peek8(address.wrapping_add(1))])
+    }
+
+    fn peek8(&self, address: u32) -> u8 {
+        self.mem.get(&address).copied().unwrap_or(0)
+    }
+}
@@ -10,9 +10,10 @@ pub struct Timers([Timer; 4]);
 impl Timers {
     /// Tick all 4 timers based on their attributes and frequencies.
     ///
-    /// Keep track of IDs for overflowing IRQ.
-    pub fn tick(&mut self, iff: &mut IF, cycles: usize) {
-        let mut tm_overflow = [false; 4];
+    /// Keep track of IDs for overflowing IRQ, returns which timers overflowed.
+    /// `cycles` is the global timestamp, prescalers tick on its multiples.
+    pub fn tick(&mut self, iff: &mut IF, cycles: u64) -> [u32; 4] {
+        let mut tm_overflow = [0; 4];
 
         for id in 0..4 {
             if !self[id].start {
@@ -26,23 +27,34 @@ impl Timers {
                 Freq::F1024 => 1024,
             };
 
-            // Either tick up normally when the frequency is reached
-            // or use Count-Up-Timing when previous timer overflows (not timer 0).
-            if (!self[id].count_up && cycles % freq == 0)
-                || (self[id].count_up && id > 0 && tm_overflow[id - 1])
-            {
-                tm_overflow[id] = self[id].tick();
+            // Either tick up normally when the frequency is reached or, exclusively,
+            // use Count-Up-Timing once per overflow of the previous timer. The prescaler is
+            // ignored while cascading. Timer 0 has no previous timer, it never counts up.
+            let ticks = match self[id].count_up {
+                true if id > 0 => tm_overflow[id - 1],
+                true => 0,
+                false => cycles.is_multiple_of(freq) as u32,
+            };
+
+            for _ in 0..ticks {
+                tm_overflow[id] += self[id].tick() as u32;
             }
 
-            if tm_overflow[id] && self[id].irq {
+            if tm_overflow[id] > 0 && self[id].irq {
                 iff.set_timer(id);
             }
         }
+
+        tm_overflow
     }
 }
 
 impl Mcu for Timers {
     fn read16(&mut self, address: u32) -> u16 {
+        self.peek16(address)
+    }
+
+    fn peek16(&self, address: u32) -> u16 {
         match address {
             0x0100 => self[0].counter,
             0x0102 => u16::from(self[0]),
@@ -78,18 +90,23 @@ impl Mcu for Timers {
     }
 
     fn write8(&mut self, address: u32, value: u8) {
-        // Make sure to "read" reload to modify it on write and not "read" counter.
-        let [lo, hi] = match (address & !1) % 4 {
-            0 => self[((address as usize & !1) - 0x0100) / 4].reload,
-            _ => self.read16(address & !1),
-        }
-        .to_le_bytes();
-
+        let [lo, hi] = self.raw_read16(address & !1).to_le_bytes();
         match address & 1 == 0 {
             true => self.write16(address, (hi as u16) << 8 | value as u16),
             false => self.write16(address & !1, (value as u16) << 8 | lo as u16),
         }
     }
+
+    /// The reload value instead of the counter, so byte writes modify what was written.
+    fn raw_read16(&mut self, address: u32) -> u16 {
+        match address {
+            0x0100 => self[0].reload,
+            0x0104 => self[1].reload,
+            0x0108 => self[2].reload,
+            0x010C => self[3].reload,
+            _ => self.peek16(address),
+        }
+    }
 }
 
 impl Index<usize> for Timers {
@@ -126,6 +143,7 @@ impl Timer {
         self.start = value & (1 << 7) != 0;
 
         self.irq = value & (1 << 6) != 0;
+        // TM0CNT_H has no Count-Up-Timing, the bit is ignored.
         self.count_up = if ID > 0 { value & (1 << 2) != 0 } else { false };
         self.freq = Freq::try_from(value & 0x3).unwrap();
 
@@ -153,8 +171,8 @@ impl Timer {
 
 impl From<Timer> for u16 {
     fn from(value: Timer) -> Self {
-        0xFF00
-            | (value.start as u16) << 7
+        // Bits 3-5 and 8-15 are unused.
+        (value.start as u16) << 7
             | (value.irq as u16) << 6
             | (value.count_up as u16) << 2
             | value.freq as u16
@@ -169,3 +187,67 @@ enum Freq {
     F256,
     F1024,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cascade_ticks_only_on_overflow() {
+        let (mut timers, mut iff) = (Timers::default(), IF::default());
+
+        // Timer 0 overflows every other cycle. Timer 1 cascades with a prescaler that must be
+        // ignored, timer 2 cascades from timer 1 and raises an IRQ on its first overflow.
+        timers.write16(0x0100, 0xFFFE);
+        timers.write16(0x0102, 0x0084);
+        timers.write16(0x0104, 0xFFF0);
+        timers.write16(0x0106, 0x0087);
+        timers.write16(0x0108, 0xFFFE);
+        timers.write16(0x010A, 0x00C4);
+
+        for cycle in 1..=64 {
+            let overflows = timers.tick(&mut iff, cycle);
+            assert_eq!(overflows[0], (cycle % 2 == 0) as u32, "cycle {cycle}");
+            assert_eq!(overflows[1], (cycle % 32 == 0) as u32, "cycle {cycle}");
+            assert_eq!(overflows[2], (cycle == 64) as u32, "cycle {cycle}");
+        }
+
+        assert_eq!(timers.read16(0x0100), 0xFFFE);
+        assert_eq!(timers.read16(0x0104), 0xFFF0);
+        assert_eq!(timers.read16(0x0108), 0xFFFE);
+        assert!(!iff.timer0() && !iff.timer1() && iff.timer2());
+        // Timer 0 ticked on its own, its count-up bit doesn't even read back.
+        assert_eq!(timers.read16(0x0102), 0x0080);
+
+        // Without timer 0 running, timer 1 stays put.
+        timers.write16(0x0102, 0);
+        for cycle in 65..=1024 {
+            assert_eq!(timers.tick(&mut iff, cycle), [0; 4]);
+        }
+        assert_eq!(timers.read16(0x0104), 0xFFF0);
+    }
+
+    #[test]
+    fn byte_writes() {
+        let mut timers = Timers::default();
+
+        // Both reload bytes, which only reach the counter on start.
+        timers.write8(0x0100, 0x34);
+        timers.write8(0x0101, 0x12);
+        assert_eq!(timers.read16(0x0100), 0);
+        timers.write8(0x0102, 0x83);
+        assert_eq!(timers.read16(0x0100), 0x1234);
+        assert_eq!(timers.read16(0x0102), 0x0083);
+
+        // The unused high byte of TM0CNT_H keeps the control bits and doesn't restart.
+        timers[0].counter = 0x2000;
+        timers.write8(0x0103, 0xFF);
+        assert_eq!(timers.read16(0x0102), 0x0083);
+        assert_eq!(timers.read8(0x0103), 0);
+        assert_eq!(timers.read16(0x0100), 0x2000);
+
+        timers.write8(0x0102, 0x42);
+        assert_eq!(timers.read16(0x0102), 0x0042);
+        assert_eq!(timers[0].reload, 0x1234);
+    }
+}
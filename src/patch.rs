@@ -0,0 +1,344 @@
+use std::path::{Path, PathBuf};
+
+use crate::rom::MAX_ROM_SIZE;
+
+/// Look for `game.ips` or `game.ups` next to `game.gba`.
+pub fn find_patch(rom_path: &str) -> Option<PathBuf> {
+    ["ips", "ups"]
+        .into_iter()
+        .map(|ext| Path::new(rom_path).with_extension(ext))
+        .find(|path| path.is_file())
+}
+
+/// Patch `rom` in place with an IPS or UPS file, detected by its header.
+///
+/// UPS checksum mismatches are an error unless `force` is set, then they're only warnings.
+/// A patched rom larger than the 32 MiB ROM region is always an error.
+pub fn apply(rom: &mut Vec<u8>, path: &Path, force: bool) -> Result<(), String> {
+    let patch = std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+
+    match &patch[..patch.len().min(5)] {
+        b"PATCH" => apply_ips(rom, &patch)?,
+        [b'U', b'P', b'S', b'1', ..] => apply_ups(rom, &patch, force)?,
+        _ => return Err(format!("{} is neither an IPS nor UPS patch", path.display())),
+    }
+
+    match rom.len() {
+        0..=MAX_ROM_SIZE => Ok(()),
+        len => Err(format!("Patched rom is {len} bytes, more than the {MAX_ROM_SIZE} of the ROM region")),
+    }
+}
+
+/// IPS: 24 bit offset, 16 bit size and data, or size 0 with a 16 bit count and a byte to repeat (RLE).
+///
+/// The records end at `EOF`, which is also why offset 0x454F46 can't be patched.
+/// An optional 24 bit size after the marker truncates the ROM.
+pub fn apply_ips(rom: &mut Vec<u8>, patch: &[u8]) -> Result<(), String> {
+    let mut reader = Reader::new(&patch[5..]);
+
+    loop {
+        let offset = reader.bytes(3)?;
+        if offset == b"EOF" {
+            break;
+        }
+
+        let offset = u32::from_be_bytes([0, offset[0], offset[1], offset[2]]) as usize;
+        let size = u16::from_be_bytes(reader.array()?) as usize;
+
+        let data = match size {
+            0 => {
+                let count = u16::from_be_bytes(reader.array()?) as usize;
+                vec![reader.byte()?; count]
+            }
+            _ => reader.bytes(size)?.to_vec(),
+        };
+
+        if rom.len() < offset + data.len() {
+            rom.resize(offset + data.len(), 0);
+        }
+        rom[offset..offset + data.len()].copy_from_slice(&data);
+    }
+
+    if let Ok(size) = reader.bytes(3) {
+        rom.truncate(u32::from_be_bytes([0, size[0], size[1], size[2]]) as usize);
+    }
+
+    Ok(())
+}
+
+/// UPS: source and target size, then hunks of a relative offset and bytes XORed
+/// onto the source up to a zero byte. Ends with the source, target and patch CRC32.
+pub fn apply_ups(rom: &mut Vec<u8>, patch: &[u8], force: bool) -> Result<(), String> {
+    if patch.len() < 16 {
+        return Err("UPS patch is truncated".to_string());
+    }
+
+    let (body, crcs) = patch.split_at(patch.len() - 12);
+    let crc = |i: usize| u32::from_le_bytes(crcs[i * 4..i * 4 + 4].try_into().unwrap());
+
+    let check = |what: &str, expected: u32, actual: u32| {
+        if expected == actual {
+            return Ok(());
+        }
+
+        let msg = format!("UPS {what} mismatch ({actual:08X}, expected {expected:08X})");
+        if !force {
+            return Err(format!("{msg}, use --force-patch to apply anyway"));
+        }
+
+        eprintln!("Warning: {msg}");
+        Ok(())
+    };
+
+    check("patch CRC32", crc(2), crc32(&patch[..patch.len() - 4]))?;
+    check("source CRC32", crc(0), crc32(rom))?;
+
+    let mut reader = Reader::new(&body[4..]);
+    let source_size = reader.varint()? as usize;
+    let target_size = reader.varint()? as usize;
+
+    if rom.len() != source_size {
+        check("source size", source_size as u32, rom.len() as u32)?;
+    }
+    if target_size > MAX_ROM_SIZE {
+        return Err(format!("UPS target is {target_size} bytes, more than the {MAX_ROM_SIZE} of the ROM region"));
+    }
+
+    rom.resize(target_size, 0);
+    let mut offset: usize = 0;
+
+    while !reader.is_empty() {
+        offset = offset
+            .checked_add(reader.varint()? as usize)
+            .filter(|&offset| offset <= target_size)
+            .ok_or("UPS hunk starts past the end of the target")?;
+
+        loop {
+            let byte = reader.byte()?;
+            if byte == 0 {
+                offset += 1;
+                break;
+            }
+
+            if let Some(b) = rom.get_mut(offset) {
+                *b ^= byte;
+            }
+            offset += 1;
+        }
+    }
+
+    check("target CRC32", crc(1), crc32(rom))
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| match crc & 1 {
+            1 => (crc >> 1) ^ 0xEDB8_8320,
+            _ => crc >> 1,
+        })
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < n {
+            return Err("Patch ended unexpectedly".to_string());
+        }
+
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// UPS variable length integer, 7 bits per byte with the last one marked by bit 7.
+    /// Malformed patches can encode more than 64 bits, that's an error.
+    fn varint(&mut self) -> Result<u64, String> {
+        let too_large = || "UPS number doesn't fit into 64 bits".to_string();
+        let mut value: u64 = 0;
+        let mut shift: u64 = 1;
+
+        loop {
+            let byte = self.byte()? as u64;
+            value = shift
+                .checked_mul(byte & 0x7F)
+                .and_then(|bits| value.checked_add(bits))
+                .ok_or_else(too_large)?;
+
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+
+            shift = shift.checked_mul(0x80).ok_or_else(too_large)?;
+            value = value.checked_add(shift).ok_or_else(too_large)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_varint(out: &mut Vec<u8>, mut value: u64) {
+        loop {
+            let bits = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                return out.push(0x80 | bits);
+            }
+            out.push(bits);
+            value -= 1;
+        }
+    }
+
+    /// Append the source, target and patch CRC32 to a UPS body.
+    fn with_crcs(mut patch: Vec<u8>, source: u32, target: u32) -> Vec<u8> {
+        patch.extend(source.to_le_bytes());
+        patch.extend(target.to_le_bytes());
+        patch.extend(crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    /// A UPS patch from `source` to `target`, one hunk per run of differing bytes.
+    fn ups(source: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut patch = b"UPS1".to_vec();
+        push_varint(&mut patch, source.len() as u64);
+        push_varint(&mut patch, target.len() as u64);
+
+        let (mut i, mut last) = (0, 0);
+        while i < target.len() {
+            let xor = |i: usize| source.get(i).copied().unwrap_or(0) ^ target[i];
+            if xor(i) == 0 {
+                i += 1;
+                continue;
+            }
+
+            push_varint(&mut patch, (i - last) as u64);
+            while i < target.len() && xor(i) != 0 {
+                patch.push(xor(i));
+                i += 1;
+            }
+            patch.push(0);
+            i += 1;
+            last = i;
+        }
+
+        with_crcs(patch, crc32(source), crc32(target))
+    }
+
+    #[test]
+    fn ips_records_and_rle() {
+        let mut patch = b"PATCH".to_vec();
+        // 3 bytes at 2, 4 times 0xAA at 8 (RLE), 2 bytes past the end.
+        patch.extend([0x00, 0x00, 0x02, 0x00, 0x03, 1, 2, 3]);
+        patch.extend([0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x04, 0xAA]);
+        patch.extend([0x00, 0x00, 0x12, 0x00, 0x02, 5, 6]);
+        patch.extend(b"EOF");
+
+        let mut rom = vec![0; 16];
+        apply_ips(&mut rom, &patch).unwrap();
+        assert_eq!(rom, [0, 0, 1, 2, 3, 0, 0, 0, 0xAA, 0xAA, 0xAA, 0xAA, 0, 0, 0, 0, 0, 0, 5, 6]);
+
+        // A size after EOF truncates.
+        patch.extend([0x00, 0x00, 0x04]);
+        apply_ips(&mut rom, &patch).unwrap();
+        assert_eq!(rom, [0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn patched_rom_past_the_rom_region() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend([0x00, 0x00, 0x00, 0x00, 0x01, 0x42]);
+        patch.extend(b"EOF");
+        let path = std::env::temp_dir().join(format!("kba-test-{}.ips", std::process::id()));
+        std::fs::write(&path, patch).unwrap();
+
+        let (mut fits, mut too_large) = (vec![0; MAX_ROM_SIZE], vec![0; MAX_ROM_SIZE + 1]);
+        let results = (apply(&mut fits, &path, false), apply(&mut too_large, &path, false));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(results.0, Ok(()));
+        assert!(results.1.is_err());
+    }
+
+    #[test]
+    fn ups_extends_the_target() {
+        let source: Vec<u8> = (0..32).collect();
+        let mut target = source.clone();
+        target[3] = 0xFF;
+        target[10..14].copy_from_slice(&[9, 9, 9, 9]);
+        target.extend([0, 0, 0x12, 0x34, 0, 0x56]);
+
+        let mut rom = source.clone();
+        apply_ups(&mut rom, &ups(&source, &target), false).unwrap();
+        assert_eq!(rom, target);
+
+        // Shrinking works the same way.
+        let mut rom = target.clone();
+        apply_ups(&mut rom, &ups(&target, &source[..20]), false).unwrap();
+        assert_eq!(rom, source[..20]);
+    }
+
+    #[test]
+    fn ups_checksums() {
+        let (source, target) = ([1, 2, 3, 4], [1, 2, 0, 4]);
+        let patch = ups(&source, &target);
+
+        let mut other = vec![5, 6, 7, 8];
+        assert!(apply_ups(&mut other, &patch, false).is_err());
+        assert_eq!(other, [5, 6, 7, 8], "patched despite the mismatch");
+        apply_ups(&mut other, &patch, true).unwrap();
+        assert_eq!(other, [5, 6, 4, 8]);
+
+        let mut corrupt = patch.clone();
+        corrupt[6] ^= 1;
+        assert!(apply_ups(&mut source.to_vec(), &corrupt, false).is_err());
+    }
+
+    #[test]
+    fn malformed_ups() {
+        let source = [0; 4];
+
+        // A target larger than the ROM region.
+        let mut patch = b"UPS1".to_vec();
+        push_varint(&mut patch, 4);
+        push_varint(&mut patch, MAX_ROM_SIZE as u64 + 1);
+        let patch = with_crcs(patch, crc32(&source), 0);
+        assert!(apply_ups(&mut source.to_vec(), &patch, true).is_err());
+
+        // A source size that never ends overflows 64 bits.
+        let mut patch = b"UPS1".to_vec();
+        patch.extend([0; 16]);
+        let patch = with_crcs(patch, crc32(&source), 0);
+        let err = apply_ups(&mut source.to_vec(), &patch, true).unwrap_err();
+        assert!(err.contains("64 bits"), "{err}");
+
+        // A hunk offset past the target.
+        let mut patch = b"UPS1".to_vec();
+        push_varint(&mut patch, 4);
+        push_varint(&mut patch, 4);
+        push_varint(&mut patch, u64::MAX);
+        patch.extend([1, 0]);
+        let patch = with_crcs(patch, crc32(&source), 0);
+        assert!(apply_ups(&mut source.to_vec(), &patch, true).is_err());
+    }
+}
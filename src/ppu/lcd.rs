@@ -1,5 +1,3 @@
-use std::collections::HashSet;
-
 use derivative::Derivative;
 use proc_bitfield::{bitfield, BitRange, ConvRaw};
 use seq_macro::seq;
@@ -8,7 +6,7 @@ use crate::{
     bits,
     gba::{LCD_HEIGHT, LCD_WIDTH},
     mmu::{irq::IF, Mcu},
-    set_bits,
+    profile, set_bits,
 };
 
 use super::{
@@ -16,9 +14,14 @@ use super::{
     sprite::{ObjMode, Sprite},
 };
 
+/// Dots until HBlank starts and dots per line, a frame is 1232 * 228 = 280896 cycles.
 const HDRAW_LEN: u16 = 1006;
 const TOTAL_LEN: u16 = 1232;
-const TOTAL_LINES: u8 = 227;
+const TOTAL_LINES: u8 = 228;
+
+/// Cycles per line for rendering sprites, fewer if the OBJ renderer has to stop at HBlank.
+pub const OBJ_CYCLES: u16 = 1210;
+pub const OBJ_CYCLES_HBLANK_FREE: u16 = 954;
 
 #[derive(Derivative)]
 #[derivative(Default)]
@@ -39,10 +42,12 @@ pub struct Ppu {
     pub bgxx: [i32; 2],
     pub bgxy: [i32; 2],
 
-    /// Rotation/Scaling parameters for affine transformations.
+    /// Rotation/Scaling parameters for affine transformations, identity after the BIOS.
+    #[derivative(Default(value = "[0x100; 2]"))]
     pub bgxpa: [i16; 2],
     pub bgxpb: [i16; 2],
     pub bgxpc: [i16; 2],
+    #[derivative(Default(value = "[0x100; 2]"))]
     pub bgxpd: [i16; 2],
 
     pub bldcnt: BLDCNT,
@@ -53,8 +58,6 @@ pub struct Ppu {
     pub mosaic: MOSAIC,
     #[derivative(Default(value = "[[None; 512]; 4]"))]
     bg_mosaic_v_buf: [[Option<u16>; 512]; 4],
-    #[derivative(Default(value = "[Obj::default(); 512]"))]
-    obj_mosaic_v_buf: [Obj; 512],
 
     /// Window X horizontal and vertical dimensions.
     pub winxh: [u16; 2],
@@ -62,12 +65,23 @@ pub struct Ppu {
 
     pub winin: WININ,
     pub winout: WINOUT,
-    /// All obj coordinates that have `ObjMode = Window`.
-    obj_window_buf: HashSet<(usize, usize)>,
+    /// Pixels of the current line covered by sprites with `ObjMode = Window`.
+    #[derivative(Default(value = "[false; 512]"))]
+    obj_win_mask: [bool; 512],
 
     #[derivative(Default(value = "vec![None; LCD_WIDTH * LCD_HEIGHT]"))]
     pub buffer: Vec<Option<u16>>,
 
+    /// Enhancement: affine backgrounds, bitmaps and sprites are sampled `internal_scale` times per pixel
+    /// in each direction into `hires_buffer`, everything else is integer-upscaled.
+    /// `buffer` stays the native output either way.
+    #[derivative(Default(value = "1"))]
+    pub internal_scale: usize,
+    pub hires_buffer: Vec<Option<u16>>,
+    /// Average the `internal_scale` samples of each pixel into `buffer` instead,
+    /// anti-aliasing rotated and scaled layers at the native resolution.
+    pub supersample: bool,
+
     /// Current to-be-drawn line from the backgrounds, one for each prio.
     #[derivative(Default(value = "[[None; 512]; 4]"))]
     current_bg_line: [[Option<u16>; 512]; 4],
@@ -87,7 +101,14 @@ pub struct Ppu {
     // pub vid_capture: bool,
     pub prev_mode: Mode,
     pub current_mode: Mode,
-    cycle: u16,
+    /// Cycle within the current line.
+    pub cycle: u16,
+    /// Frames since power on.
+    pub frame: u64,
+    /// Bumped at VBlank only if any line of `buffer` changed during the frame.
+    pub frame_seq: u64,
+    /// A line of the current frame differs from the previous frame.
+    dirty: bool,
 }
 
 #[derive(Default, Clone, Copy, PartialEq)]
@@ -127,7 +148,8 @@ impl Ppu {
     pub fn cycle(&mut self, vram: &[u8], palette_ram: &[u8], oam: &[u8], iff: &mut IF) {
         match self.current_mode {
             Mode::HDraw => {
-                if self.cycle > HDRAW_LEN {
+                // Rendered at the end of HDraw, so mid-line register writes before it are seen.
+                if self.cycle == HDRAW_LEN {
                     self.scanline(vram, palette_ram, oam);
 
                     self.dispstat.set_hblank(true);
@@ -140,14 +162,13 @@ impl Ppu {
                 }
             }
             Mode::HBlank => {
-                if self.cycle > TOTAL_LEN {
+                if self.cycle == TOTAL_LEN - 1 {
                     // Internal reference point regs get incremented by dmx/dmy each scanline.
                     for bg in 0..2 {
                         self.internal_ref_xx[bg] += self.bgxpb[bg] as i32;
                         self.internal_ref_xy[bg] += self.bgxpd[bg] as i32;
                     }
 
-                    self.cycle = 0;
                     self.dispstat.set_hblank(false);
 
                     self.vcount.set_ly(self.vcount.ly() + 1);
@@ -159,6 +180,11 @@ impl Ppu {
                     }
 
                     if self.vcount.ly() >= 160 {
+                        if self.dirty {
+                            self.frame_seq += 1;
+                            self.dirty = false;
+                        }
+
                         if self.dispstat.vblank_irq() {
                             iff.set_vblank(true);
                         }
@@ -175,17 +201,16 @@ impl Ppu {
             }
             Mode::VBlank => {
                 // HBlank in DIPSTAT still gets set during VBlank.
-                if self.cycle > HDRAW_LEN {
+                if self.cycle >= HDRAW_LEN {
                     // if self.dispstat.hblank_irq() { iff.set_hblank(true); }
                     self.dispstat.set_hblank(true);
                 }
 
-                if self.cycle > TOTAL_LEN {
+                if self.cycle == TOTAL_LEN - 1 {
                     // Reference points get copied to internal regs during VBlank.
                     self.internal_ref_xx = self.bgxx;
                     self.internal_ref_xy = self.bgxy;
 
-                    self.cycle = 0;
                     self.dispstat.set_hblank(false);
 
                     self.vcount.set_ly(self.vcount.ly() + 1);
@@ -198,6 +223,7 @@ impl Ppu {
 
                     if self.vcount.ly() >= TOTAL_LINES {
                         self.vcount.set_ly(0); // vcount irq for ly = 0
+                        self.frame += 1;
 
                         self.dispstat
                             .set_v_counter(self.vcount.ly() == self.dispstat.lyc());
@@ -215,14 +241,31 @@ impl Ppu {
             }
         }
 
-        self.cycle += 1;
+        // `cycle` is the dot of the line being processed, 0..TOTAL_LEN.
+        self.cycle = (self.cycle + 1) % TOTAL_LEN;
+    }
+
+    /// Extra CPU cycles for accessing palette RAM, VRAM or OAM while the PPU is fetching from it.
+    ///
+    /// All three are busy during HDraw. Sprites for the next line are also read from OAM
+    /// in HBlank unless DISPCNT frees the HBlank interval. Forced blank frees everything.
+    pub fn video_stall(&self, address: u32) -> u32 {
+        let rendering = self.current_mode == Mode::HDraw && !self.dispcnt.forced_blank();
+        let obj_fetch = self.current_mode == Mode::HBlank
+            && !self.dispcnt.forced_blank()
+            && !self.dispcnt.hblank_interval_free();
+
+        match address >> 24 {
+            0x05 | 0x06 if rendering => 1,
+            0x07 if rendering || obj_fetch => 1,
+            _ => 0,
+        }
     }
 
     /// Render and draw one scanline fully.
     ///
     /// 1. `update_bg_scanline`:
-    ///     - **if** mode < 3: `render_{text, affine}_bg` depending on mode.
-    ///     - **else**: render directly into the buffer.
+    ///     - `render_{text, affine, bitmap}_bg` depending on mode.
     ///
     /// 2. `render_sprite_line`:
     ///     - collect sprites from OAM.
@@ -232,34 +275,173 @@ impl Ppu {
     ///     - mix background and sprite lines according to their priorities.
     ///     - apply blending and other color effects.
     fn scanline(&mut self, vram: &[u8], palette_ram: &[u8], oam: &[u8]) {
+        profile!(Ppu);
+
+        // Keep the old line around to see if this frame actually changed anything.
+        let line = self.vcount.ly() as usize * LCD_WIDTH..(self.vcount.ly() as usize + 1) * LCD_WIDTH;
+        let previous: [Option<u16>; LCD_WIDTH] = self.buffer[line.clone()].try_into().unwrap();
+
+        // Not every mode writes every pixel, nothing of the previous frame may be left over.
+        // Forced blank shows white lines without rendering anything.
+        if self.dispcnt.forced_blank() {
+            self.buffer[line.clone()].fill(Some(0x7FFF));
+            let (y, scale) = (self.vcount.ly() as usize, self.internal_scale);
+            if scale > 1 {
+                let rows = y * scale * LCD_WIDTH * scale..(y + 1) * scale * LCD_WIDTH * scale;
+                self.dirty |= self.hires_buffer[rows.clone()].iter().any(|&px| px != Some(0x7FFF));
+                self.hires_buffer[rows].fill(Some(0x7FFF));
+            }
+
+            self.dirty |= self.buffer[line] != previous;
+            return;
+        }
+        self.buffer[line.clone()].fill(None);
+
         // Render backgrounds by either drawing text backgrounds or affine backgrounds.
         self.update_bg_scanline(vram, palette_ram);
 
         // Render sprites by first collecting all sprites from OAM
-        // that are on this line, then drawing them. (todo: draw sprites for mode 4, 5)
+        // that are on this line, then drawing them.
         self.current_sprites = Sprite::collect_obj_ly(oam, self.vcount.ly());
         self.current_rot_scale = Sprite::collect_rot_scale_params(oam);
-        self.render_sprite_line(vram, palette_ram);
-
-        // If mode >= 3, we render directly into `self.buffer`
-        // and don't use the line draw function.
-        if self.dispcnt.bg_mode() < 3 {
-            self.draw_line(palette_ram);
-        } else if self.dispcnt.bg_mode() == 3 {
-            let start = self.vcount.ly() as usize * LCD_WIDTH * 2;
-            let line = self.current_sprite_line;
-
-            for (i, px) in line[..LCD_WIDTH].iter().enumerate() {
-                if let Some(obj_px) = px.px {
-                    self.buffer[(start / 2) + i] = Some(obj_px);
+        self.render_sprite_line(vram, palette_ram, (0, 0));
+
+        // The compositor consumes these, keep the originals for the sub-pixel passes.
+        let hires_src = match self.internal_scale > 1 {
+            true => Some((self.current_bg_line, self.current_sprite_line, self.obj_win_mask)),
+            false => None,
+        };
+
+        self.draw_line(palette_ram);
+
+        // At higher internal scales the native line doesn't tell, `buffer` may hold its downsampled version.
+        match hires_src {
+            Some((bg_line, sprite_line, obj_win_mask)) => {
+                self.render_hires_line(vram, palette_ram, bg_line, sprite_line, obj_win_mask)
+            }
+            None => self.dirty |= self.buffer[line] != previous,
+        }
+    }
+
+    pub fn set_internal_scale(&mut self, scale: usize) {
+        self.internal_scale = scale.max(1);
+        self.hires_buffer = match scale > 1 {
+            true => vec![None; LCD_WIDTH * LCD_HEIGHT * scale * scale],
+            false => Vec::new(),
+        };
+    }
+
+    /// Fill the `internal_scale` rows of `hires_buffer` belonging to the current line.
+    ///
+    /// If the line has affine backgrounds, a bitmap or affine sprites, every sub-pixel position re-renders them
+    /// with the sampling point moved by that fraction of a pixel and runs the compositor again.
+    /// Everything else is integer-upscaled.
+    fn render_hires_line(
+        &mut self,
+        vram: &[u8],
+        palette_ram: &[u8],
+        bg_line: [[Option<u16>; 512]; 4],
+        sprite_line: [Obj; 512],
+        obj_win_mask: [bool; 512],
+    ) {
+        let scale = self.internal_scale;
+        let y = self.vcount.ly() as usize;
+        let line = y * LCD_WIDTH..(y + 1) * LCD_WIDTH;
+        let native = self.buffer[line.clone()].to_vec();
+        let mode = self.dispcnt.bg_mode();
+
+        let rows = y * scale * LCD_WIDTH * scale..(y + 1) * scale * LCD_WIDTH * scale;
+        let previous = self.hires_buffer[rows.clone()].to_vec();
+
+        // Mode 0 only has text backgrounds, the bitmap of modes 3 - 5 is transformed like affine ones.
+        let affine_bgs = mode != 0;
+        let affine_objs = self.dispcnt.obj()
+            && self.current_sprites[..self.sprites_in_budget()].iter().any(|sprite| sprite.rot_scale);
+
+        for sub_y in 0..scale {
+            for sub_x in 0..scale {
+                if affine_bgs || affine_objs {
+                    self.current_bg_line = bg_line;
+                    self.current_sprite_line = sprite_line;
+                    self.obj_win_mask = obj_win_mask;
+
+                    // BG2 is affine in modes 1 and 2 and the bitmap after that, BG3 only in mode 2.
+                    if affine_bgs && self.dispcnt.bg2() {
+                        self.current_bg_line[2] = [None; 512];
+                        match mode {
+                            1 | 2 => self.render_affine_bg::<2>(vram, palette_ram, (sub_x, sub_y)),
+                            _ => self.render_bitmap_bg(vram, palette_ram, (sub_x, sub_y)),
+                        }
+                    }
+                    if mode == 2 && self.dispcnt.bg3() {
+                        self.current_bg_line[3] = [None; 512];
+                        self.render_affine_bg::<3>(vram, palette_ram, (sub_x, sub_y));
+                    }
+                    if affine_objs {
+                        self.render_sprite_line(vram, palette_ram, (sub_x, sub_y));
+                    }
+
+                    self.draw_line(palette_ram);
+                }
+
+                let row = (y * scale + sub_y) * LCD_WIDTH * scale;
+                for (x, px) in self.buffer[line.clone()].iter().enumerate() {
+                    self.hires_buffer[row + x * scale + sub_x] = *px;
                 }
             }
-        } else {
-            todo!("sprites in mode 4 and 5");
+        }
+
+        self.dirty |= self.hires_buffer[rows] != previous[..];
+
+        match self.supersample {
+            true => self.downsample_line(palette_ram),
+            false => self.buffer[line].copy_from_slice(&native),
         }
     }
 
-    /// Render one background scanline fully. (Mode 3 & 4 render directly into `self.buffer`)
+    /// Average the `internal_scale`² samples of each pixel of the current line into `buffer`.
+    /// Only pixels with no sample at all stay empty, the others blend with the backdrop.
+    fn downsample_line(&mut self, palette_ram: &[u8]) {
+        let (scale, y) = (self.internal_scale, self.vcount.ly() as usize);
+        let backdrop = u16::from_le_bytes([palette_ram[0], palette_ram[1]]);
+        let count = (scale * scale) as u32;
+
+        for x in 0..LCD_WIDTH {
+            let samples = (0..scale * scale).map(|i| {
+                let row = (y * scale + i / scale) * LCD_WIDTH * scale;
+                self.hires_buffer[row + x * scale + i % scale]
+            });
+
+            if samples.clone().all(|px| px.is_none()) {
+                self.buffer[y * LCD_WIDTH + x] = None;
+                continue;
+            }
+
+            let [r, g, b] = samples
+                .map(|px| px.unwrap_or(backdrop))
+                .fold([0; 3], |[r, g, b], px| {
+                    [r + (px & 0x1F) as u32, g + (px >> 5 & 0x1F) as u32, b + (px >> 10 & 0x1F) as u32]
+                })
+                .map(|sum| ((sum + count / 2) / count) as u16);
+
+            self.buffer[y * LCD_WIDTH + x] = Some(b << 10 | g << 5 | r);
+        }
+    }
+
+    /// Enabled backgrounds that exist in the current mode, e.g. mode 2 only has the affine BG2 and BG3.
+    fn enabled_bgs(&self) -> u8 {
+        let layers = match self.dispcnt.bg_mode() {
+            0 => 0b1111,
+            1 => 0b0111,
+            2 => 0b1100,
+            _ => 0b0100,
+        };
+
+        let enabled: u8 = bits!(self.dispcnt.0, 8..=11);
+        enabled & layers
+    }
+
+    /// Render one background scanline fully.
     fn update_bg_scanline(&mut self, vram: &[u8], palette_ram: &[u8]) {
         match self.dispcnt.bg_mode() {
             0 => {
@@ -283,7 +465,7 @@ impl Ppu {
                         if BG < 2 {
                             self.render_text_bg::<BG>(vram, palette_ram);
                         } else {
-                            self.render_affine_bg::<BG>(vram, palette_ram);
+                            self.render_affine_bg::<BG>(vram, palette_ram, (0, 0));
                         }
                     }
                 });
@@ -292,28 +474,14 @@ impl Ppu {
                 self.current_bg_line = [[None; 512]; 4];
                 seq!(BG in 2..=3 {
                     if self.dispcnt.bg~BG() {
-                        self.render_affine_bg::<BG>(vram, palette_ram);
+                        self.render_affine_bg::<BG>(vram, palette_ram, (0, 0));
                     }
                 });
             }
-            3 => {
-                let start = self.vcount.ly() as usize * LCD_WIDTH * 2;
-                let line = &vram[start..(start + 480)];
-
-                for (i, px) in line.chunks(2).enumerate() {
-                    self.buffer[(start / 2) + i] = Some(u16::from_be_bytes([px[1], px[0]]));
-                }
-            }
-            4 => {
-                // TODO: this mode has two frames.
-                let start = self.vcount.ly() as usize * LCD_WIDTH;
-                let line = &vram[start..(start + LCD_WIDTH)];
-
-                for (i, px) in line.iter().enumerate() {
-                    let c0 = palette_ram[*px as usize * 2];
-                    let c1 = palette_ram[*px as usize * 2 + 1];
-
-                    self.buffer[start + i] = Some(u16::from_be_bytes([c1, c0]));
+            3..=5 => {
+                self.current_bg_line = [[None; 512]; 4];
+                if self.dispcnt.bg2() {
+                    self.render_bitmap_bg(vram, palette_ram, (0, 0));
                 }
             }
             _ => {}
@@ -365,6 +533,11 @@ impl Ppu {
             let tile_off = if v_flip { 7 - (y_off % 8) } else { y_off % 8 } * 8 + x_flip;
 
             let tile_addr = tile_start_addr + tile_off / (2 >> bg_cnt.bpp() as usize);
+            // Backgrounds only see the first 64 KiB of VRAM, tiles past it are transparent.
+            if tile_addr >= 0x10000 {
+                continue;
+            }
+
             let (px_idx, px) = if !bg_cnt.bpp() {
                 // 4 bits per pixel -> 16 palettes w/ 16 colors (1 byte holds the data for two neighboring pixels).
                 let px_idx = ((vram[tile_addr] >> ((tile_off & 1) * 4)) & 0xF) as usize;
@@ -383,29 +556,17 @@ impl Ppu {
                 ]))
             };
 
-            let mosaic_h = self.mosaic.bg_mosaic_h() as usize;
-            let mosaic_v = self.mosaic.bg_mosaic_v() as u16;
-
-            if !bg_cnt.mosaic() && px_idx != 0 {
+            if px_idx != 0 {
                 self.current_bg_line[BG][x] = Some(px);
-            } else {
-                if x % (mosaic_h + 1) == 0 && self.vcount.ly() as u16 % (mosaic_v + 1) == 0 {
-                    self.current_bg_line[BG][x] = (px_idx != 0).then_some(px);
-                    self.bg_mosaic_v_buf[BG][x] = (px_idx != 0).then_some(px);
-                } else {
-                    if self.vcount.ly() as u16 % (mosaic_v + 1) == 0 {
-                        self.current_bg_line[BG][x] = self.current_bg_line[BG][x - (x % (mosaic_h + 1))];
-                        self.bg_mosaic_v_buf[BG][x] = self.current_bg_line[BG][x - (x % (mosaic_h + 1))];
-                    } else {
-                        self.current_bg_line[BG][x] = self.bg_mosaic_v_buf[BG][x];
-                    }
-                }
             }
         }
+
+        self.apply_bg_mosaic::<BG>();
     }
 
+    /// `sub` is the sub-pixel position in units of `1 / internal_scale` pixels, `(0, 0)` natively.
     #[rustfmt::skip]
-    fn render_affine_bg<const BG: usize>(&mut self, vram: &[u8], palette_ram: &[u8]) {
+    fn render_affine_bg<const BG: usize>(&mut self, vram: &[u8], palette_ram: &[u8], (sub_x, sub_y): (usize, usize)) {
         let bg_cnt = self.bgxcnt[BG];
         let screen_size = 128 << bg_cnt.screen_size();
 
@@ -413,38 +574,31 @@ impl Ppu {
         let mut bg_refy = self.internal_ref_xy[BG - 2] << 4 >> 4;
 
         let (pa, pc) = (self.bgxpa[BG - 2] as i32, self.bgxpc[BG - 2] as i32);
-        let tile_data = bg_cnt.char_base_block() as u32 * 0x4000;
-        let screen_y = self.vcount.ly() as i32;
+        let (pb, pd) = (self.bgxpb[BG - 2] as i32, self.bgxpd[BG - 2] as i32);
+        let (sub_x, sub_y, scale) = (sub_x as i32, sub_y as i32, self.internal_scale as i32);
+
+        bg_refx += (pa * sub_x + pb * sub_y) / scale;
+        bg_refy += (pc * sub_x + pd * sub_y) / scale;
+
+        let tile_data = bg_cnt.char_base_block() as usize * 0x4000;
+        let map_base = bg_cnt.screen_base_block() as usize * 0x800;
+        let wrap = bg_cnt.disp_area_overflow();
 
         // Screen space -> Texture space.
         for screen_x in 0..LCD_WIDTH {
-            let mut tx = (bg_refx + screen_x as i32) >> 8;
-            let mut ty = (bg_refy + screen_y) >> 8;
+            let tx = affine_coord(bg_refx >> 8, screen_size, wrap);
+            let ty = affine_coord(bg_refy >> 8, screen_size, wrap);
 
             bg_refx += pa;
             bg_refy += pc;
 
-            // Transparency or Wraparound when display area overflow.
-            if !bg_cnt.disp_area_overflow() {
-                if !(0..screen_size).contains(&tx)
-                    || !(0..screen_size).contains(&ty)
-                {
-                    continue;
-                }
-            } else {
-                tx = tx.rem_euclid(screen_size);
-                ty = ty.rem_euclid(screen_size);
-            }
-
-            // Why was this `2 * ...` here before?
-            let map_data = bg_cnt.screen_base_block() as u32 * 0x800
-                + 1 * ((screen_size as u32 / 8) * (ty as u32 / 8) + (tx as u32 / 8));
-
-            let tile_id = vram[map_data as usize];
-            let tile_start_addr = tile_data as usize + (tile_id as usize & 0x3FF) * 64;
+            let (Some(tx), Some(ty)) = (tx, ty) else {
+                continue;
+            };
 
-            let tile_off = (ty as usize % 8) * 8 + (tx as usize % 8);
-            let tile_addr = tile_start_addr + tile_off;
+            // One byte per map entry, so at most 256 tiles, always 8bpp (64 bytes).
+            let tile_id = vram[map_base + ty / 8 * (screen_size as usize / 8) + tx / 8] as usize;
+            let tile_addr = tile_data + tile_id * 64 + (ty % 8) * 8 + tx % 8;
 
             let (px_idx, px) = {
                 let px_idx = vram[tile_addr] as usize;
@@ -459,25 +613,116 @@ impl Ppu {
                 self.current_bg_line[BG][screen_x] = Some(px);
             }
         }
+
+        self.apply_bg_mosaic::<BG>();
+    }
+
+    /// Render the bitmap of modes 3 - 5 as BG2, it goes through the BG2 affine transform
+    /// like `render_affine_bg` but never wraps, everything outside of the bitmap is transparent.
+    ///
+    /// Mode 3 is a 240x160 direct color bitmap, mode 4 two frames of 240x160 palette indices
+    /// (index 0 is transparent) and mode 5 two frames of 160x128 direct colors.
+    /// The second frame starts at 0xA000.
+    #[rustfmt::skip]
+    fn render_bitmap_bg(&mut self, vram: &[u8], palette_ram: &[u8], (sub_x, sub_y): (usize, usize)) {
+        let mode = self.dispcnt.bg_mode();
+        let frame = match mode != 3 && self.dispcnt.frame_select() {
+            true => 0xA000,
+            false => 0,
+        };
+        let (width, height) = if mode == 5 { (160, 128) } else { (240, 160) };
+
+        let mut bg_refx = self.internal_ref_xx[0] << 4 >> 4;
+        let mut bg_refy = self.internal_ref_xy[0] << 4 >> 4;
+
+        let (pa, pc) = (self.bgxpa[0] as i32, self.bgxpc[0] as i32);
+        let (pb, pd) = (self.bgxpb[0] as i32, self.bgxpd[0] as i32);
+        let (sub_x, sub_y, scale) = (sub_x as i32, sub_y as i32, self.internal_scale as i32);
+
+        bg_refx += (pa * sub_x + pb * sub_y) / scale;
+        bg_refy += (pc * sub_x + pd * sub_y) / scale;
+
+        for screen_x in 0..LCD_WIDTH {
+            let (tx, ty) = (bg_refx >> 8, bg_refy >> 8);
+
+            bg_refx += pa;
+            bg_refy += pc;
+
+            if !(0..width).contains(&tx) || !(0..height).contains(&ty) {
+                continue;
+            }
+
+            let offset = (ty * width + tx) as usize;
+            let px = match mode {
+                4 => match vram[frame + offset] as usize {
+                    0 => continue,
+                    px_idx => u16::from_le_bytes([palette_ram[px_idx * 2], palette_ram[px_idx * 2 + 1]]),
+                },
+                _ => u16::from_le_bytes([vram[frame + offset * 2], vram[frame + offset * 2 + 1]]),
+            };
+
+            self.current_bg_line[2][screen_x] = Some(px & 0x7FFF);
+        }
+
+        self.apply_bg_mosaic::<2>();
+    }
+
+    /// Snap the finished line of `BG` to the MOSAIC grid, only if its BGxCNT mosaic bit is set.
+    ///
+    /// The first line of every block is kept for the following ones in `bg_mosaic_v_buf`.
+    fn apply_bg_mosaic<const BG: usize>(&mut self) {
+        if !self.bgxcnt[BG].mosaic() {
+            return;
+        }
+
+        let mosaic_h = self.mosaic.bg_mosaic_h() as usize + 1;
+        let mosaic_v = self.mosaic.bg_mosaic_v() as u16 + 1;
+
+        if self.vcount.ly() as u16 % mosaic_v == 0 {
+            let line = &mut self.current_bg_line[BG];
+            for x in 0..LCD_WIDTH {
+                line[x] = line[x - x % mosaic_h];
+            }
+
+            self.bg_mosaic_v_buf[BG] = *line;
+        } else {
+            self.current_bg_line[BG] = self.bg_mosaic_v_buf[BG];
+        }
     }
 
     /// Render all sprites in OAM at the current line.
     ///
     /// Sprite prio x > BG prio x for x in [0, 3].
+    /// `sub` moves affine sprites by a fraction of a pixel like in `render_affine_bg`.
     #[rustfmt::skip]
-    fn render_sprite_line(&mut self, vram: &[u8], palette_ram: &[u8]) {
+    fn render_sprite_line(&mut self, vram: &[u8], palette_ram: &[u8], (sub_x, sub_y): (usize, usize)) {
+        self.obj_win_mask = [false; 512];
         if !self.dispcnt.obj() {
             return;
         }
 
         self.current_sprite_line = [Obj { prio: u8::MAX, ..Default::default() }; 512];
-        for sprite in self.current_sprites.iter().rev() {
+        for sprite in self.current_sprites[..self.sprites_in_budget()].iter().rev() {
             if !sprite.rot_scale && sprite.double_or_disable {
                 continue;
             }
 
-            // Difference of y inside the sprite.
-            let y = (self.vcount.ly() - sprite.y) as i16;
+            // In modes 3-5, the lower half of OBJ VRAM holds the bitmap, sprites using tiles
+            // below 512 aren't drawn. Where the bitmap frame starts (`frame_select`) doesn't matter.
+            if self.dispcnt.bg_mode() >= 3 && sprite.tile_id < 512 {
+                continue;
+            }
+
+            // Mosaic sprites show the top left pixel of each mosaic block, the blocks are aligned
+            // to the screen but clamped to the sprite's first row and column.
+            let (mosaic_h, mosaic_v) = match sprite.mosaic {
+                true => (self.mosaic.obj_mosaic_h() as i16 + 1, self.mosaic.obj_mosaic_v() as i16 + 1),
+                false => (1, 1),
+            };
+
+            // Difference of y inside the sprite, sprites can start above the screen (y wraps at 256).
+            let y = self.vcount.ly().wrapping_sub(sprite.y) as i16;
+            let y = (y - self.vcount.ly() as i16 % mosaic_v).max(0);
 
             // Use identity matrix for regular sprites and the correct params for affine.
             let (pa, pb, pc, pd) = match sprite.rot_scale {
@@ -488,17 +733,26 @@ impl Ppu {
             let width = sprite.width() << sprite.double_or_disable as u8;
             let height = sprite.height() << sprite.double_or_disable as u8;
 
+            // Sub-pixel sampling position in texture space, 0 for regular sprites.
+            let (sub_tx, sub_ty) = match sprite.rot_scale {
+                true => {
+                    let (sub_x, sub_y, scale) = (sub_x as i32, sub_y as i32, self.internal_scale as i32);
+                    ((pa as i32 * sub_x + pb as i32 * sub_y) / scale, (pc as i32 * sub_x + pd as i32 * sub_y) / scale)
+                }
+                false => (0, 0),
+            };
+
             for spx in 0..width {
                 // "Local" sprite coordinates within its bounding box.
                 let signed_sprite_x = (sprite.x << 7) as i16 >> 7;
                 let sprite_x = if signed_sprite_x >= 240 { signed_sprite_x - 512 } else { signed_sprite_x };
 
                 let spx_off = sprite_x + spx as i16;
-                let x = spx as i16;
+                let x = (spx as i16 - spx_off.rem_euclid(mosaic_h)).max(0);
 
                 // Transform into texture space with affine transformation.
-                let mut tx = (pa as i32 * (x - (width as i16 / 2)) as i32 + pb as i32 * (y - (height as i16 / 2)) as i32) >> 8;
-                let mut ty = (pc as i32 * (x - (width as i16 / 2)) as i32 + pd as i32 * (y - (height as i16 / 2)) as i32) >> 8;
+                let mut tx = (pa as i32 * (x - (width as i16 / 2)) as i32 + pb as i32 * (y - (height as i16 / 2)) as i32 + sub_tx) >> 8;
+                let mut ty = (pc as i32 * (x - (width as i16 / 2)) as i32 + pd as i32 * (y - (height as i16 / 2)) as i32 + sub_ty) >> 8;
 
                 // Adjust sprite center.
                 tx += ((width as i32) / 2) >> sprite.double_or_disable as i32;
@@ -542,77 +796,71 @@ impl Ppu {
                         false => ty as u16 / 8 * vram_mapping_constant
                     };
 
-                // In modes 3-5, only tile numbers 512-1023 may be used, lower memory is used for background.
-                let tile_addr = match self.dispcnt.bg_mode() < 3 {
-                    true => 0x10000 + (tile_id as usize % 1024) * 32,
-                    false => 0x14000 + (tile_id as usize % 512) * 32,
-                };
+                // Tiles are numbered from the start of OBJ VRAM in every mode, tile 512 is at 0x14000.
+                let tile_addr = 0x10000 + (tile_id as usize % 1024) * 32;
 
                 let screen_x = spx_off as usize;
                 let tile_off = if sprite.v_flip && !sprite.rot_scale { 7 - (ty as u16 % 8) } else { ty as u16 % 8 }
                     * 8 + if sprite.h_flip && !sprite.rot_scale { 7 - (tx as u16 % 8) } else { tx as u16 % 8 };
 
+                // The last tiles of a sprite can reach past the end of OBJ VRAM, wrap within its 32 KiB.
+                let obj_vram = |off: usize| 0x10000 + ((tile_addr - 0x10000 + off) & 0x7FFF);
+
                 let (px_idx, px) = if !sprite.bpp {
-                    let px_idx = (vram[tile_addr as usize + tile_off as usize / 2] >> ((tile_off & 1) * 4)) & 0xF;
+                    let px_idx = (vram[obj_vram(tile_off as usize / 2)] >> ((tile_off & 1) * 4)) & 0xF;
                     (px_idx, u16::from_be_bytes([
                         palette_ram[0x200 + (sprite.pal_idx as usize * 0x20) | px_idx as usize * 2 + 1],
                         palette_ram[0x200 + (sprite.pal_idx as usize * 0x20) | px_idx as usize * 2],
                     ]))
                 } else {
-                    let px_idx = vram[tile_addr as usize + tile_off as usize];
+                    let px_idx = vram[obj_vram(tile_off as usize)];
                     (px_idx, u16::from_be_bytes([
                         palette_ram[0x200 + px_idx as usize * 2 + 1],
                         palette_ram[0x200 + px_idx as usize * 2],
                     ]))
                 };
 
-                let mosaic_h = self.mosaic.obj_mosaic_h() as usize;
-                let mosaic_v = self.mosaic.obj_mosaic_v() as usize;
-
-                if !sprite.mosaic {
-                    if px_idx != 0 && sprite.obj_mode != ObjMode::Window {
-                        self.current_sprite_line[screen_x] = Obj { 
-                            px: Some(px), 
-                            prio: sprite.prio, 
-                            alpha: sprite.obj_mode == ObjMode::SemiTransparent,
-                            window: sprite.obj_mode == ObjMode::Window,
-                        };
-                    }
-                } else {
-                    if screen_x % (mosaic_h + 1) == 0 && self.vcount.ly() as usize % (mosaic_v + 1) == 0 {
-                        if px_idx != 0 && sprite.obj_mode != ObjMode::Window { 
-                            self.current_sprite_line[screen_x] = Obj { 
-                                px: Some(px), 
-                                prio: sprite.prio, 
-                                alpha: sprite.obj_mode == ObjMode::SemiTransparent,
-                                window: sprite.obj_mode == ObjMode::Window,
-                            };
-                        }
-                        self.obj_mosaic_v_buf[screen_x] = self.current_sprite_line[screen_x];
-                    } else {
-                        if self.vcount.ly() as usize % (mosaic_v + 1) == 0 {
-                            self.current_sprite_line[screen_x] = self.current_sprite_line[screen_x - (screen_x % (mosaic_h + 1))];
-                            self.obj_mosaic_v_buf[screen_x] = self.current_sprite_line[screen_x];
-                        } else {
-                            self.current_sprite_line[screen_x] = self.obj_mosaic_v_buf[screen_x];
-                        }
-                    }
+                if px_idx != 0 && sprite.obj_mode != ObjMode::Window {
+                    self.current_sprite_line[screen_x] = Obj {
+                        px: Some(px),
+                        prio: sprite.prio,
+                        alpha: sprite.obj_mode == ObjMode::SemiTransparent,
+                        window: sprite.obj_mode == ObjMode::Window,
+                    };
                 }
 
-                // If sprite has ObjWindow, don't draw and save (x, y) position.
+                // If sprite has ObjWindow, don't draw and mark the pixel as inside the OBJ window.
                 if sprite.obj_mode == ObjMode::Window && px_idx != 0 {
-                    self.obj_window_buf.insert((screen_x, self.vcount.ly() as usize));
+                    self.obj_win_mask[screen_x] = true;
                 }
             }
         }
     }
 
-    /// Draw the background scanline and sprites by placing it into the buffer. (For mode 0, 1, 2).
+    /// How many of `current_sprites` (in OAM order) fit into the line's OBJ cycles,
+    /// the ones after that aren't drawn.
+    fn sprites_in_budget(&self) -> usize {
+        let budget = match self.dispcnt.hblank_interval_free() {
+            true => OBJ_CYCLES_HBLANK_FREE,
+            false => OBJ_CYCLES,
+        };
+
+        let mut cycles = 0;
+        self.current_sprites
+            .iter()
+            .take_while(|sprite| {
+                cycles += sprite.render_cycles();
+                cycles <= budget
+            })
+            .count()
+    }
+
+    /// Draw the background scanline and sprites by placing it into the buffer.
     fn draw_line(&mut self, palette_ram: &[u8]) {
         let y = self.vcount.ly() as usize;
 
         // Get bits 8..=11 to get bg-enable bits.
-        let is_bg_enabled: u8 = bits!(self.dispcnt.0, 8..=11);
+        let is_bg_enabled = self.enabled_bgs();
         let backdrop = u16::from_le_bytes([palette_ram[0], palette_ram[1]]);
 
         let mut bg_sorted = [0, 1, 2, 3];
@@ -681,7 +929,7 @@ impl Ppu {
                     if self.current_sprite_line[x].prio <= self.bgxcnt[prio_layer].prio() {
                         sp.or(bg)
                     } else {
-                        if ((prio_layer + 1)..self.current_sprite_line[x].prio as usize)
+                        if ((prio_layer + 1)..(self.current_sprite_line[x].prio as usize).min(4))
                             .any(|x| is_bg_enabled & (1 << x) != 0) 
                         {
                             bg
@@ -708,7 +956,6 @@ impl Ppu {
             render_line[x] = render_line[x].or(final_px);
         }
 
-        self.obj_window_buf.clear();
         for x in 0..LCD_WIDTH {
             self.buffer[y * LCD_WIDTH + x] = render_line[x];
         }
@@ -721,14 +968,14 @@ impl Ppu {
         let src: u8 = bits!(self.bldcnt.0, 0..=5);
         let dst: u8 = bits!(self.bldcnt.0, 8..=13);
 
-        let enabled_bgs: u8 = bits!(self.dispcnt.0, 8..=11);
+        let enabled_bgs = self.enabled_bgs();
         let Ok(color_effect) = self.bldcnt.color_effect() else {
             return;
         };
 
         for x in 0..512 {
-            // Top two layers (pixel, prio, bg, obj_alpha).
-            let mut layers = ([backdrop; 2], [4u8; 2], [0usize; 2], false);
+            // Top two layers (pixel, prio, bg, obj_alpha), the backdrop is layer 5 in BLDCNT.
+            let mut layers = ([backdrop; 2], [4u8; 2], [5usize; 2], false);
 
             let window = self.in_window(x, self.vcount.ly() as usize);
             let window_sfx = match window {
@@ -797,16 +1044,15 @@ impl Ppu {
                 }
             }
 
-            // Obj Alpha.
-            if layers.3 {
-                if dst & (1 << layers.2[1]) != 0 {
-                    layers.0[0] = blend(
-                        layers.0[0],
-                        layers.0[1],
-                        self.bldalpha.eva(),
-                        self.bldalpha.evb(),
-                    );
-                }
+            // A semi-transparent OBJ on top always alpha blends with a 2nd target below it,
+            // whatever effect BLDCNT selects. Without one, the regular effect applies.
+            if layers.3 && window_sfx && dst & (1 << layers.2[1]) != 0 {
+                layers.0[0] = blend(
+                    layers.0[0],
+                    layers.0[1],
+                    self.bldalpha.eva(),
+                    self.bldalpha.evb(),
+                );
                 self.current_sprite_line[x].px = self.current_sprite_line[x].px.map(|_| layers.0[0]);
             } else if window_sfx {
                 match color_effect {
@@ -830,12 +1076,15 @@ impl Ppu {
                             layers.0[0] = modify_brightness::<false>(layers.0[0], self.bldy.evy());
                         }
                     }
-                    ColorEffect::None => return,
+                    ColorEffect::None => continue,
                 }
 
-                let layer_idx = if layers.2[0] == 4 { layers.2[1] } else { layers.2[0] };
-                if enabled_bgs & (1 << layer_idx) != 0 {
-                    self.current_bg_line[layer_idx][x] = self.current_bg_line[layer_idx][x].map(|_| layers.0[0]);
+                match layers.2[0] {
+                    4 => self.current_sprite_line[x].px = self.current_sprite_line[x].px.map(|_| layers.0[0]),
+                    bg if enabled_bgs & (1 << bg) != 0 => {
+                        self.current_bg_line[bg][x] = self.current_bg_line[bg][x].map(|_| layers.0[0]);
+                    }
+                    _ => {}
                 }
             }
         }
@@ -854,12 +1103,12 @@ impl Ppu {
             let y1 = (self.winxv[win] >> 8) as usize;
             let y2 = (self.winxv[win] & 0xFF) as usize;
 
-            if x >= x1 && x < x2 && y >= y1 && y < y2 {
+            if in_window_range(x, x1, x2) && in_window_range(y, y1, y2) {
                 return if win == 0 { Window::Win0 } else { Window::Win1 };
             }
         }
 
-        if self.dispcnt.obj_win() && self.obj_window_buf.contains(&(x, y)) {
+        if self.dispcnt.obj_win() && self.obj_win_mask[x] {
             return Window::ObjWin;
         }
 
@@ -867,8 +1116,31 @@ impl Ppu {
     }
 }
 
+/// Whether `v` is inside a window edge pair, `end` exclusive. An `end` past the screen edge
+/// acts as the edge. If `start` > `end`, the window wraps around and covers `[start, edge)`
+/// and `[0, end)`.
+fn in_window_range(v: usize, start: usize, end: usize) -> bool {
+    match start <= end {
+        true => v >= start && v < end,
+        false => v >= start || v < end,
+    }
+}
+
+/// Texture coordinate `t` of an affine BG with a `size`x`size` map, wrapped around
+/// with display area overflow, `None` (transparent) outside of the map otherwise.
+fn affine_coord(t: i32, size: i32, wrap: bool) -> Option<usize> {
+    match wrap {
+        true => Some(t.rem_euclid(size) as usize),
+        false => (0..size).contains(&t).then_some(t as usize),
+    }
+}
+
 impl Mcu for Ppu {
     fn read16(&mut self, address: u32) -> u16 {
+        self.peek16(address)
+    }
+
+    fn peek16(&self, address: u32) -> u16 {
         match address {
             0x0000 => self.dispcnt.dispcnt(),
             0x0004 => self.dispstat.dispstat(),
@@ -880,6 +1152,8 @@ impl Mcu for Ppu {
             0x0048 => self.winin.winin(),
             0x004A => self.winout.winout(),
             0x0050 => self.bldcnt.bldcnt(),
+            // Only the coefficients read back, bits 5-7 and 13-15 are unused.
+            0x0052 => self.bldalpha.bldalpha() & 0x1F1F,
             _ => 0,
         }
     }
@@ -893,10 +1167,13 @@ impl Mcu for Ppu {
 
     fn write16(&mut self, address: u32, value: u16) {
         match address {
-            0x0000 => self.dispcnt.set_dispcnt(value),
-            0x0004 => self.dispstat.set_dispstat((value & !0b111) | self.dispstat.0 & 0b111),
-            0x0008 => self.bgxcnt[0].set_bg_control(value),
-            0x000A => self.bgxcnt[1].set_bg_control(value),
+            // Bit 3 (CGB mode) can only be set by BIOS opcodes.
+            0x0000 => self.dispcnt.set_dispcnt(value & !(1 << 3)),
+            // Bits 0-2 are read-only status, 3-5 IRQ enables, 6-7 unused and 8-15 LYC.
+            0x0004 => self.dispstat.set_dispstat((value & 0xFF38) | (self.dispstat.0 & 0b111)),
+            // Text-only BG0/BG1 have no display area overflow bit.
+            0x0008 => self.bgxcnt[0].set_bg_control(value & !(1 << 13)),
+            0x000A => self.bgxcnt[1].set_bg_control(value & !(1 << 13)),
             0x000C => self.bgxcnt[2].set_bg_control(value),
             0x000E => self.bgxcnt[3].set_bg_control(value),
             0x0010 => self.bgxhofs[0] = value,
@@ -951,10 +1228,11 @@ impl Mcu for Ppu {
             0x0042 => self.winxh[1] = value,
             0x0044 => self.winxv[0] = value,
             0x0046 => self.winxv[1] = value,
-            0x0048 => self.winin.set_winin(value),
-            0x004A => self.winout.set_winout(value),
+            // Bits 6-7 and 14-15 of the window and blend controls are unused.
+            0x0048 => self.winin.set_winin(value & 0x3F3F),
+            0x004A => self.winout.set_winout(value & 0x3F3F),
             0x004C => self.mosaic.set_mosaic(value),
-            0x0050 => self.bldcnt.set_bldcnt(value),
+            0x0050 => self.bldcnt.set_bldcnt(value & 0x3FFF),
             0x0052 => self.bldalpha.set_bldalpha(value),
             0x0054 => self.bldy.set_bldy(value),
             _ => {}
@@ -1093,7 +1371,7 @@ bitfield! {
 }
 
 bitfield! {
-    /// **BLDALPHA - Alpha Blending Coefficients** (w).
+    /// **BLDALPHA - Alpha Blending Coefficients** (r/w).
     #[derive(Clone, Copy, Default)]
     pub struct BLDALPHA(pub u16) {
         pub bldalpha: u16 @ ..,
@@ -1161,4 +1439,677 @@ bitfield! {
         pub obj_mosaic_h: u8 @ 8..=11,
         pub obj_mosaic_v: u8 @ 12..=15,
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mmu::bus::Bus, rng::Rng};
+
+    const FRAME_CYCLES: u64 = 1232 * 228;
+
+    /// Random VRAM, palettes, OAM and LCD registers in `mode` with every layer on.
+    fn scene(seed: u64, mode: u16, scale: usize) -> Bus {
+        let mut bus = Bus::default();
+        bus.ppu.set_internal_scale(scale);
+
+        let mut rng = Rng::new(seed);
+        rng.fill(&mut bus.vram[..]);
+        rng.fill(&mut bus.palette_ram);
+        rng.fill(&mut bus.oam);
+
+        for address in (0x0400_0008..0x0400_0056).step_by(2) {
+            bus.write16(address, rng.u32() as u16);
+        }
+        // All BGs and OBJ, random windows, no forced blank.
+        bus.write16(0x0400_0000, rng.u32() as u16 & 0xE040 | 0x1F00 | mode);
+
+        bus
+    }
+
+    fn run_frame(bus: &mut Bus, frame: u64) {
+        for cycle in frame * FRAME_CYCLES..(frame + 1) * FRAME_CYCLES {
+            bus.tick(cycle);
+        }
+    }
+
+    #[test]
+    fn internal_scale_keeps_native_output() {
+        for mode in 0..=5 {
+            for seed in 1..=2 {
+                let mut native = scene(seed, mode, 1);
+                run_frame(&mut native, 0);
+
+                for scale in [2, 3] {
+                    let mut hires = scene(seed, mode, scale);
+                    run_frame(&mut hires, 0);
+                    assert!(native.ppu.buffer == hires.ppu.buffer, "mode {mode}, seed {seed}, scale {scale}");
+
+                    // The first sub-pixel sample is the native pixel.
+                    for (i, px) in native.ppu.buffer.iter().enumerate() {
+                        let (x, y) = (i % LCD_WIDTH, i / LCD_WIDTH);
+                        let sample = hires.ppu.hires_buffer[y * scale * LCD_WIDTH * scale + x * scale];
+                        assert_eq!(*px, sample, "mode {mode}, seed {seed}, scale {scale} at {x}, {y}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unchanged_hires_frame_is_not_dirty() {
+        for mode in 0..=5 {
+            let mut bus = scene(1, mode, 2);
+            run_frame(&mut bus, 0);
+            let frame_seq = bus.ppu.frame_seq;
+
+            run_frame(&mut bus, 1);
+            assert_eq!(bus.ppu.frame_seq, frame_seq, "mode {mode}");
+        }
+    }
+
+    #[test]
+    fn forced_blank_frame_is_white() {
+        for scale in [1, 2] {
+            let mut bus = scene(1, 0, scale);
+            run_frame(&mut bus, 0);
+            assert!(bus.ppu.buffer.iter().any(|&px| px != Some(0x7FFF)), "scale {scale}");
+            let frame_seq = bus.ppu.frame_seq;
+
+            let dispcnt = bus.read16(0x0400_0000);
+            bus.write16(0x0400_0000, dispcnt | 0x0080);
+            run_frame(&mut bus, 1);
+            assert!(bus.ppu.buffer.iter().all(|&px| px == Some(0x7FFF)), "scale {scale}");
+            assert!(bus.ppu.hires_buffer.iter().all(|&px| px == Some(0x7FFF)), "scale {scale}");
+            assert_ne!(bus.ppu.frame_seq, frame_seq, "scale {scale}");
+        }
+    }
+
+    #[test]
+    fn dispstat_write_mask() {
+        let mut bus = Bus::default();
+
+        // Status bits 0-2 and unused bits 6-7 never take a write.
+        bus.write16(0x0400_0004, 0xFFFF);
+        assert_eq!(bus.read16(0x0400_0004), 0xFF38);
+
+        // LYC 5 with only the VCount IRQ enabled.
+        bus.write16(0x0400_0004, 0x0520);
+        assert_eq!(bus.read16(0x0400_0004), 0x0520);
+
+        let line = |cycle: u64| cycle / 1232;
+        for cycle in 0..7 * 1232 {
+            bus.tick(cycle);
+            let dispstat = bus.read16(0x0400_0004);
+            assert_eq!(dispstat & 0xFF38, 0x0520, "cycle {cycle}");
+            assert_eq!(dispstat & 0b100 != 0, line(cycle + 1) == 5, "VCount match, cycle {cycle}");
+            assert_eq!(bus.iff.vcount(), line(cycle + 1) >= 5, "IRQ, cycle {cycle}");
+
+            // The status bits of the current line survive rewriting the register.
+            bus.write16(0x0400_0004, 0x0520 | (!dispstat & 0b111));
+            assert_eq!(bus.read16(0x0400_0004), dispstat, "cycle {cycle}");
+        }
+    }
+
+    #[test]
+    fn bldalpha_reads_back_bldy_is_open_bus() {
+        let mut bus = Bus::default();
+        bus.set_open_bus(0x1122_3344);
+
+        // Only the two 5-bit coefficients read back.
+        bus.write16(0x0400_0052, 0xFFFF);
+        assert_eq!(bus.read16(0x0400_0052), 0x1F1F);
+        bus.write8(0x0400_0053, 0x0A);
+        assert_eq!(bus.read16(0x0400_0052), 0x0A1F);
+        assert_eq!(bus.read8(0x0400_0053), 0x0A);
+
+        // BLDY still takes the write, but reading it gives the open bus value.
+        bus.write16(0x0400_0054, 0x0010);
+        assert_eq!(bus.ppu.bldy.evy(), 16);
+        assert_eq!(bus.read16(0x0400_0054), 0x3344);
+        assert_eq!(bus.read8(0x0400_0055), 0x33);
+    }
+
+    #[test]
+    fn all_ones_write_masks() {
+        let mut bus = Bus::default();
+        bus.set_open_bus(0x1122_3344);
+
+        // Readable registers and what sticks of an all-ones write.
+        for (address, mask) in [
+            (0x0400_0000, 0xFFF7),
+            (0x0400_0004, 0xFF38),
+            (0x0400_0008, 0xDFFF),
+            (0x0400_000A, 0xDFFF),
+            (0x0400_000C, 0xFFFF),
+            (0x0400_000E, 0xFFFF),
+            (0x0400_0048, 0x3F3F),
+            (0x0400_004A, 0x3F3F),
+            (0x0400_0050, 0x3FFF),
+            (0x0400_0052, 0x1F1F),
+        ] {
+            bus.write16(address, 0xFFFF);
+            assert_eq!(bus.read16(address), mask, "{address:08X}");
+            bus.write16(address, 0);
+            assert_eq!(bus.read16(address), 0, "{address:08X}");
+        }
+
+        // Write-only registers and the unused halfwords between them.
+        let unused = [0x0400_004C, 0x0400_004E, 0x0400_0054, 0x0400_0056];
+        for address in (0x0400_0010..0x0400_0048).step_by(2).chain(unused) {
+            bus.write16(address, 0xFFFF);
+            assert_eq!(bus.read16(address), (0x1122_3344 >> ((address & 2) * 8)) as u16, "{address:08X}");
+        }
+
+        // Reference points only keep their 28 bits.
+        assert_eq!(bus.ppu.bgxx, [0x0FFF_FFFF; 2]);
+        assert_eq!(bus.ppu.bgxy, [0x0FFF_FFFF; 2]);
+    }
+
+    #[test]
+    fn frame_timing() {
+        let mut bus = Bus::default();
+        let (mut frames, mut lines, mut hblanks) = (Vec::new(), Vec::new(), Vec::new());
+        let mut last = (bus.ppu.frame, bus.read16(0x0400_0006), false);
+
+        for cycle in 0..3 * FRAME_CYCLES {
+            bus.tick(cycle);
+            let now = (bus.ppu.frame, bus.read16(0x0400_0006), bus.read16(0x0400_0004) & 0b10 != 0);
+            if now.0 != last.0 {
+                frames.push(cycle);
+            }
+            if now.1 != last.1 {
+                lines.push((cycle, now.1));
+            }
+            if now.2 && !last.2 {
+                hblanks.push(cycle);
+            }
+            last = now;
+        }
+
+        assert!(frames.windows(2).all(|w| w[1] - w[0] == 280_896), "frames at {frames:?}");
+        assert_eq!(frames.len(), 3);
+        assert!(lines.windows(2).all(|w| w[1].0 - w[0].0 == 1232 && w[1].1 == (w[0].1 + 1) % 228));
+        assert_eq!(lines.iter().filter(|(_, ly)| *ly == 227).count(), 3, "228 lines per frame");
+        // HBlank starts at the same dot of every line, VBlank lines included.
+        let hblank_len: Vec<u64> = hblanks.iter().zip(&lines).map(|(hblank, (line, _))| line - hblank).collect();
+        assert_eq!(hblank_len.len(), lines.len());
+        assert!(hblank_len.windows(2).all(|w| w[0] == w[1]), "HBlank lengths {hblank_len:?}");
+    }
+
+    #[test]
+    fn window_ranges() {
+        let covered = |start, end, edge| (0..edge).filter(|&v| in_window_range(v, start, end)).collect::<Vec<_>>();
+
+        assert_eq!(covered(10, 20, 240), (10..20).collect::<Vec<_>>());
+        assert!(covered(50, 50, 240).is_empty());
+        // Inverted edges wrap around the screen.
+        assert_eq!(covered(200, 40, 240), (0..40).chain(200..240).collect::<Vec<_>>());
+        assert_eq!(covered(250, 20, 240), (0..20).collect::<Vec<_>>());
+        // X2 > 240 and Y2 > 160 act as the screen edge.
+        assert_eq!(covered(100, 255, 240), (100..240).collect::<Vec<_>>());
+        assert_eq!(covered(100, 200, 160), (100..160).collect::<Vec<_>>());
+
+        // WIN0 wrapping horizontally, clamped vertically.
+        let mut ppu = Ppu::default();
+        ppu.write16(0x0040, 230 << 8 | 10);
+        ppu.write16(0x0044, 150 << 8 | 255);
+        ppu.write16(0x0000, 0x2000);
+        for (x, y, window) in [
+            (0, 150, Window::Win0),
+            (9, 159, Window::Win0),
+            (239, 150, Window::Win0),
+            (10, 150, Window::WinOut),
+            (229, 150, Window::WinOut),
+            (0, 149, Window::WinOut),
+        ] {
+            assert_eq!(ppu.in_window(x, y), window, "at {x}, {y}");
+        }
+    }
+
+    #[test]
+    fn affine_map_sizes() {
+        // Tile `n` is solid palette index `n`, and color `n` is `n`. Tile 0 stays transparent.
+        let tile = |x: usize, y: usize| ((x * 7 + y * 13) % 255 + 1) as u8;
+
+        for size_bits in 0..4 {
+            let size = 128 << size_bits;
+            for wrap in [false, true] {
+                let mut bus = Bus::default();
+                for n in 0..256 {
+                    bus.palette_ram[n * 2] = n as u8;
+                    bus.vram[n * 64..(n + 1) * 64].fill(n as u8);
+                }
+                for (y, x) in (0..size / 8).flat_map(|y| (0..size / 8).map(move |x| (y, x))) {
+                    bus.vram[0x4000 + y * size / 8 + x] = tile(x, y);
+                }
+
+                // Identity transform from 16x8 pixels before the map's bottom right corner, plus half a
+                // pixel that must never round up.
+                let (ref_x, ref_y) = (size as u32 - 16, size as u32 - 8);
+                bus.write16(0x0400_000C, (size_bits as u16) << 14 | (wrap as u16) << 13 | 8 << 8);
+                bus.write32(0x0400_0028, ref_x << 8 | 0x80);
+                bus.write32(0x0400_002C, ref_y << 8 | 0x80);
+                bus.write16(0x0400_0000, 0x0401);
+                run_frame(&mut bus, 0);
+
+                for (i, px) in bus.ppu.buffer[..LCD_WIDTH * LCD_HEIGHT].iter().enumerate() {
+                    let (tx, ty) = (ref_x as usize + i % LCD_WIDTH, ref_y as usize + i / LCD_WIDTH);
+                    let expected = match wrap || (tx < size && ty < size) {
+                        true => Some(tile(tx % size / 8, ty % size / 8) as u16),
+                        false => None,
+                    };
+                    assert_eq!(*px, expected, "{size}x{size}, wrap {wrap} at {}, {}", i % LCD_WIDTH, i / LCD_WIDTH);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn text_bg_512x512_quadrants() {
+        let mut bus = Bus::default();
+
+        // Screen blocks 8 to 11 are all tile 1 to 4, a solid 4bpp tile of color 1 to 4.
+        for n in 1..=4u16 {
+            bus.write16(0x0500_0000 + n as u32 * 2, n);
+            for address in (0x0600_0000 + n as u32 * 32..0x0600_0000 + (n as u32 + 1) * 32).step_by(2) {
+                bus.write16(address, n * 0x1111);
+            }
+            let block = 0x0600_0000 + (7 + n as u32) * 0x800;
+            for address in (block..block + 0x800).step_by(2) {
+                bus.write16(address, n);
+            }
+        }
+
+        // Scrolled across both boundaries, then wrapping around at 512 in both directions.
+        for (hofs, vofs) in [(136, 176), (400, 450)] {
+            bus.write16(0x0400_0008, 0xC000 | 8 << 8);
+            bus.write16(0x0400_0010, hofs);
+            bus.write16(0x0400_0012, vofs);
+            bus.write16(0x0400_0000, 0x0100);
+            run_frame(&mut bus, 0);
+
+            for (i, px) in bus.ppu.buffer[..LCD_WIDTH * LCD_HEIGHT].iter().enumerate() {
+                let (x, y) = ((i % LCD_WIDTH + hofs as usize) % 512, (i / LCD_WIDTH + vofs as usize) % 512);
+                let quadrant = x / 256 + y / 256 * 2;
+                assert_eq!(*px, Some(quadrant as u16 + 1), "scroll {hofs}, {vofs} at {}, {}", i % LCD_WIDTH, i / LCD_WIDTH);
+            }
+        }
+    }
+
+    #[test]
+    fn mosaic_4x4_blocks() {
+        let grid = |n: usize| n - n % 4;
+
+        // Mode 3 gradient on BG2.
+        let mut bus = Bus::default();
+        let gradient = |x: usize, y: usize| (x | y << 8) as u16 & 0x7FFF;
+        for (y, x) in (0..LCD_HEIGHT).flat_map(|y| (0..LCD_WIDTH).map(move |x| (y, x))) {
+            bus.write16(0x0600_0000 + (y * LCD_WIDTH + x) as u32 * 2, gradient(x, y));
+        }
+        bus.write16(0x0400_004C, 0x0033);
+        bus.write16(0x0400_000C, 0x0040);
+        bus.write16(0x0400_0000, 0x0403);
+        run_frame(&mut bus, 0);
+
+        for (i, px) in bus.ppu.buffer[..LCD_WIDTH * LCD_HEIGHT].iter().enumerate() {
+            let (x, y) = (i % LCD_WIDTH, i / LCD_WIDTH);
+            assert_eq!(*px, Some(gradient(grid(x), grid(y))), "BG at {x}, {y}");
+        }
+
+        // 16x16 8bpp gradient sprite at (8, 8), palette index `n` is color `n`.
+        let mut bus = Bus::default();
+        let gradient = |tx: usize, ty: usize| (1 + (tx + ty * 16) % 255) as u8;
+        for n in 0..256 {
+            bus.palette_ram[0x200 + n * 2] = n as u8;
+        }
+        for (ty, tx) in (0..16).flat_map(|ty| (0..16).map(move |tx| (ty, tx))) {
+            let tile = tx / 8 + ty / 8 * 2;
+            bus.vram[0x1_0000 + tile * 64 + ty % 8 * 8 + tx % 8] = gradient(tx, ty);
+        }
+        for address in (0x0700_0008..0x0700_0400).step_by(8) {
+            bus.write16(address, 0x0200);
+        }
+        bus.write16(0x0700_0000, 0x3000 | 8);
+        bus.write16(0x0700_0002, 0x4000 | 8);
+        bus.write16(0x0700_0004, 0);
+        bus.write16(0x0400_004C, 0x3300);
+        bus.write16(0x0400_0000, 0x1040);
+        run_frame(&mut bus, 0);
+
+        for (i, px) in bus.ppu.buffer[..LCD_WIDTH * LCD_HEIGHT].iter().enumerate() {
+            let (x, y) = (i % LCD_WIDTH, i / LCD_WIDTH);
+            let expected = ((8..24).contains(&x) && (8..24).contains(&y))
+                .then(|| gradient(grid(x) - 8, grid(y) - 8) as u16);
+            assert_eq!(*px, expected, "OBJ at {x}, {y}");
+        }
+    }
+
+    #[test]
+    fn mosaic_only_on_flagged_bg() {
+        let grid = |n: usize| n - n % 4;
+        let mut bus = Bus::default();
+
+        // 8bpp tile 1 counts up from palette index 1, and color `n` is `n`.
+        let texel = |x: usize, y: usize| (1 + x % 8 + y % 8 * 8) as u16;
+        for n in 0..256 {
+            bus.write16(0x0500_0000 + n * 2, n as u16);
+        }
+        for (y, x) in (0..8).flat_map(|y| (0..8).map(move |x| (y, x))) {
+            bus.vram[64 + y * 8 + x] = texel(x, y) as u8;
+        }
+
+        // BG0 covers the left 128 pixels with mosaic, BG1 the rest without.
+        for row in 0..32 {
+            for col in 0..32u32 {
+                let block = if col < 16 { 0x0600_F000 } else { 0x0600_F800 };
+                bus.write16(block + (row * 32 + col) * 2, 1);
+            }
+        }
+        bus.write16(0x0400_0008, 0x1EC0);
+        bus.write16(0x0400_000A, 0x1F80);
+        bus.write16(0x0400_004C, 0x0033);
+        bus.write16(0x0400_0000, 0x0300);
+        run_frame(&mut bus, 0);
+
+        for (i, px) in bus.ppu.buffer[..LCD_WIDTH * LCD_HEIGHT].iter().enumerate() {
+            let (x, y) = (i % LCD_WIDTH, i / LCD_WIDTH);
+            let expected = match x < 128 {
+                true => texel(grid(x), grid(y)),
+                false => texel(x, y),
+            };
+            assert_eq!(*px, Some(expected), "at {x}, {y}");
+        }
+    }
+
+    #[test]
+    fn mode4_frame_1_with_sprites() {
+        let mut bus = Bus::default();
+
+        // Frame 0 is palette index 1, frame 1 index 2, and color `n` is `n`.
+        for n in 0..4 {
+            bus.write16(0x0500_0000 + n * 2, n as u16);
+        }
+        bus.vram[..0x9600].fill(1);
+        bus.vram[0xA000..0x1_3600].fill(2);
+
+        // Solid 8x8 sprites at (16, 40) from tile 512 and at (64, 40) from tile 0, which overlaps
+        // frame 1 and must not show.
+        bus.write16(0x0500_0202, 0x7C00);
+        bus.write16(0x0500_0204, 0x03E0);
+        bus.vram[0x1_4000..0x1_4020].fill(0x11);
+        for address in (0x0700_0010..0x0700_0400).step_by(8) {
+            bus.write16(address, 0x0200);
+        }
+        bus.write16(0x0700_0000, 40);
+        bus.write16(0x0700_0002, 16);
+        bus.write16(0x0700_0004, 512);
+        bus.write16(0x0700_0008, 40);
+        bus.write16(0x0700_000A, 64);
+        bus.write16(0x0700_000C, 0);
+        bus.write16(0x0400_0000, 0x1454);
+        run_frame(&mut bus, 0);
+
+        for (i, px) in bus.ppu.buffer[..LCD_WIDTH * LCD_HEIGHT].iter().enumerate() {
+            let (x, y) = (i % LCD_WIDTH, i / LCD_WIDTH);
+            let expected = match (16..24).contains(&x) && (40..48).contains(&y) {
+                true => 0x7C00,
+                false => 2,
+            };
+            assert_eq!(*px, Some(expected), "at {x}, {y}");
+        }
+    }
+
+    #[test]
+    fn mode4_frame_select_flips_pages() {
+        let mut bus = Bus::default();
+        bus.write16(0x0500_0002, 0x001F);
+        bus.write16(0x0500_0004, 0x7C00);
+        bus.vram[..0x9600].fill(1);
+        bus.vram[0xA000..0x1_3600].fill(2);
+
+        for (frame, select) in [0, 1, 1, 0, 1].into_iter().enumerate() {
+            bus.write16(0x0400_0000, 0x0404 | select << 4);
+            run_frame(&mut bus, frame as u64);
+
+            let expected = if select == 1 { 0x7C00 } else { 0x001F };
+            assert!(bus.ppu.buffer.iter().all(|&px| px == Some(expected)), "frame {frame}");
+        }
+    }
+
+    #[test]
+    fn sprites_past_the_budget_are_dropped() {
+        // `hidden` offscreen 64x64 sprites take 64 cycles each before a solid 8x8 one at (16, 40).
+        let visible = |hidden: u32, hblank_free: bool| {
+            let mut bus = Bus::default();
+            bus.write16(0x0500_0202, 0x001F);
+            bus.vram[0x1_0000..0x1_0020].fill(0x11);
+            for address in (0x0700_0000..0x0700_0400).step_by(8) {
+                bus.write16(address, 0x0200);
+            }
+            for n in 0..hidden {
+                bus.write16(0x0700_0000 + n * 8, 40);
+                bus.write16(0x0700_0002 + n * 8, 0xC000 | 300);
+            }
+            bus.write16(0x0700_0000 + hidden * 8, 40);
+            bus.write16(0x0700_0002 + hidden * 8, 16);
+            bus.write16(0x0700_0004 + hidden * 8, 0);
+            bus.write16(0x0400_0000, 0x1040 | (hblank_free as u16) << 5);
+            run_frame(&mut bus, 0);
+
+            let px = |x: usize, y: usize| bus.ppu.buffer[y * LCD_WIDTH + x];
+            let drawn = (40..48).all(|y| (16..24).all(|x| px(x, y) == Some(0x001F)));
+            assert!(drawn || (40..48).all(|y| (16..24).all(|x| px(x, y).is_none())));
+            drawn
+        };
+
+        // 1210 cycles per line, 954 with the HBlank interval free.
+        assert!(visible(18, false));
+        assert!(!visible(19, false));
+        assert!(visible(14, true));
+        assert!(!visible(15, true));
+    }
+
+    #[test]
+    fn obj_window_masks_bg() {
+        let mut bus = Bus::default();
+
+        // BG1 is solid red, tile 1 everywhere.
+        bus.write16(0x0500_0002, 0x001F);
+        for address in (0x0600_0020..0x0600_0040).step_by(2) {
+            bus.write16(address, 0x1111);
+        }
+        for address in (0x0600_F800..0x0601_0000).step_by(2) {
+            bus.write16(address, 0x0001);
+        }
+        bus.write16(0x0400_000A, 0x1F00);
+
+        // 8x8 window sprite at (16, 40), only its left four columns are opaque.
+        for address in (0x0601_0000..0x0601_0020).step_by(4) {
+            bus.write32(address, 0x0000_1111);
+        }
+        for address in (0x0700_0008..0x0700_0400).step_by(8) {
+            bus.write16(address, 0x0200);
+        }
+        bus.write16(0x0700_0000, 0x0800 | 40);
+        bus.write16(0x0700_0002, 16);
+        bus.write16(0x0700_0004, 0);
+
+        // Nothing outside the windows, BG1 inside the OBJ window.
+        bus.write16(0x0400_004A, 0x0200);
+        bus.write16(0x0400_0000, 0x9240);
+        run_frame(&mut bus, 0);
+
+        for (i, px) in bus.ppu.buffer[..LCD_WIDTH * LCD_HEIGHT].iter().enumerate() {
+            let (x, y) = (i % LCD_WIDTH, i / LCD_WIDTH);
+            let inside = (16..20).contains(&x) && (40..48).contains(&y);
+            assert_eq!(*px, inside.then_some(0x001F), "at {x}, {y}");
+        }
+    }
+
+    #[test]
+    fn obj_window_priority() {
+        let mut bus = Bus::default();
+
+        // BG `n` is solid color `n + 1` from tile `n + 1` in screen block `28 + n`.
+        for n in 0..4u32 {
+            bus.write16(0x0500_0002 + n * 2, n as u16 + 1);
+            for address in (0x0600_0020 + n * 32..0x0600_0040 + n * 32).step_by(2) {
+                bus.write16(address, (n as u16 + 1) * 0x1111);
+            }
+            for address in (0x0600_E000 + n * 0x800..0x0600_E800 + n * 0x800).step_by(2) {
+                bus.write16(address, n as u16 + 1);
+            }
+            bus.write16(0x0400_0008 + n * 2, (28 + n as u16) << 8);
+        }
+
+        // Opaque 64x64 window sprite at (60, 60), overlapping both windows.
+        for address in (0x0601_0000..0x0601_0800).step_by(2) {
+            bus.write16(address, 0x1111);
+        }
+        for address in (0x0700_0008..0x0700_0400).step_by(8) {
+            bus.write16(address, 0x0200);
+        }
+        bus.write16(0x0700_0000, 0x0800 | 60);
+        bus.write16(0x0700_0002, 0xC000 | 60);
+        bus.write16(0x0700_0004, 0);
+
+        // WIN0 at [0, 80) and WIN1 at [40, 120) in both directions. BG0 in WIN0, BG1 in WIN1,
+        // BG2 in the OBJ window and BG3 outside.
+        bus.write16(0x0400_0040, 0x0050);
+        bus.write16(0x0400_0044, 0x0050);
+        bus.write16(0x0400_0042, 0x2878);
+        bus.write16(0x0400_0046, 0x2878);
+        bus.write16(0x0400_0048, 0x0201);
+        bus.write16(0x0400_004A, 0x0408);
+        bus.write16(0x0400_0000, 0xFF40);
+        run_frame(&mut bus, 0);
+
+        for (i, px) in bus.ppu.buffer[..LCD_WIDTH * LCD_HEIGHT].iter().enumerate() {
+            let (x, y) = (i % LCD_WIDTH, i / LCD_WIDTH);
+            let inside = |range: std::ops::Range<usize>| range.contains(&x) && range.contains(&y);
+            let expected = match () {
+                _ if inside(0..80) => 1,
+                _ if inside(40..120) => 2,
+                _ if inside(60..124) => 3,
+                _ => 4,
+            };
+            assert_eq!(*px, Some(expected), "at {x}, {y}");
+        }
+    }
+
+    #[test]
+    fn double_size_sprite_at_screen_edges() {
+        let mut bus = Bus::default();
+
+        // Solid 64x64 4bpp sprites sharing affine group 0, an identity matrix.
+        bus.write16(0x0500_0202, 0x001F);
+        bus.write16(0x0500_0222, 0x7C00);
+        for address in (0x0601_0000..0x0601_0800).step_by(2) {
+            bus.write16(address, 0x1111);
+        }
+        for address in (0x0700_0010..0x0700_0400).step_by(8) {
+            bus.write16(address, 0x0200);
+        }
+        bus.write16(0x0700_0006, 0x0100);
+        bus.write16(0x0700_001E, 0x0100);
+
+        // A 128x128 box hanging off the bottom right, and one at x = -32 off the left.
+        bus.write16(0x0700_0000, 0x0300 | 100);
+        bus.write16(0x0700_0002, 0xC000 | 200);
+        bus.write16(0x0700_0004, 0);
+        bus.write16(0x0700_0008, 0x0300);
+        bus.write16(0x0700_000A, 0xC000 | 480);
+        bus.write16(0x0700_000C, 0x1000);
+        bus.write16(0x0400_0000, 0x1040);
+        run_frame(&mut bus, 0);
+
+        // The texture sits in the middle of its box.
+        for (i, px) in bus.ppu.buffer[..LCD_WIDTH * LCD_HEIGHT].iter().enumerate() {
+            let (x, y) = (i % LCD_WIDTH, i / LCD_WIDTH);
+            let expected = match () {
+                _ if (232..240).contains(&x) && (132..160).contains(&y) => Some(0x001F),
+                _ if (0..64).contains(&x) && (32..96).contains(&y) => Some(0x7C00),
+                _ => None,
+            };
+            assert_eq!(*px, expected, "at {x}, {y}");
+        }
+    }
+
+    #[test]
+    fn semi_transparent_obj_blends_under_brightness_effect() {
+        let mut bus = Bus::default();
+
+        // BG0 is solid half red, tile 1 everywhere.
+        bus.write16(0x0500_0002, 0x0010);
+        for address in (0x0600_0020..0x0600_0040).step_by(2) {
+            bus.write16(address, 0x1111);
+        }
+        for address in (0x0600_F800..0x0601_0000).step_by(2) {
+            bus.write16(address, 0x0001);
+        }
+        bus.write16(0x0400_0008, 0x1F00);
+
+        // Semi-transparent half blue 8x8 sprite at (16, 40).
+        bus.write16(0x0500_0202, 0x4000);
+        for address in (0x0601_0000..0x0601_0020).step_by(2) {
+            bus.write16(address, 0x1111);
+        }
+        for address in (0x0700_0008..0x0700_0400).step_by(8) {
+            bus.write16(address, 0x0200);
+        }
+        bus.write16(0x0700_0000, 0x0400 | 40);
+        bus.write16(0x0700_0002, 16);
+        bus.write16(0x0700_0004, 0);
+
+        // Full brightness increase on OBJ and BG0, BG0 also the 2nd target at 8/16 + 8/16.
+        bus.write16(0x0400_0050, 0x0191);
+        bus.write16(0x0400_0052, 0x0808);
+        bus.write16(0x0400_0054, 16);
+        bus.write16(0x0400_0000, 0x1140);
+        run_frame(&mut bus, 0);
+
+        for (i, px) in bus.ppu.buffer[..LCD_WIDTH * LCD_HEIGHT].iter().enumerate() {
+            let (x, y) = (i % LCD_WIDTH, i / LCD_WIDTH);
+            let expected = match (16..24).contains(&x) && (40..48).contains(&y) {
+                true => 0x2008,
+                false => 0x7FFF,
+            };
+            assert_eq!(*px, Some(expected), "at {x}, {y}");
+        }
+    }
+
+    #[test]
+    fn supersampled_rotation_blends_colors() {
+        let distinct_colors = |supersample: bool| {
+            let mut bus = Bus::default();
+            bus.ppu.set_internal_scale(if supersample { 4 } else { 1 });
+            bus.ppu.supersample = supersample;
+
+            // Mode 3 gradient repeating every 8 pixels, red across and blue down.
+            for (y, x) in (0..160).flat_map(|y| (0..240).map(move |x| (y, x))) {
+                let px = (x % 8 * 4) as u16 | ((y % 8 * 4) as u16) << 10;
+                bus.write16(0x0600_0000 + (y * 240 + x) * 2, px);
+            }
+
+            // BG2 rotated by 45 degrees.
+            bus.write16(0x0400_0020, 181);
+            bus.write16(0x0400_0022, -181i16 as u16);
+            bus.write16(0x0400_0024, 181);
+            bus.write16(0x0400_0026, 181);
+            bus.write32(0x0400_0028, 60 << 8);
+            bus.write16(0x0400_0000, 0x0403);
+            run_frame(&mut bus, 0);
+
+            let mut colors: Vec<u16> = bus.ppu.buffer.iter().flatten().copied().collect();
+            colors.sort_unstable();
+            colors.dedup();
+            colors.len()
+        };
+
+        // Natively every pixel is one of the 64 gradient colors, averaging adds the ones in between.
+        let (native, supersampled) = (distinct_colors(false), distinct_colors(true));
+        assert!(native <= 64, "{native} native colors");
+        assert!(supersampled > native * 2, "{supersampled} supersampled vs {native} native colors");
+    }
+}
@@ -49,19 +49,11 @@ impl Sprite {
             let attr = u64::from_le_bytes(attributes.try_into().unwrap());
             let sprite = Sprite::from(attr);
 
-            // Treat y as signed with [-128, 127].
-            // Won't fully work for affine double sprite size.
-            let mut signed_start = sprite.y as i16;
-            signed_start -= if sprite.y >= 160 { 256 } else { 0 };
+            // Y wraps at 256, a sprite near the bottom of that range continues at the top.
+            // Affine double size sprites cover twice their height.
+            let height = (sprite.height() as u16) << (sprite.rot_scale && sprite.double_or_disable) as u16;
 
-            // Double sprite size for LY check to include the lower half of double size sprites.
-            let sprite_height =
-                (sprite.height() as i16) << (sprite.rot_scale && sprite.double_or_disable) as i16;
-
-            let signed_end = signed_start + sprite_height;
-            let wrapped_ly = ly as i16;
-
-            if wrapped_ly >= signed_start && wrapped_ly < signed_end {
+            if (ly.wrapping_sub(sprite.y) as u16) < height {
                 sprites.push(sprite);
             }
         }
@@ -89,6 +81,16 @@ impl Sprite {
         params
     }
 
+    /// Cycles the OBJ renderer spends on this sprite in each of its lines: one per pixel,
+    /// affine ones 10 plus two per pixel of their (maybe doubled) bounding box. Disabled ones are free.
+    pub fn render_cycles(&self) -> u16 {
+        match (self.rot_scale, self.double_or_disable) {
+            (false, true) => 0,
+            (false, false) => self.width() as u16,
+            (true, double) => 10 + 2 * ((self.width() as u16) << double as u16),
+        }
+    }
+
     pub fn width(&self) -> u8 {
         use ObjShape::*;
         match (self.size, &self.shape) {
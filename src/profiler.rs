@@ -0,0 +1,102 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Frame phases timed with the `profiling` feature.
+#[derive(Clone, Copy)]
+pub enum Phase {
+    Cpu,
+    Ppu,
+    Dma,
+    Upload,
+}
+
+const PHASES: [(Phase, &str); 4] = [
+    (Phase::Cpu, "cpu"),
+    (Phase::Ppu, "ppu"),
+    (Phase::Dma, "dma"),
+    (Phase::Upload, "upload"),
+];
+
+thread_local! {
+    static CURRENT: RefCell<[Duration; 4]> = const { RefCell::new([Duration::ZERO; 4]) };
+}
+
+/// Adds the time until it is dropped to its phase, see `profile!`.
+pub struct Scope(Phase, Instant);
+
+impl Scope {
+    pub fn new(phase: Phase) -> Self {
+        Self(phase, Instant::now())
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        CURRENT.with(|c| c.borrow_mut()[self.0 as usize] += self.1.elapsed());
+    }
+}
+
+/// Time the rest of the enclosing block as `Phase::$phase`, compiles to nothing without `profiling`.
+#[macro_export]
+macro_rules! profile {
+    ($phase:ident) => {
+        #[cfg(feature = "profiling")]
+        let _scope = $crate::profiler::Scope::new($crate::profiler::Phase::$phase);
+    };
+}
+
+/// Time spent per phase over one or more frames.
+#[derive(Default, Clone, Copy)]
+pub struct FrameProfile {
+    pub phases: [Duration; 4],
+    pub frames: u32,
+}
+
+impl FrameProfile {
+    /// Take everything recorded since the last call as one frame.
+    pub fn take() -> Self {
+        Self {
+            phases: CURRENT.with(|c| std::mem::take(&mut *c.borrow_mut())),
+            frames: 1,
+        }
+    }
+
+    pub fn add(&mut self, other: &FrameProfile) {
+        for (total, phase) in self.phases.iter_mut().zip(other.phases) {
+            *total += phase;
+        }
+        self.frames += other.frames;
+    }
+
+    /// Average microseconds per frame and share of the total for each phase.
+    pub fn per_frame(&self) -> [(&'static str, f64, f64); 4] {
+        let total: Duration = self.phases.iter().sum();
+
+        PHASES.map(|(phase, name)| {
+            let time = self.phases[phase as usize];
+            let us = time.as_secs_f64() * 1e6 / self.frames.max(1) as f64;
+            let percent = match total.is_zero() {
+                true => 0.0,
+                false => time.as_secs_f64() / total.as_secs_f64() * 100.0,
+            };
+
+            (name, us, percent)
+        })
+    }
+}
+
+impl fmt::Display for FrameProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, us, percent)) in self.per_frame().into_iter().enumerate() {
+            if i > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{name} {us:.0}us {percent:.0}%")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+};
+
+use crate::{
+    apu,
+    gba::{LCD_HEIGHT, LCD_WIDTH},
+    ppu,
+};
+
+/// Writes every presented frame as raw 240x160 RGBA, to be encoded with e.g.
+/// `ffmpeg -f rawvideo -pixel_format rgba -video_size 240x160 -framerate 60 -i <path> out.mp4`.
+pub struct VideoRecorder<W: Write> {
+    writer: W,
+    pub frames: usize,
+}
+
+impl VideoRecorder<BufWriter<File>> {
+    pub fn create(path: &str) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("Could not create {path}: {e}"))?;
+        Ok(Self::new(BufWriter::new(file)))
+    }
+}
+
+impl<W: Write> VideoRecorder<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, frames: 0 }
+    }
+
+    /// Convert the PPU buffer like the frontend does (without any output filters) and append it.
+    pub fn write_frame(&mut self, buffer: &[Option<u16>], backdrop: u16) -> std::io::Result<()> {
+        let frame: Vec<u8> = buffer[0..(LCD_WIDTH * LCD_HEIGHT)]
+            .iter()
+            .flat_map(|px| ppu::rgb555_to_color(px.unwrap_or(backdrop)).to_be_bytes())
+            .collect();
+
+        self.writer.write_all(&frame)?;
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+/// Writes interleaved stereo samples as a 16-bit PCM WAV at `apu::SAMPLE_RATE`.
+///
+/// The header is written up front with empty sizes, `finish` patches in the final lengths.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    /// `i16` values written so far, left and right count separately.
+    pub samples: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &str) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("Could not create {path}: {e}"))?;
+        Self::new(BufWriter::new(file)).map_err(|e| format!("Could not write {path}: {e}"))
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W) -> std::io::Result<Self> {
+        writer.write_all(&wav_header(0))?;
+        Ok(Self { writer, samples: 0 })
+    }
+
+    pub fn write_samples(&mut self, samples: &[i16]) -> std::io::Result<()> {
+        let bytes: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+
+        self.writer.write_all(&bytes)?;
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    /// Patch the RIFF and data chunk sizes, the file is a valid WAV only after this.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&wav_header(self.samples * 2))?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+/// RIFF header of a 2 channel, 16-bit PCM WAV with `data_len` bytes of samples.
+fn wav_header(data_len: u32) -> [u8; 44] {
+    const CHANNELS: u16 = 2;
+    const BLOCK_ALIGN: u16 = CHANNELS * 2;
+
+    [
+        b"RIFF".as_slice(),
+        &(36 + data_len).to_le_bytes(),
+        b"WAVEfmt ",
+        &16u32.to_le_bytes(),
+        // PCM
+        &1u16.to_le_bytes(),
+        &CHANNELS.to_le_bytes(),
+        &apu::SAMPLE_RATE.to_le_bytes(),
+        &(apu::SAMPLE_RATE * BLOCK_ALIGN as u32).to_le_bytes(),
+        &BLOCK_ALIGN.to_le_bytes(),
+        &16u16.to_le_bytes(),
+        b"data",
+        &data_len.to_le_bytes(),
+    ]
+    .concat()
+    .try_into()
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{arm::interpreter::arm7tdmi::Mode, gba::Gba, mmu::Mcu};
+
+    fn u32_at(wav: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(wav[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u16_at(wav: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(wav[offset..offset + 2].try_into().unwrap())
+    }
+
+    #[test]
+    fn wav_of_known_samples() {
+        let samples = [i16::MIN, -1, 0, 1, 0x1234, i16::MAX];
+
+        let mut wav = WavWriter::new(Cursor::new(Vec::new())).unwrap();
+        wav.write_samples(&samples[..2]).unwrap();
+        wav.write_samples(&samples[2..]).unwrap();
+        assert_eq!(wav.samples, 6);
+        let wav = wav.finish().unwrap().into_inner();
+
+        assert_eq!(wav.len(), 44 + 12);
+        assert_eq!((&wav[0..4], u32_at(&wav, 4)), (b"RIFF".as_slice(), 36 + 12));
+        assert_eq!((&wav[36..40], u32_at(&wav, 40)), (b"data".as_slice(), 12));
+        assert_eq!(wav[44..], [0x00, 0x80, 0xFF, 0xFF, 0, 0, 1, 0, 0x34, 0x12, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn video_of_known_frames() {
+        let mut kba = Gba::with_rom(&0xEAFF_FFFEu32.to_le_bytes());
+        kba.cpu.boot_at(0x0800_0000, Mode::System);
+        let mut video = VideoRecorder::new(Vec::new());
+
+        // Mode 3 with a red pixel at (1, 0), then nothing enabled to show the blue backdrop.
+        kba.cpu.bus.write16(0x0600_0002, 0x001F);
+        kba.cpu.bus.write16(0x0500_0000, 0x7C00);
+        for display in [0x0403, 0x0403, 0x0000] {
+            kba.cpu.bus.write16(0x0400_0000, display);
+            kba.run_frame();
+            video.write_frame(&kba.cpu.bus.ppu.buffer, 0x7C00).unwrap();
+        }
+
+        let frame_len = LCD_WIDTH * LCD_HEIGHT * 4;
+        assert_eq!(video.frames, 3);
+        assert_eq!(video.writer.len(), 3 * frame_len);
+
+        let frames: Vec<&[u8]> = video.writer.chunks(frame_len).collect();
+        assert_eq!(frames[0], frames[1]);
+        assert_eq!(frames[1][..12], [0, 0, 0, 255, 255, 0, 0, 255, 0, 0, 0, 255]);
+        assert!(frames[2].chunks(4).all(|px| px == [0, 0, 255, 255]));
+    }
+
+    /// `b .` at the ROM entry, the APU plays a square wave on its own.
+    fn square_wave() -> Gba {
+        let mut kba = Gba::with_rom(&0xEAFF_FFFEu32.to_le_bytes());
+        kba.cpu.boot_at(0x0800_0000, Mode::System);
+
+        let bus = &mut kba.cpu.bus;
+        bus.write16(0x0400_0084, 0x0080);
+        bus.write16(0x0400_0080, 0x1177);
+        bus.write16(0x0400_0082, 0x0002);
+        bus.write16(0x0400_0062, 0xF080);
+        bus.write16(0x0400_0064, 0x8000 | 1750);
+
+        kba
+    }
+
+    #[test]
+    fn dump_wav_of_deterministic_run() {
+        let mut kba = square_wave();
+        let mut wav = WavWriter::new(Cursor::new(Vec::new())).unwrap();
+        let mut audio = Vec::new();
+        for frame in kba.frames().take(3) {
+            wav.write_samples(&frame.audio).unwrap();
+            audio.extend(frame.audio);
+        }
+        let wav = wav.finish().unwrap().into_inner();
+
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32_at(&wav, 4) as usize, wav.len() - 8);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!((u32_at(&wav, 16), u16_at(&wav, 20), u16_at(&wav, 22)), (16, 1, 2));
+        assert_eq!((u32_at(&wav, 24), u32_at(&wav, 28)), (apu::SAMPLE_RATE, apu::SAMPLE_RATE * 4));
+        assert_eq!((u16_at(&wav, 32), u16_at(&wav, 34)), (4, 16));
+        assert_eq!(&wav[36..40], b"data");
+
+        // A sample pair every 512 cycles, 280896 cycles per frame.
+        let pairs = u32_at(&wav, 40) as usize / 4;
+        assert!(pairs.abs_diff(3 * 280_896 / 512) <= 3, "{pairs} sample pairs");
+        assert_eq!(wav[44..], audio.iter().flat_map(|s| s.to_le_bytes()).collect::<Vec<_>>());
+        assert!(audio.iter().any(|&s| s != 0), "silent dump");
+    }
+
+    #[test]
+    fn dump_is_independent_of_output_settings() {
+        // What the frontend does per frame: dump the mixer output, then play it with the settings.
+        let run = |volume: u8| {
+            let (mut kba, settings) = (square_wave(), apu::OutputSettings { volume, ..Default::default() });
+            let (mut wav, mut played) = (WavWriter::new(Cursor::new(Vec::new())).unwrap(), Vec::new());
+            for _ in 0..3 {
+                kba.run_frame();
+                let samples = kba.take_audio();
+                wav.write_samples(&samples).unwrap();
+                played.extend(settings.apply(&samples));
+            }
+
+            (wav.finish().unwrap().into_inner(), played)
+        };
+
+        let ((full_dump, full_played), (half_dump, half_played)) = (run(100), run(50));
+        assert!(full_dump == half_dump, "dump changed with the volume");
+        assert_ne!(full_played, half_played);
+    }
+}
@@ -0,0 +1,118 @@
+use std::path::{Path, PathBuf};
+
+/// Multiboot images are sent to the GBA over the link cable and run from EWRAM, so they're at most 256 KiB.
+pub const MULTIBOOT_SIZE: usize = 0x40000;
+
+/// The Game Pak ROM region is 32 MiB, larger roms can't be mapped.
+pub const MAX_ROM_SIZE: usize = 0x0200_0000;
+
+/// A rom read from disk, either directly or out of an archive.
+pub struct RomFile {
+    pub data: Vec<u8>,
+    /// Path the rom was loaded from, the archive for zipped roms.
+    pub path: PathBuf,
+}
+
+impl RomFile {
+    /// Load `path`, extracting the `.gba` file from `.zip` archives (with the `archive` feature).
+    pub fn load(path: &str) -> Result<Self, String> {
+        let path = PathBuf::from(path);
+        let extension = path.extension().and_then(|e| e.to_str()).map(str::to_lowercase);
+
+        let data = match extension.as_deref() {
+            Some("zip") => read_zip(&path)?,
+            Some("7z") => return Err("7z archives are not supported, please extract the rom or use a zip".to_string()),
+            _ => std::fs::read(&path).map_err(|e| format!("{}: {e}", path.display()))?,
+        };
+
+        Ok(Self { data, path })
+    }
+
+    /// Multiboot image by name, `game.mb` or devkitARM's `game_mb.gba`, that fits into EWRAM.
+    pub fn is_multiboot(&self) -> bool {
+        let extension = self.path.extension().and_then(|e| e.to_str()).map(str::to_lowercase);
+        let stem = self.path.file_stem().and_then(|s| s.to_str()).map(str::to_lowercase);
+
+        let named = extension.as_deref() == Some("mb") || stem.is_some_and(|s| s.ends_with("_mb"));
+        named && self.data.len() <= MULTIBOOT_SIZE
+    }
+
+    /// Save file next to the rom (or the archive), `game.gba` -> `game.sav`.
+    pub fn save_path(&self) -> PathBuf {
+        self.path.with_extension("sav")
+    }
+}
+
+#[cfg(feature = "archive")]
+fn read_zip(path: &Path) -> Result<Vec<u8>, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    extract_gba(file).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+#[cfg(not(feature = "archive"))]
+fn read_zip(path: &Path) -> Result<Vec<u8>, String> {
+    Err(format!("{}: zip support needs the `archive` feature", path.display()))
+}
+
+/// Extract the first `.gba` file of a zip archive, warns if there are more.
+#[cfg(feature = "archive")]
+pub fn extract_gba<R: std::io::Read + std::io::Seek>(reader: R) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+
+    let mut archive = zip::ZipArchive::new(reader).map_err(|e| e.to_string())?;
+    let roms: Vec<String> = archive
+        .file_names()
+        .filter(|name| name.to_lowercase().ends_with(".gba"))
+        .map(str::to_string)
+        .collect();
+
+    let Some(name) = roms.iter().min() else {
+        return Err("archive contains no .gba rom".to_string());
+    };
+
+    if roms.len() > 1 {
+        eprintln!("Archive contains {} roms, loading {name}", roms.len());
+    }
+
+    let mut rom = Vec::new();
+    archive
+        .by_name(name)
+        .and_then(|mut file| Ok(file.read_to_end(&mut rom)?))
+        .map_err(|e| e.to_string())?;
+
+    Ok(rom)
+}
+
+#[cfg(all(test, feature = "archive"))]
+mod tests {
+    use super::*;
+
+    /// Zip archive in memory with the given files, stored uncompressed.
+    fn zip(files: &[(&str, &[u8])]) -> std::io::Cursor<Vec<u8>> {
+        use std::io::Write;
+
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (name, data) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+
+        let mut archive = writer.finish().unwrap();
+        archive.set_position(0);
+        archive
+    }
+
+    #[test]
+    fn extract_gba_from_zip() {
+        let rom = zip(&[("readme.txt", b"hi"), ("game.GBA", &[1, 2, 3, 4])]);
+        assert_eq!(extract_gba(rom).unwrap(), [1, 2, 3, 4]);
+
+        // The first name in order wins, whatever order the archive has.
+        let roms = zip(&[("b.gba", &[2]), ("a.gba", &[1])]);
+        assert_eq!(extract_gba(roms).unwrap(), [1]);
+
+        assert!(extract_gba(zip(&[("readme.txt", b"hi")])).is_err());
+        assert!(extract_gba(std::io::Cursor::new(b"not a zip".to_vec())).is_err());
+    }
+}